@@ -6,6 +6,7 @@ use ir_client::{config::Config, verify_digest};
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use log::info;
 use ratls::{load_root_cert_store, RaTlsCertResolver, TokenFromFile};
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
 use tokio::{fs::File, io::{AsyncReadExt, AsyncWriteExt}};
 
 #[derive(ValueEnum, Default, Debug, Clone)]
@@ -13,6 +14,7 @@ pub enum ConnectionType {
     #[default]
     NoTLS,
     TLS,
+    MTLS,
     RaTLS,
 }
 
@@ -31,9 +33,25 @@ struct ConnectionArgs {
     #[arg(long, id = "token.bin", default_value = "./res/token.bin")]
     token: String,
 
-    /// Root certificate file in PEM format (used with tls and ra-tls)
+    /// Root certificate file in PEM format (used with tls, mtls and ra-tls)
     #[arg(long, id = "root-ca.crt", default_value = "./res/root-ca.crt")]
     root_ca: String,
+
+    /// Client certificate chain file in PEM format (used with mtls)
+    #[arg(long, id = "client.crt", default_value = "./res/client.crt")]
+    client_cert: String,
+
+    /// Client private key file in PEM format (used with mtls)
+    #[arg(long, id = "client.key", default_value = "./res/client.key")]
+    client_key: String,
+
+    /// Username for the servers requiring a token (used with no-tls and tls)
+    #[arg(long, requires = "secret")]
+    username: Option<String>,
+
+    /// Secret of the username
+    #[arg(long, requires = "username")]
+    secret: Option<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -99,7 +117,7 @@ struct ListTagsArgs {
 
 
 fn build_config(conn: ConnectionArgs) -> Config {
-    match conn.tls {
+    let config = match conn.tls {
         ConnectionType::NoTLS => {
             Config::builder().host(conn.host).no_tls()
         },
@@ -108,6 +126,17 @@ fn build_config(conn: ConnectionArgs) -> Config {
                 .host(conn.host)
                 .rustls_no_auth(load_root_cert_store(conn.root_ca).unwrap())
         }
+        ConnectionType::MTLS => {
+            Config::builder()
+                .host(conn.host)
+                .mtls(
+                    load_root_cert_store(conn.root_ca).unwrap(),
+                    CertificateDer::pem_file_iter(conn.client_cert).unwrap()
+                        .collect::<Result<_, _>>().unwrap(),
+                    PrivateKeyDer::from_pem_file(conn.client_key).unwrap()
+                )
+                .unwrap()
+        }
         ConnectionType::RaTLS => {
             Config::builder()
                 .host(conn.host)
@@ -122,6 +151,11 @@ fn build_config(conn: ConnectionArgs) -> Config {
                     )
                 )
         },
+    };
+
+    match (conn.username, conn.secret) {
+        (Some(username), Some(secret)) => config.with_credentials(username, secret),
+        _ => config,
     }
 }
 
@@ -2,16 +2,18 @@ use std::path::Path;
 use std::sync::Arc;
 
 use ir_client::oci::reference::Reference;
-use ir_client::{client::Client, config::Config};
+use ir_client::{client::Client, config::Config, revocation::RevocationList};
 
 use clap::{Args, Parser, ValueEnum};
 use ratls::{load_root_cert_store, RaTlsCertResolver, TokenFromFile};
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
 
 #[derive(ValueEnum, Default, Debug, Clone)]
 pub enum ConnectionType {
     #[default]
     NoTLS,
     TLS,
+    MTLS,
     RaTLS,
 }
 
@@ -30,9 +32,25 @@ struct ConnectionArgs {
     #[arg(long, id = "token.bin", default_value = "./res/token.bin")]
     token: String,
 
-    /// Root certificate file in PEM format (used with tls and ra-tls)
+    /// Root certificate file in PEM format (used with tls, mtls and ra-tls)
     #[arg(long, id = "root-ca.crt", default_value = "./res/root-ca.crt")]
     root_ca: String,
+
+    /// Client certificate chain file in PEM format (used with mtls)
+    #[arg(long, id = "client.crt", default_value = "./res/client.crt")]
+    client_cert: String,
+
+    /// Client private key file in PEM format (used with mtls)
+    #[arg(long, id = "client.key", default_value = "./res/client.key")]
+    client_key: String,
+
+    /// Username for the servers requiring a token (used with no-tls and tls)
+    #[arg(long, requires = "secret")]
+    username: Option<String>,
+
+    /// Secret of the username
+    #[arg(long, requires = "username")]
+    secret: Option<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -52,10 +70,22 @@ struct Cli {
     /// directory to unpack the image
     #[arg(short, long)]
     dest: String,
+
+    /// Root-ca public key in DER format (used to verify revocation list)
+    #[arg(long, requires_all = ["revocation_list", "revocation_signature"])]
+    ca_pub: Option<String>,
+
+    /// Revocation list of vendor keys, images signed with those are rejected
+    #[arg(long, requires = "ca_pub")]
+    revocation_list: Option<String>,
+
+    /// Signature of the revocation list signed by root-ca
+    #[arg(long, requires = "ca_pub")]
+    revocation_signature: Option<String>,
 }
 
 fn build_config(conn: ConnectionArgs) -> Config {
-    match conn.tls {
+    let config = match conn.tls {
         ConnectionType::NoTLS => {
             Config::builder().host(conn.host).no_tls()
         },
@@ -64,6 +94,17 @@ fn build_config(conn: ConnectionArgs) -> Config {
                 .host(conn.host)
                 .rustls_no_auth(load_root_cert_store(conn.root_ca).unwrap())
         }
+        ConnectionType::MTLS => {
+            Config::builder()
+                .host(conn.host)
+                .mtls(
+                    load_root_cert_store(conn.root_ca).unwrap(),
+                    CertificateDer::pem_file_iter(conn.client_cert).unwrap()
+                        .collect::<Result<_, _>>().unwrap(),
+                    PrivateKeyDer::from_pem_file(conn.client_key).unwrap()
+                )
+                .unwrap()
+        }
         ConnectionType::RaTLS => {
             Config::builder()
                 .host(conn.host)
@@ -78,6 +119,11 @@ fn build_config(conn: ConnectionArgs) -> Config {
                     )
                 )
         },
+    };
+
+    match (conn.username, conn.secret) {
+        (Some(username), Some(secret)) => config.with_credentials(username, secret),
+        _ => config,
     }
 }
 
@@ -88,7 +134,11 @@ async fn main() {
     let cli = Cli::parse();
 
     let config = build_config(cli.conn);
-    let client = Client::from_config(config).unwrap();
+    let mut client = Client::from_config(config).unwrap();
+
+    if let (Some(ca_pub), Some(list), Some(signature)) = (cli.ca_pub, cli.revocation_list, cli.revocation_signature) {
+        client = client.with_revocation_list(RevocationList::load(list, signature, ca_pub).unwrap());
+    }
 
     let reference = Reference::try_from(cli.reference.as_str()).unwrap();
 
@@ -0,0 +1,194 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ir_client::config::Config;
+use ir_client::inspect::{self, ImageDiff, ImageSource};
+use ir_client::oci::client::Client;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ratls::{load_root_cert_store, RaTlsCertResolver, TokenFromFile};
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+
+const LAYOUT_PREFIX: &str = "oci:";
+
+#[derive(ValueEnum, Default, Debug, Clone)]
+pub enum ConnectionType {
+    #[default]
+    NoTLS,
+    TLS,
+    MTLS,
+    RaTLS,
+}
+
+#[derive(Args, Debug)]
+#[command(author, version, about)]
+struct ConnectionArgs {
+    /// Host url address
+    #[arg(short = 'a', long, default_value = "localhost:1337")]
+    host: String,
+
+    /// Connection type
+    #[arg(short, long, default_value_t, value_enum, id = "mode")]
+    tls: ConnectionType,
+
+    /// CCA token file in binary CBOR format (used with ra-tls)
+    #[arg(long, id = "token.bin", default_value = "./res/token.bin")]
+    token: String,
+
+    /// Root certificate file in PEM format (used with tls, mtls and ra-tls)
+    #[arg(long, id = "root-ca.crt", default_value = "./res/root-ca.crt")]
+    root_ca: String,
+
+    /// Client certificate chain file in PEM format (used with mtls)
+    #[arg(long, id = "client.crt", default_value = "./res/client.crt")]
+    client_cert: String,
+
+    /// Client private key file in PEM format (used with mtls)
+    #[arg(long, id = "client.key", default_value = "./res/client.key")]
+    client_key: String,
+
+    /// Username for the servers requiring a token (used with no-tls and tls)
+    #[arg(long, requires = "secret")]
+    username: Option<String>,
+
+    /// Secret of the username
+    #[arg(long, requires = "username")]
+    secret: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Cli {
+    #[command(flatten)]
+    conn: ConnectionArgs,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Print annotations, config and layers of an image as JSON
+    Inspect(InspectArgs),
+    /// Print differences between two images, exits with 1 if there are any
+    Diff(DiffArgs),
+}
+
+#[derive(Args, Debug)]
+struct InspectArgs {
+    /// Image as APP@REFERENCE (fetched from host) or oci:DIR@REFERENCE (local OCI image layout)
+    image: String,
+
+    /// List files of the image too (fetches all the layers)
+    #[arg(short, long)]
+    files: bool,
+}
+
+#[derive(Args, Debug)]
+struct DiffArgs {
+    /// Old image as APP@REFERENCE or oci:DIR@REFERENCE
+    from: String,
+
+    /// New image as APP@REFERENCE or oci:DIR@REFERENCE
+    to: String,
+
+    /// Compare files of the images too (fetches all the layers)
+    #[arg(short, long)]
+    files: bool,
+
+    /// Print differences as JSON
+    #[arg(short, long)]
+    json: bool,
+}
+
+fn build_config(conn: ConnectionArgs) -> Config {
+    let config = match conn.tls {
+        ConnectionType::NoTLS => {
+            Config::builder().host(conn.host).no_tls()
+        },
+        ConnectionType::TLS => {
+            Config::builder()
+                .host(conn.host)
+                .rustls_no_auth(load_root_cert_store(conn.root_ca).unwrap())
+        }
+        ConnectionType::MTLS => {
+            Config::builder()
+                .host(conn.host)
+                .mtls(
+                    load_root_cert_store(conn.root_ca).unwrap(),
+                    CertificateDer::pem_file_iter(conn.client_cert).unwrap()
+                        .collect::<Result<_, _>>().unwrap(),
+                    PrivateKeyDer::from_pem_file(conn.client_key).unwrap()
+                )
+                .unwrap()
+        }
+        ConnectionType::RaTLS => {
+            Config::builder()
+                .host(conn.host)
+                .ratls(
+                    load_root_cert_store(conn.root_ca).unwrap(),
+                    Arc::new(
+                        RaTlsCertResolver::from_token_resolver(
+                            Arc::new(
+                                TokenFromFile::from_path(conn.token).unwrap()
+                            )
+                        ).unwrap()
+                    )
+                )
+        },
+    };
+
+    match (conn.username, conn.secret) {
+        (Some(username), Some(secret)) => config.with_credentials(username, secret),
+        _ => config,
+    }
+}
+
+/// Split "APP@REFERENCE" or "oci:DIR@REFERENCE" into a source and a reference
+fn parse_image<'a>(client: &'a Client, image: &str) -> Result<(ImageSource<'a>, String), String> {
+    let Some((location, reference)) = image.rsplit_once('@') else {
+        return Err(format!("Image \"{}\" is not in APP@REFERENCE or oci:DIR@REFERENCE format", image));
+    };
+
+    let source = match location.strip_prefix(LAYOUT_PREFIX) {
+        Some(dir) => ImageSource::Layout(PathBuf::from(dir)),
+        None => ImageSource::Registry(client, location.to_string()),
+    };
+
+    Ok((source, reference.to_string()))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("warn"));
+
+    let cli = Cli::parse();
+    let client = Client::from_config(build_config(cli.conn))?;
+
+    match cli.command {
+        Commands::Inspect(args) => {
+            let (source, reference) = parse_image(&client, &args.image)?;
+            let details = inspect::inspect(&source, &reference, args.files).await?;
+            println!("{}", serde_json::to_string_pretty(&details)?);
+        },
+        Commands::Diff(args) => {
+            let (from_source, from_reference) = parse_image(&client, &args.from)?;
+            let (to_source, to_reference) = parse_image(&client, &args.to)?;
+            let from = inspect::inspect(&from_source, &from_reference, args.files).await?;
+            let to = inspect::inspect(&to_source, &to_reference, args.files).await?;
+
+            let diff = ImageDiff::new(&from, &to);
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&diff)?);
+            } else {
+                print!("{}", diff);
+            }
+
+            if !diff.is_empty() {
+                std::process::exit(1);
+            }
+        },
+    }
+
+    Ok(())
+}
@@ -10,7 +10,8 @@ use crate::config::Config;
 use crate::error::Error;
 use crate::layer::Image;
 use crate::oci::client::Client as OciClient;
-use crate::oci::reference::{Digest, Reference};
+use crate::oci::reference::{Digest, Reference, Tag};
+use crate::revocation::{self, RevocationList};
 use crate::verify_digest;
 
 #[derive(Debug, Clone)]
@@ -32,21 +33,34 @@ impl ImageInfo {
 
 pub struct Client {
     oci_client: OciClient,
+    revocation: Option<RevocationList>,
 }
 
 impl Client {
     pub fn from_config(config: Config) -> Result<Self, Error> {
         Ok(Self {
             oci_client: OciClient::from_config(config)?,
+            revocation: None,
         })
     }
 
+    /// Reject images signed with keys from the revocation list
+    pub fn with_revocation_list(mut self, revocation: RevocationList) -> Self {
+        self.revocation = Some(revocation);
+        self
+    }
+
     pub async fn get_image_info(
         &self,
         app_name: &str,
         reference: Reference,
     ) -> Result<ImageInfo, Error> {
-        let manifest = self.oci_client.get_manifest(app_name, reference).await?;
+        let (manifest, digest) = self
+            .oci_client
+            .get_manifest_with_digest(app_name, reference)
+            .await?;
+        revocation::check_annotations(manifest.annotations().as_ref(), self.revocation.as_ref())?;
+        self.check_cosign_signatures(app_name, &digest).await?;
         let config_digest_str = manifest.config().digest();
         let config_digest = Digest::try_from(config_digest_str.as_str())?;
 
@@ -70,6 +84,44 @@ impl Client {
         })
     }
 
+    /// Apply the annotation checks to the cosign signatures of a manifest,
+    /// kept in a separate manifest tagged "sha256-DIGEST.sig" if any
+    async fn check_cosign_signatures(&self, app_name: &str, digest: &Digest) -> Result<(), Error> {
+        let tag = format!("{}-{}.sig", digest.hash_type(), digest.value());
+        let reference = Reference::Tag(Tag::try_from(tag.as_str())?);
+        let manifest = match self.oci_client.get_manifest(app_name, reference).await {
+            Ok(manifest) => manifest,
+            Err(Error::StatusError(404)) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        for layer in manifest.layers() {
+            if *layer.media_type() != MediaType::Other(revocation::COSIGN_SIMPLESIGNING.to_string()) {
+                continue;
+            }
+
+            let payload_digest = Digest::try_from(layer.digest().as_str())?;
+            let mut payload_reader = self
+                .oci_client
+                .get_blob_reader(app_name, payload_digest.clone())
+                .await?;
+            let mut payload = Vec::new();
+            payload_reader.read_to_end(&mut payload).await?;
+
+            if !verify_digest(&payload_digest, &payload) {
+                error!("Digest of cosign payload returned by server differs from manifest");
+                return Err(Error::DigestInvalidError);
+            }
+            revocation::check_cosign_layer(
+                layer.annotations().as_ref(),
+                &payload,
+                self.revocation.as_ref(),
+            )?;
+        }
+
+        Ok(())
+    }
+
     pub async fn unpack_image(
         &self,
         image_info: &ImageInfo,
@@ -1,7 +1,9 @@
 use std::sync::Arc;
 
 use rustls::{client::ResolvesClientCert, crypto::{ring::default_provider, CryptoProvider}, ClientConfig, RootCertStore};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 
+use crate::error::Error;
 use crate::oci::service_url::{Scheme, HTTPS_SCHEME, HTTP_SCHEME};
 
 fn install_default_crypto_provider() {
@@ -14,6 +16,7 @@ fn install_default_crypto_provider() {
 pub(crate) enum ConnectionMode {
     None,
     RusTLS(ClientConfig),
+    MTls(ClientConfig),
     RaTLS(ClientConfig),
     CustomTLS(ClientConfig),
 }
@@ -24,6 +27,7 @@ impl ConnectionMode {
             Self::None => &HTTP_SCHEME,
             Self::CustomTLS(_) => &HTTPS_SCHEME,
             Self::RaTLS(_) => &HTTPS_SCHEME,
+            Self::MTls(_) => &HTTPS_SCHEME,
             Self::RusTLS(_) => &HTTPS_SCHEME,
         }
     }
@@ -32,14 +36,22 @@ impl ConnectionMode {
             ConnectionMode::None => None,
             ConnectionMode::CustomTLS(config) => Some(config),
             ConnectionMode::RaTLS(config) => Some(config),
+            ConnectionMode::MTls(config) => Some(config),
             ConnectionMode::RusTLS(config) => Some(config),
         }
     }
 }
 
+/// Username and secret exchanged for Bearer tokens when the server asks for them
+pub(crate) struct Credentials {
+    pub(crate) username: String,
+    pub(crate) secret: String,
+}
+
 pub struct Config {
     pub(crate) host: String,
     pub(crate) mode: ConnectionMode,
+    pub(crate) credentials: Option<Credentials>,
 }
 
 impl Config {
@@ -48,6 +60,13 @@ impl Config {
             state: WantsConfig {}
         }
     }
+
+    /// Authenticate with a token from the server's token endpoint when it
+    /// requires one, tokens are cached until they expire
+    pub fn with_credentials(mut self, username: String, secret: String) -> Self {
+        self.credentials = Some(Credentials { username, secret });
+        self
+    }
 }
 
 pub struct ConfigBuilder<S> {
@@ -75,6 +94,7 @@ impl ConfigBuilder<WantsHost> {
         Config {
             host: self.state.host,
             mode: ConnectionMode::None,
+            credentials: None,
         }
     }
 
@@ -86,10 +106,26 @@ impl ConfigBuilder<WantsHost> {
         Config {
             mode: ConnectionMode::RusTLS(rustls_config),
             host: self.state.host,
+            credentials: None,
 
         }
     }
 
+    /// TLS with a client certificate, for the servers verifying those against
+    /// their CA bundle. Fails if the key doesn't match the certificate.
+    pub fn mtls(self, root_cert_store: RootCertStore, cert_chain: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> Result<Config, Error> {
+        install_default_crypto_provider();
+        let rustls_config = ClientConfig::builder()
+            .with_root_certificates(root_cert_store)
+            .with_client_auth_cert(cert_chain, key)
+            .map_err(Error::TLSError)?;
+        Ok(Config {
+            mode: ConnectionMode::MTls(rustls_config),
+            host: self.state.host,
+            credentials: None,
+        })
+    }
+
     pub fn ratls(self, root_cert_store: RootCertStore, resolver: Arc<dyn ResolvesClientCert>) -> Config {
         install_default_crypto_provider();
         let rustls_config = ClientConfig::builder()
@@ -98,6 +134,7 @@ impl ConfigBuilder<WantsHost> {
         Config {
             mode: ConnectionMode::RaTLS(rustls_config),
             host: self.state.host,
+            credentials: None,
 
         }
     }
@@ -106,6 +143,7 @@ impl ConfigBuilder<WantsHost> {
         Config {
             mode: ConnectionMode::CustomTLS(rustls_config),
             host: self.state.host,
+            credentials: None,
         }
     }
 }
@@ -10,8 +10,11 @@ pub enum Error {
     ConnectionError,
     IOError(IOError),
     StatusError(u16),
+    AuthError(String),
+    TLSError(rustls::Error),
 
     ReferenceInvalidError,
+    ReferenceNotFoundError,
     DigestInvalidError,
     TagInvalidError,
 
@@ -20,6 +23,11 @@ pub enum Error {
 
     ResponseLengthInvalid,
     ResponseDigestInvalid,
+
+    RevocationListError(String),
+    SignatureExpiredError,
+    SignatureRevokedError,
+
     UnknownError,
 }
 
@@ -33,7 +41,10 @@ impl Display for Error {
             Self::JSONParsingError(json_error) => write!(f, "JSON parsing error: {}", json_error)?,
             Self::ManifestFormatError => f.write_str("Invalid manifest format")?,
             Self::ReferenceInvalidError => f.write_str("Invalid reference format")?,
+            Self::ReferenceNotFoundError => f.write_str("Reference not found")?,
             Self::StatusError(status_error) => write!(f, "HTTP status error code: {}", status_error)?,
+            Self::AuthError(auth_error) => write!(f, "Authentication error: {}", auth_error)?,
+            Self::TLSError(tls_error) => write!(f, "TLS error: {}", tls_error)?,
             Self::TagInvalidError => f.write_str("Invalid tag format")?,
             Self::UnknownError => f.write_str("Unknown error")?,
             Self::UrlParsingError(url_error) => write!(f, "Url parsing error: {}", url_error)?,
@@ -41,6 +52,9 @@ impl Display for Error {
             Self::ResponseDigestInvalid => f.write_str("Response digest invalid")?,
             Self::LayerInvalidError => f.write_str("Layer invalid")?,
             Self::LayerInvalidDiffIdError => f.write_str("Layer diff_id invalid")?,
+            Self::RevocationListError(revocation_error) => write!(f, "Revocation list error: {}", revocation_error)?,
+            Self::SignatureExpiredError => f.write_str("Image signature expired")?,
+            Self::SignatureRevokedError => f.write_str("Image signed with a revoked key")?,
         }
 
         Ok(())
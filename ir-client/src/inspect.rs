@@ -0,0 +1,522 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::path::{Component, Path, PathBuf};
+
+use log::{debug, error, info};
+use oci_spec::image::{ImageIndex, ImageManifest, ANNOTATION_REF_NAME};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_stream::StreamExt;
+use tokio_tar::{Archive, EntryType};
+
+use crate::error::Error;
+use crate::hasher::{HashType, Hasher};
+use crate::layer::{get_layer_reader, WHITEOUT_FILE, WHITEOUT_OPAQUE};
+use crate::oci::client::Client as OciClient;
+use crate::oci::reference::{Digest, Reference};
+use crate::verify_digest;
+
+const INDEX_JSON: &str = "index.json";
+const BLOBS_DIR: &str = "blobs";
+
+/// Where the images are read from
+pub enum ImageSource<'a> {
+    /// OCI image layout of an application (e.g. a directory of the registry)
+    Layout(PathBuf),
+    /// Application in an image registry
+    Registry(&'a OciClient, String),
+}
+
+impl ImageSource<'_> {
+    async fn read_layout_file(dir: &Path, path: impl AsRef<Path>) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        tokio::fs::File::open(dir.join(path)).await?.read_to_end(&mut bytes).await?;
+        Ok(bytes)
+    }
+
+    fn blob_path(digest: &Digest) -> PathBuf {
+        Path::new(BLOBS_DIR).join(digest.hash_type().to_string()).join(digest.value())
+    }
+
+    /// Tags are resolved with the "index.json" of the layout
+    async fn resolve_layout_reference(dir: &Path, reference: &str) -> Result<Digest, Error> {
+        let index: ImageIndex = serde_json::from_slice(&Self::read_layout_file(dir, INDEX_JSON).await?)?;
+
+        let tagged = index.manifests().iter().find(|desc| {
+            desc.annotations()
+                .as_ref()
+                .and_then(|anns| anns.get(ANNOTATION_REF_NAME))
+                .is_some_and(|tag| tag == reference)
+        });
+
+        match tagged {
+            Some(desc) => {
+                info!("Resolved tag \"{}\" as \"{}\"", reference, desc.digest());
+                Digest::try_from(desc.digest().as_str())
+            },
+            None => Digest::try_from(reference).map_err(|_| {
+                error!("Reference \"{}\" not found in \"{}\"", reference, dir.display());
+                Error::ReferenceNotFoundError
+            }),
+        }
+    }
+
+    async fn manifest(&self, reference: &str) -> Result<(ImageManifest, Digest), Error> {
+        match self {
+            Self::Layout(dir) => {
+                let digest = Self::resolve_layout_reference(dir, reference).await?;
+                let bytes = self.blob(&digest).await?;
+                Ok((serde_json::from_slice(&bytes)?, digest))
+            },
+            Self::Registry(client, app_name) => {
+                client.get_manifest_with_digest(app_name, Reference::try_from(reference)?).await
+            },
+        }
+    }
+
+    async fn blob_reader(&self, digest: &Digest) -> Result<Box<dyn AsyncRead + Send + Sync + Unpin>, Error> {
+        match self {
+            Self::Layout(dir) => {
+                Ok(Box::new(tokio::fs::File::open(dir.join(Self::blob_path(digest))).await?))
+            },
+            Self::Registry(client, app_name) => {
+                Ok(Box::new(client.get_blob_reader(app_name, digest.clone()).await?))
+            },
+        }
+    }
+
+    /// Whole blob with its digest verified
+    async fn blob(&self, digest: &Digest) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        self.blob_reader(digest).await?.read_to_end(&mut bytes).await?;
+
+        if !verify_digest(digest, &bytes) {
+            error!("Digest of blob \"{}\" doesn't match its content", digest);
+            return Err(Error::DigestInvalidError);
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// Parts of the image config that are inspected, docker configs don't always
+/// have all the fields oci-spec requires so they are not parsed with it
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RuntimeConfig {
+    env: Option<Vec<String>>,
+    entrypoint: Option<Vec<String>>,
+    cmd: Option<Vec<String>>,
+    working_dir: Option<String>,
+    labels: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RootFs {
+    diff_ids: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImageConfig {
+    config: Option<RuntimeConfig>,
+    rootfs: RootFs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+    Hardlink,
+    Other,
+}
+
+impl Display for FileKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File => f.write_str("file"),
+            Self::Dir => f.write_str("dir"),
+            Self::Symlink => f.write_str("symlink"),
+            Self::Hardlink => f.write_str("hardlink"),
+            Self::Other => f.write_str("other"),
+        }
+    }
+}
+
+/// File of the image filesystem, after applying all the layers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileDetails {
+    pub kind: FileKind,
+    pub mode: u32,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+impl Display for FileDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {:o}", self.kind, self.mode)?;
+        match (&self.sha256, &self.link) {
+            (Some(sha256), _) => write!(f, " {} bytes sha256:{}", self.size, sha256),
+            (None, Some(link)) => write!(f, " -> {}", link),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LayerDetails {
+    pub digest: String,
+    pub media_type: String,
+    pub size: i64,
+    pub diff_id: String,
+}
+
+impl Display for LayerDetails {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}, {} bytes)", self.digest, self.media_type, self.size)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageDetails {
+    pub digest: String,
+    pub annotations: BTreeMap<String, String>,
+    pub env: Vec<String>,
+    pub entrypoint: Option<Vec<String>>,
+    pub cmd: Option<Vec<String>>,
+    pub working_dir: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    pub layers: Vec<LayerDetails>,
+    /// Only listed on request as all the layers need to be fetched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<BTreeMap<String, FileDetails>>,
+}
+
+/// Path of an archive entry relative to the image root, "" for the root
+fn entry_path(path: &Path) -> String {
+    path.components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Remove a path with everything below it
+fn remove_path(files: &mut BTreeMap<String, FileDetails>, path: &str) {
+    let prefix = format!("{}/", path);
+    files.retain(|p, _| p != path && !p.starts_with(&prefix));
+}
+
+/// Remove everything below a directory, keep the directory itself
+fn remove_dir_content(files: &mut BTreeMap<String, FileDetails>, dir: &str) {
+    if dir.is_empty() {
+        files.clear();
+        return;
+    }
+
+    let prefix = format!("{}/", dir);
+    files.retain(|p, _| !p.starts_with(&prefix));
+}
+
+/// Apply a layer to the files following the layer changeset rules, whiteouts
+/// only hide the files of the lower layers
+async fn apply_layer<R: AsyncRead + Send + Sync + Unpin>(
+    files: &mut BTreeMap<String, FileDetails>,
+    reader: R,
+    diff_id: &Digest,
+) -> Result<(), Error> {
+    let mut archive = Archive::new(Hasher::new(diff_id.hash_type(), reader));
+    let mut whiteouts = Vec::new();
+    let mut added = Vec::new();
+
+    let mut entries = archive.entries()?;
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry.map_err(|e| {
+            error!("Failed to read entries from archive: {e}");
+            Error::LayerInvalidError
+        })?;
+
+        let path = entry.path().map_err(|e| {
+            error!("Archive entry has invalid path: {e}");
+            Error::LayerInvalidError
+        })?;
+        let path = entry_path(&path);
+        let (parent, name) = match path.rsplit_once('/') {
+            Some((parent, name)) => (parent.to_string(), name.to_string()),
+            None => (String::new(), path.clone()),
+        };
+
+        if name == WHITEOUT_OPAQUE {
+            whiteouts.push((parent, true));
+            continue;
+        }
+        if let Some(name) = name.strip_prefix(WHITEOUT_FILE) {
+            let hidden = if parent.is_empty() { name.to_string() } else { format!("{}/{}", parent, name) };
+            whiteouts.push((hidden, false));
+            continue;
+        }
+        if path.is_empty() {
+            continue;
+        }
+
+        let header = entry.header();
+        let kind = match header.entry_type() {
+            EntryType::Regular | EntryType::Continuous => FileKind::File,
+            EntryType::Directory => FileKind::Dir,
+            EntryType::Symlink => FileKind::Symlink,
+            EntryType::Link => FileKind::Hardlink,
+            _ => FileKind::Other,
+        };
+        let mode = header.mode()?;
+        let size = header.size()?;
+        let link = entry
+            .link_name()?
+            .map(|link| link.to_string_lossy().to_string());
+
+        let sha256 = match kind {
+            FileKind::File => {
+                let mut hasher = Hasher::new(&HashType::Sha256, &mut entry);
+                tokio::io::copy(&mut hasher, &mut tokio::io::sink()).await?;
+                Some(hex::encode(hasher.finalize()))
+            },
+            _ => None,
+        };
+
+        debug!("Layer entry: {}", path);
+        added.push((path, FileDetails { kind, mode, size, sha256, link }));
+    }
+    drop(entries);
+
+    // read the archive to the end, so the hash covers the whole layer
+    let mut rest = Vec::new();
+    archive.read_to_end(&mut rest).await?;
+    let mut hasher = archive.into_inner().map_err(|_| Error::UnknownError)?;
+    let encoded_diff_id = hex::encode(hasher.finalize());
+    if diff_id.value() != encoded_diff_id {
+        error!("Diff id does not match! Expected: \"{}\", Got: \"{}\"", diff_id.value(), encoded_diff_id);
+        return Err(Error::LayerInvalidDiffIdError);
+    }
+
+    for (path, opaque) in whiteouts {
+        if opaque {
+            remove_dir_content(files, &path);
+        } else {
+            remove_path(files, &path);
+        }
+    }
+    for (path, file) in added {
+        if file.kind != FileKind::Dir {
+            // a file replacing a directory hides its content
+            remove_path(files, &path);
+        }
+        files.insert(path, file);
+    }
+
+    Ok(())
+}
+
+/// Inspect an image: annotations, runtime config and layers and optionally
+/// the files of the image filesystem (all the layers are fetched for that)
+pub async fn inspect(source: &ImageSource<'_>, reference: &str, with_files: bool) -> Result<ImageDetails, Error> {
+    let (manifest, digest) = source.manifest(reference).await?;
+    info!("Inspecting manifest \"{}\"", digest);
+
+    let config_digest = Digest::try_from(manifest.config().digest().as_str())?;
+    let config: ImageConfig = serde_json::from_slice(&source.blob(&config_digest).await?)?;
+    let runtime = config.config.unwrap_or_default();
+
+    if config.rootfs.diff_ids.len() != manifest.layers().len() {
+        error!("Not enough diff_ids for layers");
+        return Err(Error::LayerInvalidDiffIdError);
+    }
+
+    let layers: Vec<LayerDetails> = manifest
+        .layers()
+        .iter()
+        .zip(&config.rootfs.diff_ids)
+        .map(|(layer, diff_id)| LayerDetails {
+            digest: layer.digest().to_string(),
+            media_type: layer.media_type().to_string(),
+            size: layer.size(),
+            diff_id: diff_id.clone(),
+        })
+        .collect();
+
+    let files = if with_files {
+        let mut files = BTreeMap::new();
+        for (layer, diff_id) in manifest.layers().iter().zip(&config.rootfs.diff_ids) {
+            info!("Listing files of layer \"{}\"", layer.digest());
+            let layer_digest = Digest::try_from(layer.digest().as_str())?;
+            let reader = get_layer_reader(source.blob_reader(&layer_digest).await?, layer.media_type())?;
+            apply_layer(&mut files, reader, &Digest::try_from(diff_id.as_str())?).await?;
+        }
+        Some(files)
+    } else {
+        None
+    };
+
+    Ok(ImageDetails {
+        digest: digest.to_string(),
+        annotations: manifest.annotations().clone().unwrap_or_default().into_iter().collect(),
+        env: runtime.env.unwrap_or_default(),
+        entrypoint: runtime.entrypoint,
+        cmd: runtime.cmd,
+        working_dir: runtime.working_dir.filter(|dir| !dir.is_empty()),
+        labels: runtime.labels.unwrap_or_default().into_iter().collect(),
+        layers,
+        files,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change<T> {
+    Added(T),
+    Removed(T),
+    Changed { from: T, to: T },
+}
+
+impl<T: Clone + PartialEq> Change<T> {
+    fn new(from: Option<&T>, to: Option<&T>) -> Option<Self> {
+        match (from, to) {
+            (None, Some(to)) => Some(Self::Added(to.clone())),
+            (Some(from), None) => Some(Self::Removed(from.clone())),
+            (Some(from), Some(to)) if from != to => {
+                Some(Self::Changed { from: from.clone(), to: to.clone() })
+            },
+            _ => None,
+        }
+    }
+}
+
+fn diff_maps<K: Ord + Clone, T: Clone + PartialEq>(from: &BTreeMap<K, T>, to: &BTreeMap<K, T>) -> BTreeMap<K, Change<T>> {
+    from.keys()
+        .chain(to.keys())
+        .filter_map(|key| Change::new(from.get(key), to.get(key)).map(|change| (key.clone(), change)))
+        .collect()
+}
+
+/// Environment as a map of variable names to values
+fn env_map(env: &[String]) -> BTreeMap<String, String> {
+    env.iter()
+        .map(|var| match var.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => (var.clone(), String::new()),
+        })
+        .collect()
+}
+
+/// Differences between two images, empty fields mean no change. Layers are
+/// compared by their position.
+#[derive(Debug, Default, Serialize)]
+pub struct ImageDiff {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<Change<String>>,
+    pub annotations: BTreeMap<String, Change<String>>,
+    pub env: BTreeMap<String, Change<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entrypoint: Option<Change<Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cmd: Option<Change<Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<Change<String>>,
+    pub labels: BTreeMap<String, Change<String>>,
+    pub layers: BTreeMap<usize, Change<LayerDetails>>,
+    /// Only compared when both images have their files listed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub files: Option<BTreeMap<String, Change<FileDetails>>>,
+}
+
+impl ImageDiff {
+    pub fn new(from: &ImageDetails, to: &ImageDetails) -> Self {
+        let layers = |image: &ImageDetails| -> BTreeMap<usize, LayerDetails> {
+            image.layers.iter().cloned().enumerate().collect()
+        };
+
+        Self {
+            digest: Change::new(Some(&from.digest), Some(&to.digest)),
+            annotations: diff_maps(&from.annotations, &to.annotations),
+            env: diff_maps(&env_map(&from.env), &env_map(&to.env)),
+            entrypoint: Change::new(from.entrypoint.as_ref(), to.entrypoint.as_ref()),
+            cmd: Change::new(from.cmd.as_ref(), to.cmd.as_ref()),
+            working_dir: Change::new(from.working_dir.as_ref(), to.working_dir.as_ref()),
+            labels: diff_maps(&from.labels, &to.labels),
+            layers: diff_maps(&layers(from), &layers(to)),
+            files: match (&from.files, &to.files) {
+                (Some(from), Some(to)) => Some(diff_maps(from, to)),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.digest.is_none()
+            && self.annotations.is_empty()
+            && self.env.is_empty()
+            && self.entrypoint.is_none()
+            && self.cmd.is_none()
+            && self.working_dir.is_none()
+            && self.labels.is_empty()
+            && self.layers.is_empty()
+            && !matches!(&self.files, Some(files) if !files.is_empty())
+    }
+}
+
+fn write_change<T>(
+    f: &mut std::fmt::Formatter<'_>,
+    name: &str,
+    change: &Change<T>,
+    show: impl Fn(&T) -> String,
+) -> std::fmt::Result {
+    match change {
+        Change::Added(value) => writeln!(f, "+ {}: {}", name, show(value)),
+        Change::Removed(value) => writeln!(f, "- {}: {}", name, show(value)),
+        Change::Changed { from, to } => writeln!(f, "~ {}: {} -> {}", name, show(from), show(to)),
+    }
+}
+
+fn write_changes<K: Display, T>(
+    f: &mut std::fmt::Formatter<'_>,
+    name: &str,
+    changes: &BTreeMap<K, Change<T>>,
+    show: impl Fn(&T) -> String,
+) -> std::fmt::Result {
+    for (key, change) in changes {
+        write_change(f, &format!("{}[{}]", name, key), change, &show)?;
+    }
+    Ok(())
+}
+
+/// One change per line: "+" added, "-" removed and "~" changed
+impl Display for ImageDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = |value: &String| value.clone();
+        let args = |value: &Vec<String>| format!("{:?}", value);
+
+        if let Some(digest) = &self.digest {
+            write_change(f, "digest", digest, string)?;
+        }
+        write_changes(f, "annotations", &self.annotations, string)?;
+        write_changes(f, "env", &self.env, string)?;
+        if let Some(entrypoint) = &self.entrypoint {
+            write_change(f, "entrypoint", entrypoint, args)?;
+        }
+        if let Some(cmd) = &self.cmd {
+            write_change(f, "cmd", cmd, args)?;
+        }
+        if let Some(working_dir) = &self.working_dir {
+            write_change(f, "working_dir", working_dir, string)?;
+        }
+        write_changes(f, "labels", &self.labels, string)?;
+        write_changes(f, "layers", &self.layers, |layer| layer.to_string())?;
+        if let Some(files) = &self.files {
+            write_changes(f, "files", files, |file| file.to_string())?;
+        }
+
+        Ok(())
+    }
+}
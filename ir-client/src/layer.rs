@@ -12,8 +12,8 @@ use tokio_tar::{Archive, ArchiveBuilder};
 
 use crate::{error::Error, hasher::Hasher, oci::reference::Digest};
 
-const WHITEOUT_FILE: &str = ".wh.";
-const WHITEOUT_OPAQUE: &str = ".wh..wh..opq";
+pub(crate) const WHITEOUT_FILE: &str = ".wh.";
+pub(crate) const WHITEOUT_OPAQUE: &str = ".wh..wh..opq";
 
 enum Whiteout {
     WhiteoutOpaque,
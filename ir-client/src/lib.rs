@@ -5,6 +5,8 @@ pub mod oci;
 pub mod client;
 pub mod config;
 pub mod error;
+pub mod inspect;
 pub mod layer;
+pub mod revocation;
 
 pub use utils::verify_digest;
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use reqwest::header::{HeaderMap, WWW_AUTHENTICATE};
+use serde::Deserialize;
+use url::Url;
+
+use crate::error::Error;
+
+// Token lifetime when the server doesn't give one, as in the distribution spec
+const DEFAULT_EXPIRES_IN: u64 = 60;
+
+pub(crate) fn pull_scope(app_name: &str) -> String {
+    format!("repository:{}:pull", app_name)
+}
+
+/// Parameters of a `WWW-Authenticate: Bearer ...` challenge
+#[derive(Debug)]
+pub(crate) struct Challenge {
+    realm: String,
+    service: Option<String>,
+    scope: Option<String>,
+}
+
+impl Challenge {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, Error> {
+        headers
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(Self::parse)
+            .ok_or(Error::AuthError("Server didn't send a Bearer challenge".to_string()))
+    }
+
+    fn parse(header: &str) -> Option<Self> {
+        let (scheme, mut rest) = header.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return None;
+        }
+
+        // key=value or key="value" pairs, quoted values can contain commas
+        let mut params = HashMap::new();
+        loop {
+            rest = rest.trim_start_matches([' ', ',']);
+            let Some((key, value)) = rest.split_once('=') else {
+                break;
+            };
+            let (value, next) = match value.strip_prefix('"') {
+                Some(quoted) => quoted.split_once('"')?,
+                None => value.split_once(',').unwrap_or((value, "")),
+            };
+            params.insert(key.trim().to_ascii_lowercase(), value.to_string());
+            rest = next;
+        }
+
+        Some(Self {
+            realm: params.remove("realm")?,
+            service: params.remove("service"),
+            scope: params.remove("scope"),
+        })
+    }
+
+    /// Scope the token is requested for, the cache key
+    pub fn scope(&self) -> &str {
+        self.scope.as_deref().unwrap_or_default()
+    }
+
+    /// URL the credentials are sent to. The realm has to use HTTPS unless the
+    /// registry itself is plain HTTP, a realm on another host is allowed (token
+    /// servers often are separate) but reported.
+    pub fn token_url(&self, registry: &Url) -> Result<Url, Error> {
+        let mut url = Url::parse(&self.realm)?;
+        if url.scheme() != "https" && registry.scheme() != "http" {
+            return Err(Error::AuthError(format!(
+                "Refusing to send credentials to a {} realm of a {} registry: {}",
+                url.scheme(),
+                registry.scheme(),
+                url
+            )));
+        }
+        if url.host_str() != registry.host_str() {
+            warn!(
+                "Token realm host {} differs from the registry host {}",
+                url.host_str().unwrap_or_default(),
+                registry.host_str().unwrap_or_default()
+            );
+        }
+        {
+            let mut query = url.query_pairs_mut();
+            if let Some(service) = &self.service {
+                query.append_pair("service", service);
+            }
+            if let Some(scope) = &self.scope {
+                query.append_pair("scope", scope);
+            }
+        }
+        Ok(url)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+    expires_in: Option<u64>,
+}
+
+/// Bearer tokens by scope until they expire
+#[derive(Debug, Default)]
+pub(crate) struct TokenCache {
+    tokens: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl TokenCache {
+    pub fn get(&self, scope: &str) -> Option<String> {
+        let tokens = self.tokens.lock().unwrap();
+        tokens
+            .get(scope)
+            .filter(|(_, expires)| *expires > Instant::now())
+            .map(|(token, _)| token.clone())
+    }
+
+    pub fn insert(&self, scope: &str, response: TokenResponse) -> Result<String, Error> {
+        let token = response
+            .token
+            .or(response.access_token)
+            .ok_or(Error::AuthError("Token missing in the response".to_string()))?;
+        let expires = Instant::now()
+            + Duration::from_secs(response.expires_in.unwrap_or(DEFAULT_EXPIRES_IN));
+
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(scope.to_string(), (token.clone(), expires));
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::{HeaderMap, HeaderValue, WWW_AUTHENTICATE};
+    use url::Url;
+
+    use super::Challenge;
+
+    fn challenge(realm: &str) -> Challenge {
+        let mut headers = HeaderMap::new();
+        let value = format!(
+            "Bearer realm=\"{}\",service=\"registry\",scope=\"repository:app:pull\"",
+            realm
+        );
+        headers.insert(WWW_AUTHENTICATE, HeaderValue::from_str(&value).unwrap());
+        Challenge::from_headers(&headers).unwrap()
+    }
+
+    #[test]
+    fn token_url_realm() {
+        let https = Url::parse("https://registry.example:1337/v2/app/manifests/v1").unwrap();
+        let http = Url::parse("http://registry.example:1337/v2/app/manifests/v1").unwrap();
+
+        let url = challenge("https://registry.example/token").token_url(&https).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://registry.example/token?service=registry&scope=repository%3Aapp%3Apull"
+        );
+        assert_eq!(challenge("https://registry.example/token").scope(), "repository:app:pull");
+
+        // another host is only reported
+        assert!(challenge("https://auth.example/token").token_url(&https).is_ok());
+
+        // no downgrade from an HTTPS registry, an HTTP one is unencrypted anyway
+        assert!(challenge("http://registry.example/token").token_url(&https).is_err());
+        assert!(challenge("ftp://registry.example/token").token_url(&https).is_err());
+        assert!(challenge("http://registry.example/token").token_url(&http).is_ok());
+    }
+}
@@ -1,5 +1,6 @@
-use crate::config::Config;
+use crate::config::{Config, Credentials};
 use crate::error::Error;
+use crate::oci::auth::{self, Challenge, TokenCache};
 use crate::oci::reference::{Digest, Reference, Tag};
 use crate::oci::service_url::{ServiceFile, ServiceUrl, TagList};
 use crate::utils;
@@ -11,7 +12,9 @@ use oci_spec::distribution::TagList as OciTagList;
 use oci_spec::image::{ImageManifest as OciImageManifest, MediaType};
 use reqwest::blocking::{Client as ReqwestClient, Response};
 use reqwest::header::ACCEPT;
+use reqwest::StatusCode;
 use serde::de::DeserializeOwned;
+use url::Url;
 
 #[derive(Debug)]
 pub struct BlobReader {
@@ -63,29 +66,29 @@ impl Read for BlobReader {
 pub struct Client {
     url: ServiceUrl,
     reqwest_client: ReqwestClient,
+    credentials: Option<Credentials>,
+    tokens: TokenCache,
 }
 
 impl Client {
     /// Create new image registry client from given configuration
     pub fn from_config(config: Config) -> Result<Self, Error> {
-        let Config { host, mode } = config;
+        let Config { host, mode, credentials } = config;
         let url = ServiceUrl::init(mode.scheme(), host);
-        match mode.into_rustls_config() {
-            None => Ok(Self {
-                url,
-                reqwest_client: ReqwestClient::new(),
-            }),
-            Some(client_config) => {
-                let reqwest_client = ReqwestClient::builder()
-                    .use_preconfigured_tls(client_config)
-                    .build()
-                    .map_err(Error::into_config)?;
-                Ok(Self {
-                    url,
-                    reqwest_client,
-                })
-            }
-        }
+        let reqwest_client = match mode.into_rustls_config() {
+            None => ReqwestClient::new(),
+            Some(client_config) => ReqwestClient::builder()
+                .use_preconfigured_tls(client_config)
+                .build()
+                .map_err(Error::into_config)?,
+        };
+
+        Ok(Self {
+            url,
+            reqwest_client,
+            credentials,
+            tokens: TokenCache::default(),
+        })
     }
 
     pub fn get_manifest(
@@ -167,39 +170,87 @@ impl Client {
     fn get_response(&self, app_name: &str, file: ServiceFile) -> Result<Response, Error> {
         let accepted_types = file.supported_media_types();
         let url = self.url.get_url_path(app_name, file)?;
+        let scope = auth::pull_scope(app_name);
 
         info!("Fetching response from {}", url);
         debug!("Supported media types: {}", accepted_types.join(","));
 
-        match self
+        let token = self.tokens.get(&scope);
+        let mut response = self.send(url.clone(), &accepted_types, token)?;
+        // no token yet, or the server doesn't take the cached one anymore
+        if response.status() == StatusCode::UNAUTHORIZED {
+            if let Some(credentials) = &self.credentials {
+                let token = self.fetch_token(&response, credentials)?;
+                response = self.send(url, &accepted_types, Some(token))?;
+            }
+        }
+
+        if response.status().is_success() {
+            if let Some(content_type_str) = utils::content_type(response.headers()) {
+                debug!("Content-Type:\"{content_type_str}\"");
+
+                if !accepted_types.contains(&content_type_str.to_string()) {
+                    warn!("Server returned unsupported content type");
+                }
+            }
+
+            Ok(response)
+        } else {
+            Err(Error::StatusError(response.status().as_u16()))
+        }
+    }
+
+    fn send(
+        &self,
+        url: Url,
+        accepted_types: &[String],
+        token: Option<String>,
+    ) -> Result<Response, Error> {
+        let mut request = self
             .reqwest_client
             .get(url)
-            .header(ACCEPT, accepted_types.join(","))
+            .header(ACCEPT, accepted_types.join(","));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+
+        request
             .send()
             .inspect_err(|e| error!("Failed to send request: {}", e))
-        {
-            Ok(response) => {
-                if response.status().is_success() {
-                    if let Some(content_type_str) = utils::content_type(response.headers()) {
-                        debug!("Content-Type:\"{content_type_str}\"");
-
-                        if !accepted_types.contains(&content_type_str.to_string()) {
-                            warn!("Server returned unsupported content type");
-                        }
-                    }
-
-                    Ok(response)
-                } else {
-                    Err(Error::StatusError(response.status().as_u16()))
-                }
-            }
-            Err(err) => {
-                if let Some(status_error) = err.status() {
-                    Err(Error::StatusError(status_error.as_u16()))
-                } else {
-                    Err(Error::ConnectionError)
-                }
-            }
+            .map_err(|err| match err.status() {
+                Some(status_error) => Error::StatusError(status_error.as_u16()),
+                None => Error::ConnectionError,
+            })
+    }
+
+    /// Exchange the credentials for a token as asked by the server's challenge
+    fn fetch_token(
+        &self,
+        response: &Response,
+        credentials: &Credentials,
+    ) -> Result<String, Error> {
+        let challenge = Challenge::from_headers(response.headers())?;
+        let url = challenge.token_url(response.url())?;
+        if url.scheme() != "https" {
+            warn!("Sending credentials to {} unencrypted", url);
         }
+        info!("Fetching token from {}", url);
+
+        let token_response = self
+            .reqwest_client
+            .get(url)
+            .basic_auth(&credentials.username, Some(&credentials.secret))
+            .send()
+            .inspect_err(|e| error!("Failed to send token request: {}", e))
+            .map_err(|_| Error::ConnectionError)?;
+        if !token_response.status().is_success() {
+            return Err(Error::AuthError(format!(
+                "Token request failed with status {}",
+                token_response.status().as_u16()
+            )));
+        }
+
+        let bytes = token_response.bytes().map_err(|_| Error::UnknownError)?;
+        self.tokens.insert(challenge.scope(), serde_json::from_slice(&bytes)?)
     }
 }
@@ -1,3 +1,4 @@
+pub(crate) mod auth;
 pub(crate) mod service_url;
 
 pub mod client;
@@ -24,6 +24,10 @@ impl Digest {
         &self.value
     }
 
+    pub(crate) fn from_sha256(hash: &[u8]) -> Self {
+        Digest { hash_type: HashType::Sha256, value: hex::encode(hash) }
+    }
+
     fn from_str(value: &str) -> Option<Self> {
         let digest_re = Regex::new(Self::REGEX).expect("Digest regex is malformed");
         let captures = digest_re.captures(value)?;
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ir_verify::annotation::{annotation, SIGNATURE_EXPIRY, SIGNATURE_TIMESTAMP, VENDORPUB};
+use log::error;
+use serde::Deserialize;
+use sha2::{Digest as Sha2Digest, Sha256};
+
+use crate::error::Error;
+
+/// List of revoked vendor keys as produced by `ir-sign revoke-key`.
+/// Keys are identified by SHA256 fingerprints of their SPKI DER encoding.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RevocationList {
+    pub issued: u64,
+    pub revoked: Vec<String>,
+}
+
+impl RevocationList {
+    /// Load the list verifying its signature with ROOT-CA public key
+    pub fn load(
+        path: impl AsRef<Path>,
+        signature: impl AsRef<Path>,
+        ca_pub: impl AsRef<Path>,
+    ) -> Result<Self, Error> {
+        let json = std::fs::read(path)?;
+        let signature = std::fs::read(signature)?;
+        let ca_pub = std::fs::read(ca_pub)?;
+
+        // the signature may be written in PEM by `ir-sign`
+        ir_verify::signature_der(&signature)
+            .and_then(|signature| ir_verify::verify(&ca_pub, &json, &signature))
+            .map_err(|e| Error::RevocationListError(e.to_string()))?;
+
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    pub fn is_revoked(&self, public_der: &[u8]) -> bool {
+        let fingerprint = hex::encode(Sha256::digest(public_der));
+        self.revoked.contains(&fingerprint)
+    }
+}
+
+/// Media type of the cosign signature payloads made by `ir-sign --format cosign`
+pub(crate) const COSIGN_SIMPLESIGNING: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+
+/// Check signature annotations of an image against expiry and optionally
+/// a revocation list. Images without signature annotations are accepted,
+/// images with multiple signatures are rejected if any of them is expired or
+/// made with a revoked key: the slots aren't verified here, so one can't
+/// vouch for another. Verification of the signatures is left to the caller.
+pub fn check_annotations(
+    annotations: Option<&HashMap<String, String>>,
+    revocation: Option<&RevocationList>,
+) -> Result<(), Error> {
+    let Some(annotations) = annotations else {
+        return Ok(());
+    };
+
+    let mut slot = 0;
+    while let Some(vendor_pub) = annotations.get(&annotation(slot, VENDORPUB)) {
+        let timestamp = annotations.get(&annotation(slot, SIGNATURE_TIMESTAMP));
+        let expiry = annotations.get(&annotation(slot, SIGNATURE_EXPIRY));
+        check_signature(vendor_pub, timestamp, expiry, revocation)?;
+        slot += 1;
+    }
+
+    Ok(())
+}
+
+/// Same checks for a layer of a cosign signature manifest: the vendor key is
+/// kept in the layer annotations, timestamp and expiry in the signed payload
+/// under the same names as in the manifest annotations.
+pub fn check_cosign_layer(
+    annotations: Option<&HashMap<String, String>>,
+    payload: &[u8],
+    revocation: Option<&RevocationList>,
+) -> Result<(), Error> {
+    let Some(vendor_pub) = annotations.and_then(|a| a.get(&annotation(0, VENDORPUB))) else {
+        error!("Cosign signature without a vendor key");
+        return Err(Error::ManifestFormatError);
+    };
+
+    let payload: serde_json::Value = serde_json::from_slice(payload)?;
+    let optional = |name| {
+        payload["optional"][&annotation(0, name)]
+            .as_str()
+            .map(|v| v.to_string())
+    };
+    let timestamp = optional(SIGNATURE_TIMESTAMP);
+    let expiry = optional(SIGNATURE_EXPIRY);
+
+    check_signature(vendor_pub, timestamp.as_ref(), expiry.as_ref(), revocation)
+}
+
+fn check_signature(
+    vendor_pub: &str,
+    timestamp: Option<&String>,
+    expiry: Option<&String>,
+    revocation: Option<&RevocationList>,
+) -> Result<(), Error> {
+    if let Some(expiry) = expiry {
+        // the expiry is only signed along with a timestamp
+        if timestamp.is_none() {
+            error!("Signature expiry without a timestamp");
+            return Err(Error::ManifestFormatError);
+        }
+        let expiry: u64 = expiry.parse().map_err(|_| {
+            error!("Incorrect signature expiry: {}", expiry);
+            Error::ManifestFormatError
+        })?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        if now > expiry {
+            error!("Image signature expired at {}", expiry);
+            return Err(Error::SignatureExpiredError);
+        }
+    }
+
+    if let Some(revocation) = revocation {
+        let vendor_pub = hex::decode(vendor_pub).map_err(|_| Error::ManifestFormatError)?;
+        if revocation.is_revoked(&vendor_pub) {
+            error!("Image signed with a revoked vendor key");
+            return Err(Error::SignatureRevokedError);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REVOKED_PUB: &[u8] = b"revoked vendor key";
+    const FORGED_PUB: &[u8] = b"any other key";
+
+    fn revocation() -> RevocationList {
+        RevocationList {
+            issued: 0,
+            revoked: vec![hex::encode(Sha256::digest(REVOKED_PUB))],
+        }
+    }
+
+    #[test]
+    fn revoked_slot_with_forged_slot() {
+        let mut annotations = HashMap::new();
+        annotations.insert(annotation(0, VENDORPUB), hex::encode(REVOKED_PUB));
+        annotations.insert(annotation(1, VENDORPUB), hex::encode(FORGED_PUB));
+
+        let result = check_annotations(Some(&annotations), Some(&revocation()));
+        assert!(matches!(result, Err(Error::SignatureRevokedError)));
+
+        // the order of the slots doesn't matter
+        annotations.insert(annotation(0, VENDORPUB), hex::encode(FORGED_PUB));
+        annotations.insert(annotation(1, VENDORPUB), hex::encode(REVOKED_PUB));
+        let result = check_annotations(Some(&annotations), Some(&revocation()));
+        assert!(matches!(result, Err(Error::SignatureRevokedError)));
+    }
+
+    #[test]
+    fn expired_slot_with_forged_slot() {
+        let mut annotations = HashMap::new();
+        annotations.insert(annotation(0, VENDORPUB), hex::encode(FORGED_PUB));
+        annotations.insert(annotation(0, SIGNATURE_EXPIRY), "1".to_string());
+        annotations.insert(annotation(1, VENDORPUB), hex::encode(FORGED_PUB));
+
+        // the expiry isn't signed without a timestamp
+        let result = check_annotations(Some(&annotations), None);
+        assert!(matches!(result, Err(Error::ManifestFormatError)));
+
+        annotations.insert(annotation(0, SIGNATURE_TIMESTAMP), "0".to_string());
+        let result = check_annotations(Some(&annotations), None);
+        assert!(matches!(result, Err(Error::SignatureExpiredError)));
+    }
+
+    #[test]
+    fn cosign_layer() {
+        let annotations = HashMap::from([(annotation(0, VENDORPUB), hex::encode(REVOKED_PUB))]);
+        let payload = br#"{"critical":{},"optional":{"com.samsung.islet.image.signature.timestamp":"1"}}"#;
+        assert!(check_cosign_layer(Some(&annotations), payload, None).is_ok());
+        let result = check_cosign_layer(Some(&annotations), payload, Some(&revocation()));
+        assert!(matches!(result, Err(Error::SignatureRevokedError)));
+
+        let expired = br#"{"optional":{"com.samsung.islet.image.signature.timestamp":"0",
+            "com.samsung.islet.image.signature.expiry":"1"}}"#;
+        let result = check_cosign_layer(Some(&annotations), expired, None);
+        assert!(matches!(result, Err(Error::SignatureExpiredError)));
+
+        let result = check_cosign_layer(None, payload, None);
+        assert!(matches!(result, Err(Error::ManifestFormatError)));
+    }
+
+    #[test]
+    fn unsigned_and_valid_slots() {
+        assert!(check_annotations(None, Some(&revocation())).is_ok());
+
+        let mut annotations = HashMap::new();
+        annotations.insert(annotation(0, VENDORPUB), hex::encode(FORGED_PUB));
+        annotations.insert(annotation(1, VENDORPUB), hex::encode(FORGED_PUB));
+        assert!(check_annotations(Some(&annotations), Some(&revocation())).is_ok());
+    }
+}
@@ -41,6 +41,10 @@ pub(crate) fn verify_content_type(content_type: &Option<String>, media_type: &Op
     }
 }
 
+pub(crate) fn sha256_digest(content: &[u8]) -> Digest {
+    Digest::from_sha256(&Sha256::digest(content))
+}
+
 pub fn verify_digest(digest: &Digest, content: &[u8]) -> bool {
     let digest_value = hex::decode(digest.value()).unwrap_or_default();
 
@@ -0,0 +1,61 @@
+use axum::{
+    extract::{connect_info::ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::warn;
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::config::ServerConfig;
+use crate::peer::Peer;
+
+// application of the /v2/{name}/... routes
+pub(crate) fn path_app(path: &str) -> Option<&str>
+{
+    match path.split('/').collect::<Vec<_>>()[..] {
+        ["", "v2", app, _, ..] => Some(app),
+        _ => None,
+    }
+}
+
+fn denied(app: &str) -> Response
+{
+    let body = json!({
+        "errors": [{
+            "code": "DENIED",
+            "message": "requested access to the resource is denied",
+            "detail": { "name": app },
+        }]
+    });
+    (StatusCode::FORBIDDEN, Json(body)).into_response()
+}
+
+/// Middleware refusing the application routes to clients whose attested
+/// identity or certificate subject isn't allowed by the app policy
+pub async fn authorize(
+    State(config): State<Arc<ServerConfig>>,
+    request: Request,
+    next: Next,
+) -> Response
+{
+    let Some(app) = path_app(request.uri().path()) else {
+        return next.run(request).await;
+    };
+
+    let peer = request.extensions().get::<ConnectInfo<Peer>>();
+    let claims = peer.and_then(|ConnectInfo(peer)| peer.claims.as_deref());
+    let subject = peer.and_then(|ConnectInfo(peer)| peer.subject.as_deref());
+    if !config.is_app_allowed(app, claims, subject) {
+        warn!(
+            "Access to \"{}\" denied to {}",
+            app,
+            peer.map_or("unknown peer", |ConnectInfo(peer)| peer.address.as_str())
+        );
+        return denied(app);
+    }
+
+    next.run(request).await
+}
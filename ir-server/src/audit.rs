@@ -0,0 +1,152 @@
+use axum::{
+    extract::{connect_info::ConnectInfo, Request, State},
+    http::{header, Method},
+    middleware::Next,
+    response::Response,
+};
+use log::error;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::config::{Protocol, AUDIT_LOG_STDOUT};
+use crate::error::RegistryError;
+use crate::peer::Peer;
+use crate::token::Claims;
+use crate::RegistryResult;
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(RegistryError::Config(format!($($arg)+))))
+}
+
+/// One line of the audit log
+#[derive(Debug, Serialize)]
+struct Record<'a>
+{
+    time: String,
+    peer: Option<&'a str>,
+    protocol: Option<&'a Protocol>,
+    claims: Option<&'a Claims>,
+    subject: Option<&'a str>,
+    method: &'a str,
+    app: &'a str,
+    kind: &'static str,
+    reference: &'a str,
+    status: u16,
+    bytes: Option<u64>,
+}
+
+/// Log of manifest and blob requests in JSON lines, with the identity of the
+/// client as established by the TLS handshake
+pub struct AuditLog
+{
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AuditLog
+{
+    /// Append to a file, `AUDIT_LOG_STDOUT` for stdout
+    pub fn open(target: &str) -> RegistryResult<Self>
+    {
+        let out: Box<dyn Write + Send> = match target {
+            AUDIT_LOG_STDOUT => Box::new(std::io::stdout()),
+            path => match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Box::new(file),
+                Err(e) => return err!("Can't open audit log \"{}\": {}", path, e),
+            },
+        };
+
+        Ok(AuditLog {
+            out: Mutex::new(out),
+        })
+    }
+
+    fn write(&self, record: &Record)
+    {
+        // a single write per line so lines don't interleave with other writers
+        let mut line = serde_json::to_vec(record).unwrap_or_default();
+        line.push(b'\n');
+
+        let mut out = self.out.lock().unwrap();
+        if let Err(e) = out.write_all(&line).and_then(|_| out.flush()) {
+            error!("Can't write to the audit log: {}", e);
+        }
+    }
+}
+
+// (app, kind, reference) of the manifest and blob routes
+fn pull_target(path: &str) -> Option<(&str, &'static str, &str)>
+{
+    match path.split('/').collect::<Vec<_>>()[..] {
+        ["", "v2", app, "manifests", reference] => Some((app, "manifest", reference)),
+        ["", "v2", app, "blobs", digest] => Some((app, "blob", digest)),
+        _ => None,
+    }
+}
+
+/// Middleware writing a record for every manifest and blob request, other
+/// routes are passed through
+pub async fn record(State(log): State<Arc<AuditLog>>, request: Request, next: Next) -> Response
+{
+    let path = request.uri().path().to_string();
+    let Some((app, kind, reference)) = pull_target(&path) else {
+        return next.run(request).await;
+    };
+
+    let time = ir_verify::time::rfc3339_millis(SystemTime::now());
+    let method = request.method().clone();
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<Peer>>()
+        .map(|ConnectInfo(peer)| peer.clone());
+
+    let response = next.run(request).await;
+
+    // HEAD responses carry a message, not the content
+    let bytes = match method {
+        Method::GET => response
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse().ok()),
+        _ => None,
+    };
+
+    log.write(&Record {
+        time,
+        peer: peer.as_ref().map(|p| p.address.as_str()),
+        protocol: peer.as_ref().map(|p| &p.protocol),
+        claims: peer.as_ref().and_then(|p| p.claims.as_deref()),
+        subject: peer.as_ref().and_then(|p| p.subject.as_deref()),
+        method: method.as_str(),
+        app,
+        kind,
+        reference,
+        status: response.status().as_u16(),
+        bytes,
+    });
+
+    response
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::pull_target;
+
+    #[test]
+    fn audit_pull_target()
+    {
+        assert_eq!(
+            pull_target("/v2/app/manifests/latest"),
+            Some(("app", "manifest", "latest"))
+        );
+        assert_eq!(
+            pull_target("/v2/app/blobs/sha256:00"),
+            Some(("app", "blob", "sha256:00"))
+        );
+        assert_eq!(pull_target("/v2/app/tags/list"), None);
+        assert_eq!(pull_target("/v2/"), None);
+    }
+}
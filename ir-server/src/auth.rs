@@ -0,0 +1,401 @@
+use axum::{
+    extract::{connect_info::ConnectInfo, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::headers::authorization::{Basic, Bearer};
+use axum_extra::headers::{Authorization, HeaderMapExt};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use axum_extra::TypedHeader;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use log::{info, warn};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::access::path_app;
+use crate::config::{AuthConfig, Protocol};
+use crate::error::RegistryError;
+use crate::peer::Peer;
+use crate::{utils, RegistryResult};
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(RegistryError::Config(format!($($arg)+))))
+}
+
+const PULL: &str = "pull";
+const KEY_LEN: usize = 32;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct User
+{
+    /// Argon2 hash of the secret in the PHC string format
+    secret_hash: String,
+    /// Applications the user may pull, all if left out
+    apps: Option<Vec<String>>,
+}
+
+impl User
+{
+    fn may_pull(&self, app: &str) -> bool
+    {
+        self.apps
+            .as_ref()
+            .is_none_or(|apps| apps.iter().any(|a| a == app))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UsersFile
+{
+    #[serde(default)]
+    users: HashMap<String, User>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Access
+{
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+    actions: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenClaims
+{
+    iss: String,
+    sub: String,
+    aud: String,
+    iat: u64,
+    exp: u64,
+    access: Vec<Access>,
+}
+
+impl TokenClaims
+{
+    fn may_pull(&self, app: &str) -> bool
+    {
+        self.access.iter().any(|access| {
+            access.kind == "repository"
+                && access.name == app
+                && access.actions.iter().any(|a| a == PULL)
+        })
+    }
+}
+
+// only repository:<name>:<actions> scopes are known, the pull action is the
+// only one granted
+fn scope_app(scope: &str) -> Option<&str>
+{
+    let (name, actions) = scope.strip_prefix("repository:")?.rsplit_once(':')?;
+    actions.split(',').any(|a| a == PULL).then_some(name)
+}
+
+/// Issues and verifies the Bearer tokens of the distribution spec token
+/// authentication, the tokens are signed with a key generated on start so they
+/// don't outlive the server
+pub struct Authenticator
+{
+    users: HashMap<String, User>,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    validation: Validation,
+    token_lifetime: Duration,
+    realm: Option<String>,
+    service: String,
+}
+
+impl Authenticator
+{
+    pub fn new(config: &AuthConfig) -> RegistryResult<Self>
+    {
+        let Ok(text) = std::fs::read_to_string(&config.users) else {
+            return err!("Can't read users file \"{}\"", config.users);
+        };
+        let users = match toml::from_str::<UsersFile>(&text) {
+            Ok(file) => file.users,
+            Err(e) => return err!("Users file \"{}\": {}", config.users, e),
+        };
+        for (name, user) in &users {
+            if PasswordHash::new(&user.secret_hash).is_err() {
+                return err!("User \"{}\" has an invalid secret_hash", name);
+            }
+        }
+
+        let mut key = [0u8; KEY_LEN];
+        if SystemRandom::new().fill(&mut key).is_err() {
+            return Err(RegistryError::Generic(
+                "Can't generate the token signing key".to_string(),
+            ));
+        }
+
+        // only our own tokens are accepted: signed with our key, by us and for us
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_issuer(&[&config.service]);
+        validation.set_audience(&[&config.service]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        validation.leeway = 0;
+
+        info!("Token authentication enabled for {} user(s)", users.len());
+        Ok(Authenticator {
+            users,
+            encoding_key: EncodingKey::from_secret(&key),
+            decoding_key: DecodingKey::from_secret(&key),
+            validation,
+            token_lifetime: config.token_lifetime,
+            realm: config.realm.clone(),
+            service: config.service.clone(),
+        })
+    }
+
+    fn authenticate(&self, username: &str, secret: &str) -> Option<&User>
+    {
+        let user = self.users.get(username)?;
+        let hash = PasswordHash::new(&user.secret_hash).ok()?;
+        Argon2::default()
+            .verify_password(secret.as_bytes(), &hash)
+            .ok()
+            .map(|_| user)
+    }
+
+    fn issue(&self, username: &str, apps: Vec<String>) -> String
+    {
+        let now = utils::unix_time();
+        let claims = TokenClaims {
+            iss: self.service.clone(),
+            sub: username.to_string(),
+            aud: self.service.clone(),
+            iat: now,
+            exp: now + self.token_lifetime.as_secs(),
+            access: apps
+                .into_iter()
+                .map(|name| Access {
+                    kind: "repository".to_string(),
+                    name,
+                    actions: vec![PULL.to_string()],
+                })
+                .collect(),
+        };
+
+        // HMAC signing of a struct of strings and numbers can't fail
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.encoding_key)
+            .unwrap_or_default()
+    }
+
+    fn verify(&self, token: &str) -> Option<TokenClaims>
+    {
+        jsonwebtoken::decode::<TokenClaims>(token, &self.decoding_key, &self.validation)
+            .ok()
+            .map(|data| data.claims)
+    }
+
+    fn challenge(&self, request: &Request, peer: Option<&Peer>, app: Option<&str>) -> Response
+    {
+        let realm = self.realm.clone().unwrap_or_else(|| {
+            let scheme = match peer.map(|p| &p.protocol) {
+                Some(Protocol::Tls) => "https",
+                _ => "http",
+            };
+            let host = request
+                .headers()
+                .get(header::HOST)
+                .and_then(|host| host.to_str().ok())
+                .or(request.uri().authority().map(|a| a.as_str()))
+                .unwrap_or("localhost");
+            format!("{}://{}/token", scheme, host)
+        });
+
+        let mut challenge = format!("Bearer realm=\"{}\",service=\"{}\"", realm, self.service);
+        if let Some(app) = app {
+            challenge += &format!(",scope=\"repository:{}:{}\"", app, PULL);
+        }
+
+        let body = json!({
+            "errors": [{
+                "code": "UNAUTHORIZED",
+                "message": "authentication required",
+                "detail": app.map(|app| json!({ "name": app })),
+            }]
+        });
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, challenge)],
+            Json(body),
+        )
+            .into_response()
+    }
+}
+
+/// Middleware requiring a Bearer token on the /v2/ routes from the clients
+/// that don't have a verified certificate
+pub async fn authenticate(
+    State(auth): State<Arc<Authenticator>>,
+    request: Request,
+    next: Next,
+) -> Response
+{
+    if !request.uri().path().starts_with("/v2/") {
+        return next.run(request).await;
+    }
+
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<Peer>>()
+        .map(|ConnectInfo(peer)| peer);
+    // RA-TLS and mTLS clients are authenticated by the handshake already
+    if peer.is_some_and(|peer| matches!(peer.protocol, Protocol::RaTls | Protocol::Mtls)) {
+        return next.run(request).await;
+    }
+
+    let app = path_app(request.uri().path());
+    let claims = request
+        .headers()
+        .typed_get::<Authorization<Bearer>>()
+        .and_then(|bearer| auth.verify(bearer.token()));
+
+    match claims {
+        Some(claims) if app.is_none_or(|app| claims.may_pull(app)) => next.run(request).await,
+        _ => auth.challenge(&request, peer, app),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TokenResponse
+{
+    token: String,
+    access_token: String,
+    expires_in: u64,
+    issued_at: String,
+}
+
+/// Token endpoint, the client authenticates with Basic and gets a token for
+/// the requested scopes it's allowed
+pub async fn get_token(
+    State(auth): State<Arc<Authenticator>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Response
+{
+    let user = basic.as_ref().and_then(|TypedHeader(basic)| {
+        Some((
+            basic.username(),
+            auth.authenticate(basic.username(), basic.password())?,
+        ))
+    });
+    let Some((username, user)) = user else {
+        warn!("Token request with invalid credentials");
+        let challenge = format!("Basic realm=\"{}\"", auth.service);
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, challenge)],
+        )
+            .into_response();
+    };
+
+    let mut apps = Vec::new();
+    for (name, value) in &params {
+        match name.as_str() {
+            "service" if *value != auth.service => {
+                return (StatusCode::BAD_REQUEST, "Unknown service").into_response();
+            }
+            // per the spec, scopes that aren't allowed are left out of the token
+            "scope" => {
+                for app in value.split(' ').filter_map(scope_app) {
+                    if user.may_pull(app) && !apps.iter().any(|a| a == app) {
+                        apps.push(app.to_string());
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    info!("Token for \"{}\" issued, pull: {:?}", username, apps);
+    let token = auth.issue(username, apps);
+    Json(TokenResponse {
+        access_token: token.clone(),
+        token,
+        expires_in: auth.token_lifetime.as_secs(),
+        issued_at: ir_verify::time::rfc3339_millis(SystemTime::now()),
+    })
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{scope_app, Authenticator, TokenClaims};
+    use crate::config::AuthConfig;
+    use crate::utils;
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+
+    #[test]
+    fn auth_token_roundtrip()
+    {
+        let users =
+            std::env::temp_dir().join(format!("ir-server-users-{}.toml", std::process::id()));
+        // `echo -n secret | argon2 ir-server-salt -id -t 2 -k 19456 -e`
+        std::fs::write(
+            &users,
+            "[users.ci]\nsecret_hash = \"$argon2id$v=19$m=19456,t=2,p=1$aXItc2VydmVyLXNhbHQ$JJ1aBCiJnnZzHoPrWhwp4ClbRcRM4qIRmtEblwirrNw\"\napps = [\"app\"]\n",
+        )
+        .unwrap();
+        let auth = Authenticator::new(&AuthConfig::new(&users.to_string_lossy())).unwrap();
+        std::fs::remove_file(&users).unwrap();
+
+        let user = auth.authenticate("ci", "secret").unwrap();
+        assert!(user.may_pull("app") && !user.may_pull("other"));
+        assert!(auth.authenticate("ci", "wrong").is_none());
+        assert!(auth.authenticate("nobody", "secret").is_none());
+
+        let token = auth.issue("ci", vec!["app".to_string()]);
+        let claims = auth.verify(&token).unwrap();
+        assert!(claims.may_pull("app") && !claims.may_pull("other"));
+
+        // any change to the token breaks the signature
+        let mut forged = token.clone();
+        forged.insert(token.find('.').unwrap() + 1, 'x');
+        assert!(auth.verify(&forged).is_none());
+
+        // signed with our key but issued for another service
+        let now = utils::unix_time();
+        let mut claims = TokenClaims {
+            iss: "other".to_string(),
+            sub: "ci".to_string(),
+            aud: auth.service.clone(),
+            iat: now,
+            exp: now + 60,
+            access: Vec::new(),
+        };
+        let header = Header::new(Algorithm::HS256);
+        let key = &auth.encoding_key;
+        let other = jsonwebtoken::encode(&header, &claims, key).unwrap();
+        assert!(auth.verify(&other).is_none());
+        claims.iss = auth.service.clone();
+        claims.exp = now - 1;
+        let expired = jsonwebtoken::encode(&header, &claims, key).unwrap();
+        assert!(auth.verify(&expired).is_none());
+        claims.exp = now + 60;
+        let valid = jsonwebtoken::encode(&header, &claims, key).unwrap();
+        assert!(auth.verify(&valid).is_some());
+        let foreign = EncodingKey::from_secret(b"another key");
+        assert!(auth.verify(&jsonwebtoken::encode(&header, &claims, &foreign).unwrap()).is_none());
+    }
+
+    #[test]
+    fn auth_scope()
+    {
+        assert_eq!(scope_app("repository:app:pull"), Some("app"));
+        assert_eq!(scope_app("repository:app:push,pull"), Some("app"));
+        assert_eq!(scope_app("repository:app:push"), None);
+        assert_eq!(scope_app("registry:catalog:*"), None);
+    }
+}
@@ -1,64 +1,552 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
-use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use crate::error::RegistryError;
-use crate::{utils, RegistryResult};
+use crate::token::Claims;
+use crate::RegistryResult;
 
 pub const DEFAULT_SERVER: &str = "../registry";
-pub const DEFAULT_CERT: &str = "certs/server.crt";
-pub const DEFAULT_KEY: &str = "certs/server.key";
-pub const DEFAULT_RATLS_VERAISON_KEY: &str = "ratls/pkey.jwk";
-pub const DEFAULT_RATLS_REFERENCE_JSON: &str = "ratls/example.json";
+pub const DEFAULT_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+pub const DEFAULT_PORT: u16 = 1337;
+pub const DEFAULT_VERAISON_URL: &str = "https://localhost:8080";
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+pub const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(300);
+pub const DEFAULT_AUTH_SERVICE: &str = "ir-server";
+pub const CONFIG_FILE_ENV: &str = "IR_SERVER_CONFIG";
+pub const AUDIT_LOG_STDOUT: &str = "-";
 
 macro_rules! err {
     ($($arg:tt)+) => (Err(RegistryError::Config(format!($($arg)+))))
 }
 
-#[derive(clap::ValueEnum, Clone, Default, Debug)]
+#[derive(clap::ValueEnum, Clone, Default, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Protocol
 {
     #[default]
     NoTls,
     Tls,
+    /// TLS with client certificates verified against a CA bundle
+    Mtls,
     RaTls,
 }
 
-#[derive(Debug)]
+impl Protocol
+{
+    /// Name as used in the config file, the metrics and the audit log
+    pub fn name(&self) -> &'static str
+    {
+        match self {
+            Protocol::NoTls => "no-tls",
+            Protocol::Tls => "tls",
+            Protocol::Mtls => "mtls",
+            Protocol::RaTls => "ra-tls",
+        }
+    }
+}
+
+/// Where a listener accepts connections
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint
+{
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl std::fmt::Display for Endpoint
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self {
+            Endpoint::Tcp(address) => write!(f, "{}", address),
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListenerConfig
+{
+    pub endpoint: Endpoint,
+    pub protocol: Protocol,
+}
+
+impl ListenerConfig
+{
+    pub fn new(endpoint: Endpoint, protocol: Protocol) -> Self
+    {
+        ListenerConfig { endpoint, protocol }
+    }
+}
+
+impl Default for ListenerConfig
+{
+    fn default() -> Self
+    {
+        ListenerConfig::new(
+            Endpoint::Tcp(SocketAddr::new(DEFAULT_ADDRESS, DEFAULT_PORT)),
+            Protocol::default(),
+        )
+    }
+}
+
+/// Either a TCP address and port or a Unix socket path
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListenerSection
+{
+    pub address: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub path: Option<String>,
+    pub protocol: Option<Protocol>,
+}
+
+impl ListenerSection
+{
+    fn to_listener(&self) -> RegistryResult<ListenerConfig>
+    {
+        let endpoint = match (&self.path, self.address, self.port) {
+            (Some(path), None, None) => Endpoint::Unix(PathBuf::from(path)),
+            (Some(path), _, _) => {
+                return err!(
+                    "Listener on \"{}\" can't have an address or port, it's a Unix socket",
+                    path
+                )
+            }
+            (None, address, port) => Endpoint::Tcp(SocketAddr::new(
+                address.unwrap_or(DEFAULT_ADDRESS),
+                port.unwrap_or(DEFAULT_PORT),
+            )),
+        };
+
+        Ok(ListenerConfig::new(
+            endpoint,
+            self.protocol.clone().unwrap_or_default(),
+        ))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSection
+{
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    /// CA bundle the mTLS client certificates are verified against
+    pub client_ca: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RaTlsSection
+{
+    pub veraison_url: Option<String>,
+    pub veraison_pubkey: Option<String>,
+    pub reference_json: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RevocationSection
+{
+    pub ca_pub: Option<String>,
+    pub list: Option<String>,
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthSection
+{
+    pub users: Option<String>,
+    pub token_lifetime: Option<u64>,
+    pub realm: Option<String>,
+    pub service: Option<String>,
+}
+
+/// Attested identity allowed to pull an application, the claims given have to
+/// match those of the client token, the ones left out match anything. Hex
+/// values are compared case insensitive.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClaimsRule
+{
+    pub realm_initial_measurement: Option<String>,
+    pub realm_extensible_measurements: Option<Vec<String>>,
+    pub realm_personalization_value: Option<String>,
+    pub realm_hash_algorithm: Option<String>,
+    pub platform_profile: Option<String>,
+    pub platform_implementation_id: Option<String>,
+    pub platform_instance_id: Option<String>,
+}
+
+impl ClaimsRule
+{
+    pub fn matches(&self, claims: &Claims) -> bool
+    {
+        let matches = |rule: &Option<String>, claim: &str| {
+            rule.as_ref()
+                .is_none_or(|value| value.eq_ignore_ascii_case(claim))
+        };
+        let rems_match = self
+            .realm_extensible_measurements
+            .as_ref()
+            .is_none_or(|rems| {
+                rems.len() == claims.realm_extensible_measurements.len()
+                    && rems
+                        .iter()
+                        .zip(&claims.realm_extensible_measurements)
+                        .all(|(rule, claim)| rule.eq_ignore_ascii_case(claim))
+            });
+
+        rems_match
+            && matches(
+                &self.realm_initial_measurement,
+                &claims.realm_initial_measurement,
+            )
+            && matches(
+                &self.realm_personalization_value,
+                &claims.realm_personalization_value,
+            )
+            && matches(&self.realm_hash_algorithm, &claims.realm_hash_algorithm)
+            && matches(&self.platform_profile, &claims.platform_profile)
+            && matches(
+                &self.platform_implementation_id,
+                &claims.platform_implementation_id,
+            )
+            && matches(&self.platform_instance_id, &claims.platform_instance_id)
+    }
+}
+
+/// Policy of a single application in the registry
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AppPolicy
+{
+    /// Disabled applications are neither loaded nor served
+    #[serde(default = "AppPolicy::default_enabled")]
+    pub enabled: bool,
+    /// RA-TLS clients allowed to pull the application
+    #[serde(default)]
+    pub allow: Vec<ClaimsRule>,
+    /// Subjects of the mTLS client certificates allowed to pull the
+    /// application, in RFC 4514 format
+    #[serde(default)]
+    pub allow_subjects: Vec<String>,
+}
+
+impl AppPolicy
+{
+    fn default_enabled() -> bool
+    {
+        true
+    }
+
+    /// Restricted applications are served only to the clients the rules allow
+    pub fn is_restricted(&self) -> bool
+    {
+        !self.allow.is_empty() || !self.allow_subjects.is_empty()
+    }
+}
+
+impl Default for AppPolicy
+{
+    fn default() -> Self
+    {
+        AppPolicy {
+            enabled: AppPolicy::default_enabled(),
+            allow: Vec::new(),
+            allow_subjects: Vec::new(),
+        }
+    }
+}
+
+/// Server configuration file in TOML format, everything is optional and the
+/// command line takes precedence. Relative paths are relative to the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile
+{
+    pub root: Option<String>,
+    /// Seconds given to connections in flight to finish on shutdown
+    pub shutdown_timeout: Option<u64>,
+    /// Seconds between the checks for changed certificate, key and reference
+    /// values files, 0 to reload them on SIGHUP only
+    pub reload_interval: Option<u64>,
+    /// File to append the audit log of pulls to, "-" for stdout
+    pub audit_log: Option<String>,
+    #[serde(default)]
+    pub listeners: Vec<ListenerSection>,
+    #[serde(default)]
+    pub tls: TlsSection,
+    #[serde(default)]
+    pub ratls: RaTlsSection,
+    #[serde(default)]
+    pub revocation: RevocationSection,
+    #[serde(default)]
+    pub auth: AuthSection,
+    #[serde(default)]
+    pub apps: HashMap<String, AppPolicy>,
+}
+
+impl ConfigFile
+{
+    pub fn load<T: AsRef<Path>>(path: T) -> RegistryResult<Self>
+    {
+        let path = path.as_ref();
+        let Ok(text) = fs::read_to_string(path) else {
+            return err!("Can't read config file \"{}\"", path.display());
+        };
+
+        let mut file = match ConfigFile::parse(&text) {
+            Ok(file) => file,
+            Err(e) => return err!("Config file \"{}\": {}", path.display(), e),
+        };
+        // unwrap() is safe, the file has been read so it has a parent
+        file.resolve_paths(path.parent().unwrap());
+
+        Ok(file)
+    }
+
+    /// Listeners from the file, none if the file doesn't have any
+    pub fn listeners(&self) -> RegistryResult<Vec<ListenerConfig>>
+    {
+        self.listeners
+            .iter()
+            .map(ListenerSection::to_listener)
+            .collect()
+    }
+
+    fn parse(text: &str) -> Result<Self, toml::de::Error>
+    {
+        toml::from_str(text)
+    }
+
+    fn resolve_paths(&mut self, base: &Path)
+    {
+        let resolve = |path: &mut Option<String>| {
+            if let Some(p) = path {
+                *p = base.join(&*p).to_string_lossy().to_string();
+            }
+        };
+
+        resolve(&mut self.root);
+        if self.audit_log.as_deref() != Some(AUDIT_LOG_STDOUT) {
+            resolve(&mut self.audit_log);
+        }
+        resolve(&mut self.tls.cert);
+        resolve(&mut self.tls.key);
+        resolve(&mut self.tls.client_ca);
+        resolve(&mut self.ratls.veraison_pubkey);
+        resolve(&mut self.ratls.reference_json);
+        resolve(&mut self.revocation.ca_pub);
+        resolve(&mut self.revocation.list);
+        resolve(&mut self.revocation.signature);
+        resolve(&mut self.auth.users);
+        for listener in &mut self.listeners {
+            resolve(&mut listener.path);
+        }
+    }
+}
+
+/// Bearer token authentication of the clients that aren't attested with RA-TLS
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthConfig
+{
+    /// TOML file with the users, their secrets and applications
+    pub users: String,
+    pub token_lifetime: Duration,
+    /// URL of the token endpoint sent to the clients, none to derive it from
+    /// the request
+    pub realm: Option<String>,
+    pub service: String,
+}
+
+impl AuthConfig
+{
+    pub fn new(users: &str) -> Self
+    {
+        AuthConfig {
+            users: users.to_string(),
+            token_lifetime: DEFAULT_TOKEN_LIFETIME,
+            realm: None,
+            service: DEFAULT_AUTH_SERVICE.to_string(),
+        }
+    }
+}
+
+/// Settings of a single server instance, passed to `httpd_run()` and shared
+/// with the request handlers
+#[derive(Debug, Clone)]
 #[non_exhaustive]
-pub struct Config
+pub struct ServerConfig
 {
     pub root: String,
-    pub cert: String,
-    pub key: String,
-    pub tls: Protocol,
-    pub port: u16,
+    /// Server certificate and key, required by the TLS, mTLS and RA-TLS listeners
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    /// CA bundle the client certificates are verified against, required by
+    /// the mTLS listeners
+    pub client_ca: Option<String>,
+    pub listeners: Vec<ListenerConfig>,
+    pub shutdown_timeout: Duration,
+    /// How often the TLS credential files are checked for changes, zero to
+    /// reload them on SIGHUP only
+    pub reload_interval: Duration,
+    /// File to append the audit log of pulls to, `AUDIT_LOG_STDOUT` for stdout
+    pub audit_log: Option<String>,
+    /// Clients on plain and TLS listeners need a token when set
+    pub auth: Option<AuthConfig>,
     pub veraison_url: String,
-    pub veraison_pubkey: String,
-    pub reference_json: String,
+    /// Required by the RA-TLS listeners
+    pub veraison_pubkey: Option<String>,
+    pub reference_json: Option<String>,
+    pub ca_pub: Option<String>,
+    pub revocation_list: Option<String>,
+    pub revocation_signature: Option<String>,
+    pub apps: HashMap<String, AppPolicy>,
 }
 
-impl Config
+impl ServerConfig
 {
-    fn new() -> Self
+    /// Paths are left unset, set them with the `set_*()` functions
+    pub fn new() -> Self
     {
-        Config {
+        ServerConfig {
             root: String::new(),
-            cert: String::new(),
-            key: String::new(),
-            tls: Protocol::default(),
-            port: 0,
-            veraison_url: String::new(),
-            veraison_pubkey: String::new(),
-            reference_json: String::new(),
+            cert: None,
+            key: None,
+            client_ca: None,
+            listeners: vec![ListenerConfig::default()],
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            reload_interval: DEFAULT_RELOAD_INTERVAL,
+            audit_log: None,
+            auth: None,
+            veraison_url: DEFAULT_VERAISON_URL.to_string(),
+            veraison_pubkey: None,
+            reference_json: None,
+            ca_pub: None,
+            revocation_list: None,
+            revocation_signature: None,
+            apps: HashMap::new(),
         }
     }
 
-    pub fn set_server_root(&mut self, root: Option<&str>) -> RegistryResult<()>
+    /// Whether an application is to be loaded and served
+    pub fn is_app_enabled(&self, app: &str) -> bool
+    {
+        self.apps.get(app).is_none_or(|policy| policy.enabled)
+    }
+
+    /// Whether a client may access an application, restricted ones are only
+    /// served to RA-TLS clients whose claims match one of the rules and to
+    /// mTLS clients with one of the allowed subjects
+    pub fn is_app_allowed(&self, app: &str, claims: Option<&Claims>, subject: Option<&str>)
+        -> bool
     {
-        let base = match root {
-            Some(r) => fs::canonicalize(r).or(err!("Server root path \"{}\" doesn't exist", r))?,
-            None => fs::canonicalize(utils::get_crate_root())?.join(DEFAULT_SERVER),
+        match self.apps.get(app) {
+            Some(policy) if policy.is_restricted() => {
+                claims.is_some_and(|claims| policy.allow.iter().any(|rule| rule.matches(claims)))
+                    || subject.is_some_and(|subject| {
+                        policy
+                            .allow_subjects
+                            .iter()
+                            .any(|allowed| allowed == subject)
+                    })
+            }
+            _ => true,
+        }
+    }
+
+    /// Check that the files required by the chosen protocol exist, so a
+    /// misconfiguration is reported on start and not on the first connection
+    pub fn validate(&self) -> RegistryResult<()>
+    {
+        for (app, policy) in &self.apps {
+            if !Path::new(&self.root).join(app).is_dir() {
+                warn!(
+                    "Application \"{}\" from the config file not found in the registry",
+                    app
+                );
+            }
+            // a rule without claims matches every attested client
+            if policy.allow.iter().any(|rule| *rule == ClaimsRule::default()) {
+                return err!("Application \"{}\" has an allow rule without claims", app);
+            }
+        }
+
+        if self.listeners.is_empty() {
+            return err!("No listeners configured");
+        }
+
+        let uses = |protocol: Protocol| self.listeners.iter().any(|l| l.protocol == protocol);
+
+        let restricted = self
+            .apps
+            .iter()
+            .filter(|(_, policy)| policy.is_restricted());
+        for (app, policy) in restricted {
+            let ratls = !policy.allow.is_empty() && uses(Protocol::RaTls);
+            let mtls = !policy.allow_subjects.is_empty() && uses(Protocol::Mtls);
+            if !ratls && !mtls {
+                warn!(
+                    "Application \"{}\" is restricted but there is no listener its allowed clients can use",
+                    app
+                );
+            }
+        }
+
+        // paths are only set when given, only the protocols in use need them
+        let required = |name: &str, path: &Option<String>, protocol: Protocol| {
+            if !uses(protocol.clone()) {
+                return Ok(());
+            }
+            match path {
+                Some(path) if Path::new(path).is_file() => Ok(()),
+                Some(path) => err!(
+                    "{} \"{}\" required by {:?} doesn't exist",
+                    name,
+                    path,
+                    protocol
+                ),
+                None => err!("{} required by {:?} not set", name, protocol),
+            }
         };
+
+        for protocol in [Protocol::Tls, Protocol::Mtls, Protocol::RaTls] {
+            required("Server cert", &self.cert, protocol.clone())?;
+            required("Server key", &self.key, protocol)?;
+        }
+        required("Client CA", &self.client_ca, Protocol::Mtls)?;
+
+        if let Some(auth) = &self.auth {
+            if !Path::new(&auth.users).is_file() {
+                return err!("Users file \"{}\" doesn't exist", auth.users);
+            }
+        }
+
+        #[cfg(not(feature = "disable-challenge-veraison"))]
+        required("Veraison key", &self.veraison_pubkey, Protocol::RaTls)?;
+        required(
+            "Veraison reference JSON",
+            &self.reference_json,
+            Protocol::RaTls,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_server_root(&mut self, root: Option<&str>) -> RegistryResult<()>
+    {
+        let root = root.unwrap_or(DEFAULT_SERVER);
+        let base =
+            fs::canonicalize(root).or(err!("Server root path \"{}\" doesn't exist", root))?;
         self.root = base.to_string_lossy().to_string();
 
         Ok(())
@@ -66,92 +554,262 @@ impl Config
 
     pub fn set_server_cert(&mut self, cert: Option<&str>) -> RegistryResult<()>
     {
-        let base = match cert {
-            Some(c) => fs::canonicalize(c).or(err!("Server cert path \"{}\" doesn't exist", c))?,
-            None => fs::canonicalize(utils::get_crate_root())?.join(DEFAULT_CERT),
-        };
-        self.cert = base.to_string_lossy().to_string();
-
+        self.cert = canonicalize_opt("Server cert", cert)?;
         Ok(())
     }
 
     pub fn set_server_key(&mut self, key: Option<&str>) -> RegistryResult<()>
     {
-        let base = match key {
-            Some(k) => fs::canonicalize(k).or(err!("Server key path \"{}\" doesn't exist", k))?,
-            None => fs::canonicalize(utils::get_crate_root())?.join(DEFAULT_KEY),
-        };
-        self.key = base.to_string_lossy().to_string();
+        self.key = canonicalize_opt("Server key", key)?;
+        Ok(())
+    }
 
+    pub fn set_client_ca(&mut self, client_ca: Option<&str>) -> RegistryResult<()>
+    {
+        self.client_ca = canonicalize_opt("Client CA", client_ca)?;
         Ok(())
     }
 
     pub fn set_veraison_key(&mut self, key: Option<&str>) -> RegistryResult<()>
     {
-        let base = match key {
-            Some(k) => fs::canonicalize(k).or(err!("Veraizon key path \"{}\" doesn't exist", k))?,
-            None => fs::canonicalize(utils::get_crate_root())?.join(DEFAULT_RATLS_VERAISON_KEY),
-        };
-        self.veraison_pubkey = base.to_string_lossy().to_string();
+        self.veraison_pubkey = canonicalize_opt("Veraison key", key)?;
+        Ok(())
+    }
 
+    pub fn set_reference_json(&mut self, json: Option<&str>) -> RegistryResult<()>
+    {
+        self.reference_json = canonicalize_opt("Veraison reference JSON", json)?;
         Ok(())
     }
 
-    pub fn set_reference_json(&mut self, key: Option<&str>) -> RegistryResult<()>
+    pub fn set_revocation(
+        &mut self,
+        ca_pub: Option<&str>,
+        list: Option<&str>,
+        signature: Option<&str>,
+    ) -> RegistryResult<()>
     {
-        let base = match key {
-            Some(j) => fs::canonicalize(j)
-                .or(err!("Veraizon reference JSON path \"{}\" doesn't exist", j))?,
-            None => fs::canonicalize(utils::get_crate_root())?.join(DEFAULT_RATLS_REFERENCE_JSON),
+        let (ca_pub, list, signature) = match (ca_pub, list, signature) {
+            (Some(c), Some(l), Some(s)) => (c, l, s),
+            (None, None, None) => return Ok(()),
+            _ => {
+                err!("Root-ca public key, revocation list and its signature are required together")?
+            }
         };
-        self.reference_json = base.to_string_lossy().to_string();
+
+        let ca_pub =
+            fs::canonicalize(ca_pub).or(err!("Root-ca key path \"{}\" doesn't exist", ca_pub))?;
+        let list =
+            fs::canonicalize(list).or(err!("Revocation list path \"{}\" doesn't exist", list))?;
+        let signature = fs::canonicalize(signature).or(err!(
+            "Revocation signature path \"{}\" doesn't exist",
+            signature
+        ))?;
+
+        self.ca_pub = Some(ca_pub.to_string_lossy().to_string());
+        self.revocation_list = Some(list.to_string_lossy().to_string());
+        self.revocation_signature = Some(signature.to_string_lossy().to_string());
 
         Ok(())
     }
 }
 
-///////////////////////////
-//    SINGLETON BELOW    //
-//    HERE BE DRAGONS    //
-///////////////////////////
-
-// First usage of singleton must happen before any threads are created
+/// Canonical form of an optional path, which needs to exist when given
+fn canonicalize_opt(name: &str, path: Option<&str>) -> RegistryResult<Option<String>>
+{
+    match path {
+        Some(p) => Ok(Some(
+            fs::canonicalize(p)
+                .or(err!("{} path \"{}\" doesn't exist", name, p))?
+                .to_string_lossy()
+                .to_string(),
+        )),
+        None => Ok(None),
+    }
+}
 
-type LockResult<Guard> = Result<Guard, PoisonError<Guard>>;
+impl Default for ServerConfig
+{
+    fn default() -> Self
+    {
+        ServerConfig::new()
+    }
+}
 
-#[allow(static_mut_refs)]
-impl Config
+#[cfg(test)]
+mod tests
 {
-    pub fn get() -> &'static RwLock<Config>
+    use super::{ConfigFile, Endpoint, ListenerConfig, Protocol, ServerConfig};
+    use crate::token::Claims;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn config_file_full()
     {
-        static mut CONFIG: Option<RwLock<Config>> = None;
+        let mut file = ConfigFile::parse(
+            r#"
+            root = "registry"
+            shutdown_timeout = 5
+            reload_interval = 0
+            audit_log = "audit.jsonl"
 
-        unsafe {
-            if (*std::ptr::addr_of!(CONFIG)).is_none() {
-                CONFIG.replace(RwLock::new(Config::new()));
-            }
+            [[listeners]]
+            address = "::1"
+            port = 8443
+            protocol = "ra-tls"
+
+            [[listeners]]
+            path = "ir-server.sock"
 
-            CONFIG.as_ref().unwrap()
+            [tls]
+            cert = "/etc/ir-server/server.crt"
+            key = "server.key"
+
+            [apps."com.samsung.example.app"]
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        file.resolve_paths(Path::new("/etc/ir-server"));
+
+        assert_eq!(file.root.as_deref(), Some("/etc/ir-server/registry"));
+        assert_eq!(file.shutdown_timeout, Some(5));
+        assert_eq!(file.reload_interval, Some(0));
+        assert_eq!(
+            file.audit_log.as_deref(),
+            Some("/etc/ir-server/audit.jsonl")
+        );
+        assert_eq!(file.tls.cert.as_deref(), Some("/etc/ir-server/server.crt"));
+        assert_eq!(file.tls.key.as_deref(), Some("/etc/ir-server/server.key"));
+        assert_eq!(
+            file.listeners().unwrap(),
+            vec![
+                ListenerConfig::new(
+                    Endpoint::Tcp("[::1]:8443".parse().unwrap()),
+                    Protocol::RaTls
+                ),
+                ListenerConfig::new(
+                    Endpoint::Unix(PathBuf::from("/etc/ir-server/ir-server.sock")),
+                    Protocol::NoTls
+                ),
+            ]
+        );
+        assert!(!file.apps["com.samsung.example.app"].enabled);
+    }
+
+    #[test]
+    fn config_file_unknown_field() -> Result<(), String>
+    {
+        match ConfigFile::parse("[[listeners]]\nprot = 1337\n") {
+            Err(e) if e.to_string().contains("unknown field `prot`") => Ok(()),
+            e => Err(format!(
+                "Config should've failed to parse with a specific error, returned {:?}",
+                e
+            )),
         }
     }
 
-    pub fn read() -> LockResult<RwLockReadGuard<'static, Config>>
+    #[test]
+    fn config_file_unix_listener_with_port() -> Result<(), String>
     {
-        Config::get().read()
+        let file = ConfigFile::parse("[[listeners]]\npath = \"ir.sock\"\nport = 1337\n").unwrap();
+        match file.listeners() {
+            Err(e) if e.to_string().contains("can't have an address or port") => Ok(()),
+            e => Err(format!(
+                "Listeners should've failed with a specific error, returned {:?}",
+                e
+            )),
+        }
     }
 
-    pub fn write() -> LockResult<RwLockWriteGuard<'static, Config>>
+    #[test]
+    fn config_app_allow_rules()
     {
-        Config::get().write()
+        let file = ConfigFile::parse(
+            r#"
+            [apps.tenant-a]
+            allow_subjects = ["CN=ci,O=Example"]
+
+            [[apps.tenant-a.allow]]
+            realm_initial_measurement = "AA01"
+
+            [[apps.tenant-a.allow]]
+            realm_personalization_value = "0b"
+            platform_instance_id = "0c"
+
+            [apps.public]
+            enabled = true
+            "#,
+        )
+        .unwrap();
+        let mut config = ServerConfig::new();
+        config.apps = file.apps;
+
+        let claims = |rim: &str, rpv: &str| Claims {
+            realm_initial_measurement: rim.to_string(),
+            realm_extensible_measurements: vec!["00".to_string(); 4],
+            realm_personalization_value: rpv.to_string(),
+            realm_hash_algorithm: "sha-256".to_string(),
+            platform_profile: "http://arm.com/CCA-SSD/1.0.0".to_string(),
+            platform_implementation_id: "0d".to_string(),
+            platform_instance_id: "0c".to_string(),
+        };
+
+        assert!(config.is_app_allowed("tenant-a", Some(&claims("aa01", "00")), None));
+        assert!(config.is_app_allowed("tenant-a", Some(&claims("ff", "0b")), None));
+        assert!(!config.is_app_allowed("tenant-a", Some(&claims("ff", "00")), None));
+        assert!(!config.is_app_allowed("tenant-a", None, None));
+        assert!(config.is_app_allowed("tenant-a", None, Some("CN=ci,O=Example")));
+        assert!(!config.is_app_allowed("tenant-a", None, Some("CN=other,O=Example")));
+        assert!(config.is_app_allowed("public", None, None));
+        assert!(config.is_app_allowed("unknown", None, None));
     }
 
-    pub fn readu() -> RwLockReadGuard<'static, Config>
+    #[test]
+    fn config_app_empty_allow_rule()
     {
-        Config::read().unwrap()
+        let file = ConfigFile::parse(
+            r#"
+            [apps.tenant-a]
+            [[apps.tenant-a.allow]]
+            realm_initial_measurement = "AA01"
+            [[apps.tenant-a.allow]]
+            "#,
+        )
+        .unwrap();
+        let mut config = ServerConfig::new();
+        config.apps = file.apps;
+        assert!(config.validate().is_err());
+
+        config.apps.get_mut("tenant-a").unwrap().allow.pop();
+        assert!(config.validate().is_ok());
     }
 
-    pub fn writeu() -> RwLockWriteGuard<'static, Config>
+    #[test]
+    fn config_unset_paths()
     {
-        Config::write().unwrap()
+        // no-tls needs none of the paths
+        let mut config = ServerConfig::new();
+        config.set_server_cert(None).unwrap();
+        config.set_veraison_key(None).unwrap();
+        assert_eq!(config.cert, None);
+        assert_eq!(config.veraison_pubkey, None);
+        assert!(config.validate().is_ok());
+
+        config.listeners = vec![ListenerConfig::new(
+            Endpoint::Tcp("0.0.0.0:1337".parse().unwrap()),
+            Protocol::Tls,
+        )];
+        assert!(config.validate().is_err());
+
+        config.set_server_cert(Some("certs/server.crt")).unwrap();
+        config.set_server_key(Some("certs/server.key")).unwrap();
+        assert!(config.validate().is_ok());
+
+        // RA-TLS needs the reference values on top of that
+        config.listeners[0].protocol = Protocol::RaTls;
+        assert!(config.validate().is_err());
+
+        assert!(config.set_server_key(Some("certs/missing.key")).is_err());
     }
 }
@@ -0,0 +1,276 @@
+use log::{info, warn};
+use ratls::{ChainVerifier, InternalTokenVerifier};
+use realm_verifier::{parser_json::parse_value, RealmVerifier};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use std::{fs::File, io::BufReader};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_rustls::rustls::crypto::ring::default_provider;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_util::sync::CancellationToken;
+#[cfg(not(feature = "disable-challenge-veraison"))]
+use veraison_verifier::VeraisonTokenVerifer;
+
+use crate::config::{Protocol, ServerConfig};
+use crate::metrics::{MeasuredVerifier, Metrics};
+use crate::error::RegistryError;
+use crate::utils;
+use crate::RegistryResult;
+
+/// Server certificate and RA-TLS client token verifier shared by the TLS
+/// listeners of a server. Both are reloaded when their files change or on
+/// SIGHUP, the connections already established keep the ones of their
+/// handshake.
+#[derive(Debug)]
+pub struct TlsCredentials
+{
+    config: Arc<ServerConfig>,
+    metrics: Arc<Metrics>,
+    cert: Option<RwLock<Arc<CertifiedKey>>>,
+    token_verifier: Option<RwLock<Arc<dyn InternalTokenVerifier>>>,
+    // modification times of the files as of the last (re)load
+    modified: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl TlsCredentials
+{
+    /// Load what the given listener protocols need, nothing for plain ones
+    pub fn load(
+        config: Arc<ServerConfig>,
+        metrics: Arc<Metrics>,
+        protocols: &[Protocol],
+    ) -> RegistryResult<Self>
+    {
+        let cert = match protocols.iter().any(|p| *p != Protocol::NoTls) {
+            true => Some(RwLock::new(load_cert(&config)?)),
+            false => None,
+        };
+        let token_verifier = match protocols.contains(&Protocol::RaTls) {
+            true => Some(RwLock::new(load_token_verifier(&config, &metrics)?)),
+            false => None,
+        };
+
+        let credentials = TlsCredentials {
+            config,
+            metrics,
+            cert,
+            token_verifier,
+            modified: Mutex::new(Vec::new()),
+        };
+        *credentials.modified.lock().unwrap() = credentials.modified_times();
+
+        Ok(credentials)
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.cert.is_none() && self.token_verifier.is_none()
+    }
+
+    /// Verifier for the next RA-TLS handshake, none if RA-TLS isn't used
+    pub fn token_verifier(&self) -> Option<Arc<dyn InternalTokenVerifier>>
+    {
+        self.token_verifier
+            .as_ref()
+            .map(|verifier| verifier.read().unwrap().clone())
+    }
+
+    // files the loaded credentials come from
+    fn files(&self) -> Vec<&str>
+    {
+        let mut files = Vec::new();
+        if self.cert.is_some() {
+            files.extend([&self.config.cert, &self.config.key]);
+        }
+        if self.token_verifier.is_some() {
+            files.push(&self.config.reference_json);
+            #[cfg(not(feature = "disable-challenge-veraison"))]
+            files.push(&self.config.veraison_pubkey);
+        }
+        files.into_iter().flatten().map(|f| f.as_str()).collect()
+    }
+
+    fn modified_times(&self) -> Vec<Option<SystemTime>>
+    {
+        self.files()
+            .iter()
+            .map(|file| std::fs::metadata(file).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    fn changed(&self) -> bool
+    {
+        *self.modified.lock().unwrap() != self.modified_times()
+    }
+
+    /// Load everything again, what fails to load (e.g. a certificate whose key
+    /// hasn't been written yet) is kept as it was
+    pub fn reload(&self)
+    {
+        // taken before loading so a write in the meantime is seen next time
+        *self.modified.lock().unwrap() = self.modified_times();
+
+        if let Some(cert) = &self.cert {
+            match load_cert(&self.config) {
+                Ok(loaded) => {
+                    *cert.write().unwrap() = loaded;
+                    info!(
+                        "Server certificate reloaded from \"{}\"",
+                        self.config.cert.as_deref().unwrap_or_default()
+                    );
+                }
+                Err(e) => warn!("Keeping the current server certificate: {}", e),
+            }
+        }
+
+        if let Some(verifier) = &self.token_verifier {
+            match load_token_verifier(&self.config, &self.metrics) {
+                Ok(loaded) => {
+                    *verifier.write().unwrap() = loaded;
+                    info!(
+                        "RA-TLS reference values reloaded from \"{}\"",
+                        self.config.reference_json.as_deref().unwrap_or_default()
+                    );
+                }
+                Err(e) => warn!("Keeping the current RA-TLS reference values: {}", e),
+            }
+        }
+    }
+
+    /// Reload on SIGHUP and when the files change, checked every `interval`
+    /// unless it's zero, until the shutdown
+    pub async fn watch(
+        self: Arc<Self>,
+        interval: Duration,
+        shutdown: CancellationToken,
+    ) -> RegistryResult<()>
+    {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let mut ticks = (!interval.is_zero()).then(|| tokio::time::interval(interval));
+
+        loop {
+            let tick = async {
+                match &mut ticks {
+                    Some(ticks) => ticks.tick().await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                _ = shutdown.cancelled() => return Ok(()),
+                _ = hangup.recv() => {
+                    info!("SIGHUP received, reloading TLS credentials");
+                    self.reload();
+                }
+                _ = tick => {
+                    if self.changed() {
+                        info!("TLS credential files changed, reloading");
+                        self.reload();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for TlsCredentials
+{
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>>
+    {
+        self.cert.as_ref().map(|cert| cert.read().unwrap().clone())
+    }
+}
+
+// ServerConfig::validate() makes sure the listeners have what they need
+fn required<'a>(name: &str, path: &'a Option<String>) -> RegistryResult<&'a str>
+{
+    path.as_deref()
+        .ok_or_else(|| RegistryError::Config(format!("{} not set", name)))
+}
+
+fn load_cert(config: &ServerConfig) -> RegistryResult<Arc<CertifiedKey>>
+{
+    // fails on a key not matching the certificate, e.g. in the middle of a renewal
+    Ok(Arc::new(CertifiedKey::from_der(
+        utils::load_certificates_from_pem(required("Server cert", &config.cert)?)?,
+        utils::load_private_key_from_file(required("Server key", &config.key)?)?,
+        &default_provider(),
+    )?))
+}
+
+fn load_token_verifier(
+    config: &ServerConfig,
+    metrics: &Arc<Metrics>,
+) -> RegistryResult<Arc<dyn InternalTokenVerifier>>
+{
+    let reference_json = required("Veraison reference JSON", &config.reference_json)?;
+    let json_reader = BufReader::new(File::open(reference_json)?);
+    let mut reference_json: serde_json::Value = serde_json::from_reader(json_reader)?;
+    let reference_measurements = parse_value(reference_json["realm"]["reference-values"].take())?;
+
+    Ok(Arc::new(ChainVerifier::new(vec![
+        #[cfg(not(feature = "disable-challenge-veraison"))]
+        MeasuredVerifier::wrap(
+            "veraison",
+            Arc::new(VeraisonTokenVerifer::new(
+                &config.veraison_url,
+                std::fs::read_to_string(required("Veraison key", &config.veraison_pubkey)?)?,
+                None,
+            )?),
+            metrics.clone(),
+        ),
+        MeasuredVerifier::wrap(
+            "realm",
+            Arc::new(RealmVerifier::init(reference_measurements)),
+            metrics.clone(),
+        ),
+    ])))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::TlsCredentials;
+    use crate::config::{Protocol, ServerConfig};
+    use crate::metrics::Metrics;
+    use std::sync::Arc;
+
+    #[test]
+    fn credentials_reload()
+    {
+        let dir =
+            std::env::temp_dir().join(format!("ir-server-credentials-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = ServerConfig::new();
+        let cert = dir.join("server.crt");
+        let key = dir.join("server.key");
+        std::fs::copy("certs/server.crt", &cert).unwrap();
+        std::fs::copy("certs/server.key", &key).unwrap();
+        config.cert = Some(cert.to_string_lossy().to_string());
+        config.key = Some(key.to_string_lossy().to_string());
+
+        let credentials = TlsCredentials::load(
+            Arc::new(config.clone()),
+            Arc::new(Metrics::new()),
+            &[Protocol::Tls],
+        )
+        .unwrap();
+        assert!(credentials.token_verifier().is_none());
+        let current = || credentials.cert.as_ref().unwrap().read().unwrap().clone();
+        let loaded = current();
+
+        // a broken certificate doesn't replace the working one
+        std::fs::write(&cert, "-----BEGIN CERTIFICATE-----\n").unwrap();
+        credentials.reload();
+        assert!(Arc::ptr_eq(&loaded, &current()));
+
+        std::fs::copy("certs/server.crt", &cert).unwrap();
+        credentials.reload();
+        assert!(!Arc::ptr_eq(&loaded, &current()));
+        assert_eq!(loaded.cert, current().cert);
+        assert!(!credentials.changed());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
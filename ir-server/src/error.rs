@@ -8,6 +8,7 @@ pub enum RegistryError
     VeraisonToken(veraison_verifier::VeraisonTokenVeriferError),
     PrivateKeyParsing(String),
     Config(String),
+    Signature(String),
     OciRegistry(String),
     Generic(String),
 }
@@ -26,6 +27,7 @@ impl std::fmt::Display for RegistryError
             RegistryError::VeraisonToken(e) => write!(f, "VeraisonToken({:?})", e),
             RegistryError::PrivateKeyParsing(s) => write!(f, "PrivateKeyParsingError({})", s),
             RegistryError::Config(s) => write!(f, "ConfigError({})", s),
+            RegistryError::Signature(s) => write!(f, "SignatureError({})", s),
             RegistryError::OciRegistry(s) => write!(f, "OciRegistryError({})", s),
             RegistryError::Generic(s) => write!(f, "GenericError({})", s),
         }
@@ -1,18 +1,28 @@
-use axum::{body::Body, extract, http, response::IntoResponse, routing, Json, Router};
+use axum::{body::Body, extract, http, middleware, response::IntoResponse, routing, Json, Router};
 use axum_extra::{headers::Range, TypedHeader};
+use futures_util::{pin_mut, StreamExt};
 use hyper::Response;
-use log::{debug, error, info};
+use log::{error, info, warn};
 use serde::Deserialize;
 use serde_json::json;
-use std::{ops::Bound, sync::Arc};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{future::Future, ops::Bound, sync::Arc};
+use tokio::signal::{self, unix::SignalKind};
+use tokio::task::{JoinHandle, JoinSet};
 use tokio::{io::AsyncSeekExt, sync::RwLock};
-use tokio_util::io::ReaderStream;
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
 
-use crate::config::{Config, Protocol};
+use crate::access;
+use crate::audit::{self, AuditLog};
+use crate::auth::{self, Authenticator};
+use crate::config::{Endpoint, Protocol, ServerConfig};
+use crate::credentials::TlsCredentials;
+use crate::error::RegistryError;
+use crate::listener::BoundListener;
+use crate::metrics::{self, Metrics};
 use crate::registry::{ImageRegistry, Payload};
-use crate::tls;
 use crate::RegistryResult;
 
 static NOT_FOUND: (http::StatusCode, &str) = (
@@ -23,12 +33,327 @@ static NOT_FOUND: (http::StatusCode, &str) = (
 const HEADER_DIGEST: http::HeaderName = http::HeaderName::from_static("docker-content-digest");
 
 type SafeReg = Arc<RwLock<dyn ImageRegistry>>;
+type SafeConfig = Arc<ServerConfig>;
+type SafeMetrics = Arc<Metrics>;
 
-pub async fn run<T: ImageRegistry + 'static>(reg: T) -> RegistryResult<()>
+/// State shared by the handlers of a single server instance, each handler
+/// extracts the part it needs
+#[derive(Clone)]
+struct AppState
 {
-    let reg = Arc::new(RwLock::new(reg));
+    reg: SafeReg,
+    config: SafeConfig,
+    metrics: SafeMetrics,
+}
+
+impl extract::FromRef<AppState> for SafeReg
+{
+    fn from_ref(state: &AppState) -> Self
+    {
+        state.reg.clone()
+    }
+}
+
+impl extract::FromRef<AppState> for SafeConfig
+{
+    fn from_ref(state: &AppState) -> Self
+    {
+        state.config.clone()
+    }
+}
+
+impl extract::FromRef<AppState> for SafeMetrics
+{
+    fn from_ref(state: &AppState) -> Self
+    {
+        state.metrics.clone()
+    }
+}
+
+/// Builds a server from a registry and its settings, by default it binds the
+/// listeners from the config
+pub struct ServerBuilder
+{
+    reg: SafeReg,
+    config: ServerConfig,
+    listeners: Vec<(BoundListener, Protocol)>,
+}
+
+impl ServerBuilder
+{
+    pub fn new<T: ImageRegistry + 'static>(reg: T) -> Self
+    {
+        ServerBuilder {
+            reg: Arc::new(RwLock::new(reg)),
+            config: ServerConfig::new(),
+            listeners: Vec::new(),
+        }
+    }
+
+    pub fn config(mut self, config: ServerConfig) -> Self
+    {
+        self.config = config;
+        self
+    }
+
+    /// Serve on an already bound TCP or Unix listener (e.g. an ephemeral port),
+    /// can be given multiple times. The listeners from the config are ignored
+    /// then.
+    pub fn listener<L: Into<BoundListener>>(mut self, listener: L, protocol: Protocol) -> Self
+    {
+        self.listeners.push((listener.into(), protocol));
+        self
+    }
+
+    /// Only the OCI distribution routes with their state, to be merged into
+    /// another application. Listening and TLS are up to the caller.
+    pub fn router(self) -> RegistryResult<Router>
+    {
+        let audit = open_audit_log(&self.config)?;
+        let auth = new_authenticator(&self.config)?;
+        Ok(router(
+            self.reg,
+            Arc::new(self.config),
+            Arc::new(Metrics::new()),
+            audit,
+            auth,
+        ))
+    }
+
+    pub async fn build(self) -> RegistryResult<Server>
+    {
+        let mut listeners = self.listeners;
+        if listeners.is_empty() {
+            for listener in &self.config.listeners {
+                let bound = BoundListener::bind(&listener.endpoint).await?;
+                listeners.push((bound, listener.protocol.clone()));
+            }
+        }
+
+        if listeners.is_empty() {
+            return Err(RegistryError::Config("No listeners configured".to_string()));
+        }
+
+        let audit = open_audit_log(&self.config)?;
+        let auth = new_authenticator(&self.config)?;
+        let config = Arc::new(self.config);
+        let metrics = Arc::new(Metrics::new());
+        let protocols: Vec<_> = listeners.iter().map(|(_, p)| p.clone()).collect();
+        let credentials = Arc::new(TlsCredentials::load(
+            config.clone(),
+            metrics.clone(),
+            &protocols,
+        )?);
+        Ok(Server {
+            app: router(self.reg, config.clone(), metrics.clone(), audit, auth),
+            config,
+            metrics,
+            credentials,
+            listeners,
+            shutdown: CancellationToken::new(),
+        })
+    }
+}
+
+/// Server bound to its listeners, ready to run
+pub struct Server
+{
+    app: Router,
+    config: SafeConfig,
+    metrics: SafeMetrics,
+    credentials: Arc<TlsCredentials>,
+    listeners: Vec<(BoundListener, Protocol)>,
+    shutdown: CancellationToken,
+}
+
+impl Server
+{
+    pub fn builder<T: ImageRegistry + 'static>(reg: T) -> ServerBuilder
+    {
+        ServerBuilder::new(reg)
+    }
+
+    pub fn local_addrs(&self) -> RegistryResult<Vec<Endpoint>>
+    {
+        self.listeners
+            .iter()
+            .map(|(listener, _)| listener.local_endpoint())
+            .collect()
+    }
+
+    /// Serve until an error or until stopped through a `ServerHandle`. On stop
+    /// new connections are refused and the ones in flight are given
+    /// `shutdown_timeout` from the config to finish.
+    pub async fn run(self) -> RegistryResult<()>
+    {
+        let shutdown = self.shutdown.clone();
+        let timeout = self.config.shutdown_timeout;
+        let serve = self.serve();
+        pin_mut!(serve);
+
+        tokio::select! {
+            ret = serve.as_mut() => return ret,
+            _ = shutdown.cancelled() => {
+                info!("Shutting down, waiting up to {:?} for connections in flight", timeout);
+            }
+        }
+
+        match tokio::time::timeout(timeout, serve).await {
+            Ok(ret) => ret,
+            Err(_) => {
+                warn!(
+                    "Connections still in flight after {:?}, closing them",
+                    timeout
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Serve until `signal` completes, then shut down as with `ServerHandle`
+    pub async fn run_until<F: Future<Output = ()>>(self, signal: F) -> RegistryResult<()>
+    {
+        let shutdown = self.shutdown.clone();
+        let run = self.run();
+        pin_mut!(run);
+
+        tokio::select! {
+            ret = run.as_mut() => return ret,
+            _ = signal => shutdown.cancel(),
+        }
+
+        run.await
+    }
+
+    async fn serve(self) -> RegistryResult<()>
+    {
+        // dropping the set (e.g. on shutdown timeout) aborts the listeners
+        let mut listeners = JoinSet::new();
+        if !self.credentials.is_empty() {
+            listeners.spawn(
+                self.credentials
+                    .clone()
+                    .watch(self.config.reload_interval, self.shutdown.clone()),
+            );
+        }
+        for (listener, protocol) in self.listeners {
+            info!(
+                "Listening on {} ({:?})",
+                listener.local_endpoint()?,
+                protocol
+            );
+            let app = self.app.clone();
+            let config = self.config.clone();
+            let credentials = self.credentials.clone();
+            let metrics = self.metrics.clone();
+            let shutdown = self.shutdown.clone();
+            listeners.spawn(async move {
+                listener
+                    .serve(protocol, app, &config, credentials, metrics, shutdown)
+                    .await
+            });
+        }
+
+        // one listener failing brings the others down too
+        let mut ret = Ok(());
+        while let Some(joined) = listeners.join_next().await {
+            let listener_ret = match joined {
+                Ok(listener_ret) => listener_ret,
+                Err(e) => Err(RegistryError::Generic(format!(
+                    "Listener task failed: {}",
+                    e
+                ))),
+            };
+
+            if let Err(e) = listener_ret {
+                error!("{}", e);
+                self.shutdown.cancel();
+                ret = ret.and(Err(e));
+            }
+        }
+
+        ret
+    }
+
+    /// Run in the background, the handle is used to stop the server
+    pub fn spawn(self) -> RegistryResult<ServerHandle>
+    {
+        let addresses = self.local_addrs()?;
+        let shutdown = self.shutdown.clone();
+        let task = tokio::spawn(self.run());
+
+        Ok(ServerHandle {
+            addresses,
+            shutdown,
+            task,
+        })
+    }
+}
 
-    let app = Router::new()
+pub struct ServerHandle
+{
+    addresses: Vec<Endpoint>,
+    shutdown: CancellationToken,
+    task: JoinHandle<RegistryResult<()>>,
+}
+
+impl ServerHandle
+{
+    pub fn local_addrs(&self) -> &[Endpoint]
+    {
+        &self.addresses
+    }
+
+    /// Stop accepting new connections, `join()` to wait for the server to end
+    pub fn shutdown(&self)
+    {
+        self.shutdown.cancel();
+    }
+
+    pub async fn join(self) -> RegistryResult<()>
+    {
+        match self.task.await {
+            Ok(ret) => ret,
+            Err(e) => Err(RegistryError::Generic(format!("Server task failed: {}", e))),
+        }
+    }
+}
+
+fn open_audit_log(config: &ServerConfig) -> RegistryResult<Option<Arc<AuditLog>>>
+{
+    match &config.audit_log {
+        Some(target) => {
+            info!("Audit log: {}", target);
+            Ok(Some(Arc::new(AuditLog::open(target)?)))
+        }
+        None => Ok(None),
+    }
+}
+
+fn new_authenticator(config: &ServerConfig) -> RegistryResult<Option<Arc<Authenticator>>>
+{
+    match &config.auth {
+        Some(auth) => Ok(Some(Arc::new(Authenticator::new(auth)?))),
+        None => Ok(None),
+    }
+}
+
+fn router(
+    reg: SafeReg,
+    config: SafeConfig,
+    metrics: SafeMetrics,
+    audit: Option<Arc<AuditLog>>,
+    auth: Option<Arc<Authenticator>>,
+) -> Router
+{
+    let state = AppState {
+        reg,
+        config: config.clone(),
+        metrics: metrics.clone(),
+    };
+
+    let mut app = Router::new()
+        .route("/metrics", routing::get(get_metrics))
         .route("/v2/", routing::get(get_support))
         .route("/v2/{name}/tags/list", routing::get(get_tags))
         .route(
@@ -39,22 +364,56 @@ pub async fn run<T: ImageRegistry + 'static>(reg: T) -> RegistryResult<()>
             "/v2/{name}/blobs/{digest}",
             routing::get(get_blob).head(head_blob),
         )
-        .with_state(reg)
-        .fallback(fallback)
-        .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()));
+        .with_state(state)
+        .fallback(fallback);
+
+    if let Some(auth) = auth {
+        app = app
+            .route(
+                "/token",
+                routing::get(auth::get_token).with_state(auth.clone()),
+            )
+            .layer(middleware::from_fn_with_state(auth, auth::authenticate));
+    }
+
+    let app = app
+        .layer(middleware::from_fn_with_state(config, access::authorize))
+        .layer(
+            ServiceBuilder::new()
+                .layer(TraceLayer::new_for_http())
+                .layer(middleware::from_fn_with_state(
+                    metrics,
+                    metrics::track_requests,
+                )),
+        );
+
+    match audit {
+        Some(audit) => app.layer(middleware::from_fn_with_state(audit, audit::record)),
+        None => app,
+    }
+}
 
-    let address = format!("0.0.0.0:{}", Config::readu().port);
-    debug!("Binding address: {}", address);
-    let listener = tokio::net::TcpListener::bind(address).await?;
+/// Completes on SIGINT or SIGTERM
+pub async fn shutdown_signal()
+{
+    let mut terminate =
+        signal::unix::signal(SignalKind::terminate()).expect("Could not install SIGTERM handler");
 
-    let tls = { Config::readu().tls.clone() };
-    match tls {
-        Protocol::NoTls => axum::serve(listener, app).await?,
-        Protocol::Tls => tls::serve_tls(listener, app).await?,
-        Protocol::RaTls => tls::serve_ratls(listener, app).await?,
+    tokio::select! {
+        _ = signal::ctrl_c() => info!("SIGINT received"),
+        _ = terminate.recv() => info!("SIGTERM received"),
     }
+}
 
-    Ok(())
+/// Bind the listeners from the config and serve until SIGINT or SIGTERM
+pub async fn run<T: ImageRegistry + 'static>(reg: T, config: ServerConfig) -> RegistryResult<()>
+{
+    Server::builder(reg)
+        .config(config)
+        .build()
+        .await?
+        .run_until(shutdown_signal())
+        .await
 }
 
 async fn fallback() -> (http::StatusCode, &'static str)
@@ -72,7 +431,22 @@ fn range_not_acceptable(payload: Payload) -> Response<Body>
     (http::StatusCode::NOT_ACCEPTABLE, headers, body).into_response()
 }
 
-async fn serve_file(mut payload: Payload, range: Option<TypedHeader<Range>>) -> Response<Body>
+// count the bytes as they are sent, not the ones promised in the headers
+fn counted_body(file: tokio::fs::File, served: Arc<AtomicU64>) -> Body
+{
+    let stream = ReaderStream::new(file).inspect(move |chunk| {
+        if let Ok(chunk) = chunk {
+            served.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+    });
+    Body::from_stream(stream)
+}
+
+async fn serve_file(
+    mut payload: Payload,
+    range: Option<TypedHeader<Range>>,
+    served: Arc<AtomicU64>,
+) -> Response<Body>
 {
     let Some(TypedHeader(range)) = range else {
         let headers = [
@@ -80,7 +454,7 @@ async fn serve_file(mut payload: Payload, range: Option<TypedHeader<Range>>) ->
             (http::header::CONTENT_LENGTH, &format!("{}", payload.size)),
             (HEADER_DIGEST, &payload.digest),
         ];
-        let body = Body::from_stream(ReaderStream::new(payload.file));
+        let body = counted_body(payload.file, served);
         return (headers, body).into_response();
     };
 
@@ -98,7 +472,7 @@ async fn serve_file(mut payload: Payload, range: Option<TypedHeader<Range>>) ->
                 return range_not_acceptable(payload);
             };
 
-            let body = Body::from_stream(ReaderStream::new(payload.file));
+            let body = counted_body(payload.file, served);
             let headers = [
                 (http::header::CONTENT_TYPE, &payload.media_type),
                 (
@@ -121,6 +495,22 @@ async fn serve_file(mut payload: Payload, range: Option<TypedHeader<Range>>) ->
     }
 }
 
+async fn get_metrics(
+    extract::State(reg): extract::State<SafeReg>,
+    extract::State(config): extract::State<SafeConfig>,
+    extract::State(metrics): extract::State<SafeMetrics>,
+) -> impl IntoResponse
+{
+    let load = reg.read().await.load_stats();
+    let headers = [(
+        http::header::CONTENT_TYPE,
+        "text/plain; version=0.0.4; charset=utf-8",
+    )];
+    // metrics are served to anyone, restricted apps must not show up there
+    let is_restricted = |app: &str| config.apps.get(app).is_some_and(|p| p.is_restricted());
+    (headers, metrics.render(load.as_ref(), is_restricted)).into_response()
+}
+
 async fn get_support() -> impl IntoResponse
 {
     (http::StatusCode::OK, "OCI Distribution Spec V2 supported").into_response()
@@ -171,6 +561,7 @@ async fn get_tags(
 
 async fn get_manifest(
     extract::State(reg): extract::State<SafeReg>,
+    extract::State(metrics): extract::State<SafeMetrics>,
     extract::Path((name, reference)): extract::Path<(String, String)>,
     range: Option<TypedHeader<Range>>,
 ) -> impl IntoResponse
@@ -186,7 +577,7 @@ async fn get_manifest(
         "Manifest \"{}\" for \"{}\" found and served",
         reference, name
     );
-    serve_file(payload, range).await
+    serve_file(payload, range, metrics.served_bytes(&name)).await
 }
 
 async fn head_manifest(
@@ -208,6 +599,7 @@ async fn head_manifest(
 
 async fn get_blob(
     extract::State(reg): extract::State<SafeReg>,
+    extract::State(metrics): extract::State<SafeMetrics>,
     extract::Path((name, digest)): extract::Path<(String, String)>,
     range: Option<TypedHeader<Range>>,
 ) -> impl IntoResponse
@@ -220,7 +612,7 @@ async fn get_blob(
     };
 
     info!("Blob \"{}\" for \"{}\" found and served", digest, name);
-    serve_file(payload, range).await
+    serve_file(payload, range, metrics.served_bytes(&name)).await
 }
 
 async fn head_blob(
@@ -239,3 +631,92 @@ async fn head_blob(
     info!("{}", msg);
     msg.into_response()
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use async_trait::async_trait;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+
+    struct EmptyRegistry;
+
+    #[async_trait]
+    impl ImageRegistry for EmptyRegistry
+    {
+        fn get_tags(&self, _app: &str) -> Option<Vec<String>>
+        {
+            None
+        }
+
+        async fn get_manifest(&self, _app: &str, _reference: &str) -> Option<Payload>
+        {
+            None
+        }
+
+        async fn get_blob(&self, _app: &str, _digest: &str) -> Option<Payload>
+        {
+            None
+        }
+    }
+
+    async fn request<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, path: &str) -> String
+    {
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    async fn get(endpoint: &Endpoint, path: &str) -> String
+    {
+        match endpoint {
+            Endpoint::Tcp(address) => {
+                request(TcpStream::connect(address).await.unwrap(), path).await
+            }
+            Endpoint::Unix(path_name) => {
+                request(UnixStream::connect(path_name).await.unwrap(), path).await
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn server_tcp_and_unix_listeners()
+    {
+        let socket =
+            std::env::temp_dir().join(format!("ir-server-test-{}.sock", std::process::id()));
+        let server = Server::builder(EmptyRegistry)
+            .listener(
+                TcpListener::bind("127.0.0.1:0").await.unwrap(),
+                Protocol::NoTls,
+            )
+            .listener(UnixListener::bind(&socket).unwrap(), Protocol::NoTls)
+            .build()
+            .await
+            .unwrap();
+        let handle = server.spawn().unwrap();
+        assert_eq!(handle.local_addrs()[1], Endpoint::Unix(socket.clone()));
+
+        for endpoint in handle.local_addrs() {
+            let response = get(endpoint, "/v2/").await;
+            assert!(response.starts_with("HTTP/1.1 200"));
+            let response = get(endpoint, "/v2/app/tags/list").await;
+            assert!(response.starts_with("HTTP/1.1 404"));
+        }
+
+        // both listeners share the metrics
+        let response = get(&handle.local_addrs()[0], "/metrics").await;
+        assert!(response
+            .contains("ir_http_requests_total{method=\"GET\",route=\"/v2/\",status=\"200\"} 2\n"));
+
+        handle.shutdown();
+        assert!(handle.join().await.is_ok());
+        assert!(!socket.exists());
+    }
+}
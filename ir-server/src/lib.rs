@@ -1,14 +1,38 @@
+mod access;
+mod audit;
+mod auth;
 mod config;
+mod credentials;
 mod error;
 mod httpd;
+mod listener;
+mod metrics;
 mod oci;
+mod peer;
 mod registry;
+mod revocation;
 mod tls;
+mod token;
 mod utils;
+mod x509;
 
 pub type RegistryResult<T> = Result<T, error::RegistryError>;
 
-pub use httpd::run as httpd_run;
-pub use config::Config;
 pub use config::Protocol as ConfigProtocol;
+pub use config::DEFAULT_VERAISON_URL;
+pub use config::{
+    AppPolicy, AuthConfig, ClaimsRule, ConfigFile, Endpoint, ListenerConfig, ServerConfig,
+};
+pub use config::{
+    AUDIT_LOG_STDOUT, CONFIG_FILE_ENV, DEFAULT_ADDRESS, DEFAULT_PORT, DEFAULT_RELOAD_INTERVAL,
+    DEFAULT_SHUTDOWN_TIMEOUT,
+};
+pub use error::RegistryError;
+pub use httpd::run as httpd_run;
+pub use httpd::{shutdown_signal, Server, ServerBuilder, ServerHandle};
+pub use listener::BoundListener;
 pub use oci::Registry as OciRegistry;
+pub use peer::Peer;
+pub use registry::{ImageRegistry, LoadStats, Payload};
+pub use revocation::RevocationList;
+pub use token::Claims;
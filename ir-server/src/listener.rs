@@ -0,0 +1,157 @@
+use axum::extract::connect_info::Connected;
+use axum::{serve::IncomingStream, Router};
+use log::{debug, warn};
+use std::{fmt::Debug, fs, os::unix::fs::FileTypeExt, path::Path, sync::Arc};
+use tokio::net::{TcpListener, UnixListener};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{Endpoint, Protocol, ServerConfig};
+use crate::credentials::TlsCredentials;
+use crate::error::RegistryError;
+use crate::metrics::Metrics;
+use crate::peer::{Peer, PeerAddress};
+use crate::tls;
+use crate::RegistryResult;
+
+/// A bound listener of any of the supported kinds
+#[derive(Debug)]
+pub enum BoundListener
+{
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl From<TcpListener> for BoundListener
+{
+    fn from(listener: TcpListener) -> Self
+    {
+        BoundListener::Tcp(listener)
+    }
+}
+
+impl From<UnixListener> for BoundListener
+{
+    fn from(listener: UnixListener) -> Self
+    {
+        BoundListener::Unix(listener)
+    }
+}
+
+impl BoundListener
+{
+    pub async fn bind(endpoint: &Endpoint) -> RegistryResult<Self>
+    {
+        debug!("Binding address: {}", endpoint);
+
+        match endpoint {
+            Endpoint::Tcp(address) => Ok(TcpListener::bind(address).await?.into()),
+            Endpoint::Unix(path) => {
+                remove_stale_socket(path)?;
+                Ok(UnixListener::bind(path)?.into())
+            }
+        }
+    }
+
+    pub fn local_endpoint(&self) -> RegistryResult<Endpoint>
+    {
+        match self {
+            BoundListener::Tcp(listener) => Ok(Endpoint::Tcp(listener.local_addr()?)),
+            BoundListener::Unix(listener) => match listener.local_addr()?.as_pathname() {
+                Some(path) => Ok(Endpoint::Unix(path.to_path_buf())),
+                None => Err(RegistryError::Config(
+                    "Unnamed Unix sockets are not supported".to_string(),
+                )),
+            },
+        }
+    }
+
+    /// Serve until the shutdown token is cancelled and the connections in
+    /// flight are finished
+    pub async fn serve(
+        self,
+        protocol: Protocol,
+        app: Router,
+        config: &ServerConfig,
+        credentials: Arc<TlsCredentials>,
+        metrics: Arc<Metrics>,
+        shutdown: CancellationToken,
+    ) -> RegistryResult<()>
+    {
+        match self {
+            BoundListener::Tcp(listener) => {
+                serve(
+                    listener,
+                    protocol,
+                    app,
+                    config,
+                    credentials,
+                    metrics,
+                    shutdown,
+                )
+                .await
+            }
+            BoundListener::Unix(listener) => {
+                let path = listener.local_addr()?.as_pathname().map(Path::to_path_buf);
+                let ret = serve(
+                    listener,
+                    protocol,
+                    app,
+                    config,
+                    credentials,
+                    metrics,
+                    shutdown,
+                )
+                .await;
+                if let Some(path) = path {
+                    if let Err(e) = fs::remove_file(&path) {
+                        warn!("Can't remove socket \"{}\": {}", path.display(), e);
+                    }
+                }
+                ret
+            }
+        }
+    }
+}
+
+// A socket left by a server that didn't exit cleanly would make the bind fail,
+// anything else than a socket is not ours to remove though
+fn remove_stale_socket(path: &Path) -> RegistryResult<()>
+{
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            debug!("Removing stale socket: \"{}\"", path.display());
+            Ok(fs::remove_file(path)?)
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn serve<L>(
+    listener: L,
+    protocol: Protocol,
+    app: Router,
+    config: &ServerConfig,
+    credentials: Arc<TlsCredentials>,
+    metrics: Arc<Metrics>,
+    shutdown: CancellationToken,
+) -> RegistryResult<()>
+where
+    L: axum::serve::Listener,
+    L::Addr: PeerAddress + Debug,
+    Peer: for<'a> Connected<IncomingStream<'a, L>>,
+{
+    match protocol {
+        Protocol::NoTls => {
+            axum::serve(listener, app.into_make_service_with_connect_info::<Peer>())
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await?
+        }
+        Protocol::Tls => tls::serve_tls(listener, app, credentials, metrics, shutdown).await?,
+        Protocol::Mtls => {
+            tls::serve_mtls(listener, app, config, credentials, metrics, shutdown).await?
+        }
+        Protocol::RaTls => tls::serve_ratls(listener, app, credentials, metrics, shutdown).await?,
+    }
+
+    Ok(())
+}
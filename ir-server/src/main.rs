@@ -2,42 +2,82 @@ use ir_server::*;
 
 use clap::Parser;
 use log::{debug, error, info};
+use std::net::SocketAddr;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 struct Cli
 {
-    /// runtime server root directory, none to use {crate_root}/registry
+    /// TOML configuration file, command line options take precedence over it
+    #[arg(long, env = ir_server::CONFIG_FILE_ENV)]
+    config: Option<String>,
+
+    /// runtime server root directory [default: ../registry]
     #[arg(short, long)]
     root: Option<String>,
 
-    /// path to server certificate, none to use {crate_root}/certs/server.crt
+    /// path to server certificate, required by tls, mtls and ra-tls
     #[arg(short, long)]
     cert: Option<String>,
 
-    /// path to server private key, none to use {crate_root}/certs/server.key
+    /// path to server private key, required by tls, mtls and ra-tls
     #[arg(short, long)]
     key: Option<String>,
 
-    /// TLS variant to use
-    #[arg(short, long, default_value_t, value_enum)]
-    tls: ConfigProtocol,
+    /// mTLS: CA bundle in PEM format the client certificates are verified against
+    #[arg(long)]
+    client_ca: Option<String>,
+
+    /// TLS variant to use, replaces the listeners from the config file [default: no-tls]
+    #[arg(short, long, value_enum)]
+    tls: Option<ConfigProtocol>,
+
+    /// server port, replaces the listeners from the config file [default: 1337]
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// seconds given to connections in flight to finish on SIGINT/SIGTERM [default: 30]
+    #[arg(long)]
+    shutdown_timeout: Option<u64>,
+
+    /// seconds between checks for changed certificate, key and reference values,
+    /// 0 to reload them on SIGHUP only [default: 10]
+    #[arg(long)]
+    reload_interval: Option<u64>,
+
+    /// append a JSON line per manifest/blob request to this file, "-" for stdout
+    #[arg(long, value_name = "FILE|-")]
+    audit_log: Option<String>,
 
-    /// server port
-    #[arg(short, long, default_value_t = 1337)]
-    port: u16,
+    /// TOML file with the users allowed to get tokens, clients on no-tls and tls
+    /// listeners need one then
+    #[arg(long)]
+    auth_users: Option<String>,
 
-    /// RA-TLS: Veraison verification service host
-    #[arg(short = 'u', long, default_value = "https://localhost:8080")]
-    veraison_url: String,
+    /// RA-TLS: Veraison verification service host [default: https://localhost:8080]
+    #[arg(short = 'u', long)]
+    veraison_url: Option<String>,
 
-    /// RA-TLS: Veraisons public key, none to use {crate_root}/ratls/pkey.jwk
+    /// RA-TLS: Veraisons public key
     #[arg(short = 'v', long)]
     veraison_pubkey: Option<String>,
 
-    /// RA-TLS: JSON containing reference values, none to use {crate_root}/ratls/example.json
+    /// RA-TLS: JSON containing reference values
     #[arg(short = 'j', long)]
     reference_json: Option<String>,
+
+    /// Root-ca public key used to verify the revocation list
+    #[arg(long)]
+    ca_pub: Option<String>,
+
+    /// Revocation list of vendor keys, images signed with those are not served
+    #[arg(long)]
+    revocation_list: Option<String>,
+
+    /// Signature of the revocation list signed by root-ca
+    #[arg(long)]
+    revocation_signature: Option<String>,
 }
 
 #[tokio::main]
@@ -47,29 +87,90 @@ async fn main() -> ir_server::RegistryResult<()>
 
     let cli = Cli::parse();
 
-    // initialize and setup the config singleton
-    {
-        let mut config = Config::writeu();
-
-        config.set_server_root(cli.root.as_deref())?;
-        config.set_server_cert(cli.cert.as_deref())?;
-        config.set_server_key(cli.key.as_deref())?;
-        config.set_veraison_key(cli.veraison_pubkey.as_deref())?;
-        config.set_reference_json(cli.reference_json.as_deref())?;
-
-        config.veraison_url = cli.veraison_url;
-        config.port = cli.port;
-        config.tls = cli.tls;
+    let file = match &cli.config {
+        Some(path) => {
+            info!("Loading config file: \"{}\"", path);
+            ConfigFile::load(path)?
+        }
+        None => ConfigFile::default(),
+    };
+
+    let file_listeners = file.listeners()?;
+
+    // options not given on the command line are taken from the config file
+    let mut config = ServerConfig::new();
+    config.set_server_root(cli.root.or(file.root).as_deref())?;
+    config.set_server_cert(cli.cert.or(file.tls.cert).as_deref())?;
+    config.set_server_key(cli.key.or(file.tls.key).as_deref())?;
+    config.set_client_ca(cli.client_ca.or(file.tls.client_ca).as_deref())?;
+    config.set_veraison_key(
+        cli.veraison_pubkey
+            .or(file.ratls.veraison_pubkey)
+            .as_deref(),
+    )?;
+    config.set_reference_json(cli.reference_json.or(file.ratls.reference_json).as_deref())?;
+    config.set_revocation(
+        cli.ca_pub.or(file.revocation.ca_pub).as_deref(),
+        cli.revocation_list.or(file.revocation.list).as_deref(),
+        cli.revocation_signature
+            .or(file.revocation.signature)
+            .as_deref(),
+    )?;
+
+    config.veraison_url = cli
+        .veraison_url
+        .or(file.ratls.veraison_url)
+        .unwrap_or(DEFAULT_VERAISON_URL.to_string());
+    // --tls and --port replace the listeners from the config file with one
+    config.listeners = match (cli.tls, cli.port, file_listeners) {
+        (None, None, listeners) if !listeners.is_empty() => listeners,
+        (tls, port, _) => vec![ListenerConfig::new(
+            Endpoint::Tcp(SocketAddr::new(
+                DEFAULT_ADDRESS,
+                port.unwrap_or(DEFAULT_PORT),
+            )),
+            tls.unwrap_or_default(),
+        )],
+    };
+    config.shutdown_timeout = cli
+        .shutdown_timeout
+        .or(file.shutdown_timeout)
+        .map_or(DEFAULT_SHUTDOWN_TIMEOUT, Duration::from_secs);
+    config.reload_interval = cli
+        .reload_interval
+        .or(file.reload_interval)
+        .map_or(DEFAULT_RELOAD_INTERVAL, Duration::from_secs);
+    config.audit_log = cli.audit_log.or(file.audit_log);
+    config.auth = cli.auth_users.or(file.auth.users).map(|users| {
+        let mut auth = AuthConfig::new(&users);
+        if let Some(lifetime) = file.auth.token_lifetime {
+            auth.token_lifetime = Duration::from_secs(lifetime);
+        }
+        auth.realm = file.auth.realm;
+        if let Some(service) = file.auth.service {
+            auth.service = service;
+        }
+        auth
+    });
+    config.apps = file.apps;
+
+    config.validate()?;
+
+    debug!("{:#?}", config);
+
+    let mut reg = OciRegistry::import_filtered(&config.root, |app| config.is_app_enabled(app))?;
+    if let (Some(list), Some(signature), Some(ca_pub)) = (
+        &config.revocation_list,
+        &config.revocation_signature,
+        &config.ca_pub,
+    ) {
+        reg.set_revocation_list(RevocationList::load(list, signature, ca_pub)?);
     }
-
-    debug!("{:#?}", ir_server::Config::readu());
-
-    let reg = OciRegistry::import(&Config::readu().root)?;
     debug!("{:#?}", reg);
     reg.log_summary();
 
     info!("Launching the HTTP(S) server");
-    if let Result::Err(e) = httpd_run(reg).await {
+    if let Result::Err(e) = httpd_run(reg, config).await {
         error!("{}", e);
     }
 
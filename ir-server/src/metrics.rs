@@ -0,0 +1,358 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use ratls::{InternalTokenVerifier, RaTlsError};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::registry::LoadStats;
+
+// request durations are measured until the response headers, blob bodies are
+// streamed afterwards
+const DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// routes that didn't match anything are reported under one label so random
+// paths don't create new series
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+#[derive(Debug, Default, Clone)]
+struct Histogram
+{
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram
+{
+    fn observe(&mut self, value: f64)
+    {
+        for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// Metrics of a single server instance in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics
+{
+    // (method, route, status)
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    // (method, route)
+    durations: Mutex<BTreeMap<(String, String), Histogram>>,
+    // app, shared with the body streams as they are sent
+    served_bytes: Mutex<BTreeMap<String, Arc<AtomicU64>>>,
+    // (protocol, result)
+    handshakes: Mutex<BTreeMap<(String, &'static str), u64>>,
+    // (verifier, result)
+    verifications: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+}
+
+fn result_label(ok: bool) -> &'static str
+{
+    match ok {
+        true => "success",
+        false => "failure",
+    }
+}
+
+fn escape(value: &str) -> String
+{
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str)
+{
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl Metrics
+{
+    pub fn new() -> Self
+    {
+        Metrics::default()
+    }
+
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, seconds: f64)
+    {
+        let key = (method.to_string(), route.to_string());
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((key.0.clone(), key.1.clone(), status))
+            .or_default() += 1;
+        self.durations
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_default()
+            .observe(seconds);
+    }
+
+    /// Counter of bytes served for an app, to be increased as the body is sent
+    pub fn served_bytes(&self, app: &str) -> Arc<AtomicU64>
+    {
+        self.served_bytes
+            .lock()
+            .unwrap()
+            .entry(app.to_string())
+            .or_default()
+            .clone()
+    }
+
+    pub fn observe_handshake(&self, protocol: &str, ok: bool)
+    {
+        *self
+            .handshakes
+            .lock()
+            .unwrap()
+            .entry((protocol.to_string(), result_label(ok)))
+            .or_default() += 1;
+    }
+
+    pub fn observe_verification(&self, verifier: &'static str, ok: bool)
+    {
+        *self
+            .verifications
+            .lock()
+            .unwrap()
+            .entry((verifier, result_label(ok)))
+            .or_default() += 1;
+    }
+
+    /// Metrics of the apps `is_hidden` is true for are left out, their names
+    /// shouldn't be disclosed to clients not allowed to pull them
+    pub fn render<F: Fn(&str) -> bool>(&self, load: Option<&LoadStats>, is_hidden: F) -> String
+    {
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "ir_http_requests_total",
+            "counter",
+            "HTTP requests by method, route and status",
+        );
+        for ((method, route, status), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "ir_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method,
+                escape(route),
+                status,
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "ir_http_request_duration_seconds",
+            "histogram",
+            "Time to the response headers by method and route",
+        );
+        for ((method, route), histogram) in self.durations.lock().unwrap().iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", method, escape(route));
+            for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets) {
+                let _ = writeln!(
+                    out,
+                    "ir_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "ir_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "ir_http_request_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "ir_http_request_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            );
+        }
+
+        header(
+            &mut out,
+            "ir_served_bytes_total",
+            "counter",
+            "Bytes of manifests and blobs sent by app",
+        );
+        for (app, bytes) in self.served_bytes.lock().unwrap().iter() {
+            if is_hidden(app) {
+                continue;
+            }
+            let _ = writeln!(
+                out,
+                "ir_served_bytes_total{{app=\"{}\"}} {}",
+                escape(app),
+                bytes.load(Ordering::Relaxed)
+            );
+        }
+
+        header(
+            &mut out,
+            "ir_tls_handshakes_total",
+            "counter",
+            "TLS and RA-TLS handshakes by protocol and result",
+        );
+        for ((protocol, result), count) in self.handshakes.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "ir_tls_handshakes_total{{protocol=\"{}\",result=\"{}\"}} {}",
+                protocol, result, count
+            );
+        }
+
+        header(
+            &mut out,
+            "ir_ratls_verifications_total",
+            "counter",
+            "RA-TLS client token verifications by verifier and result",
+        );
+        for ((verifier, result), count) in self.verifications.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "ir_ratls_verifications_total{{verifier=\"{}\",result=\"{}\"}} {}",
+                verifier, result, count
+            );
+        }
+
+        if let Some(load) = load {
+            header(
+                &mut out,
+                "ir_registry_apps",
+                "gauge",
+                "Applications found in the registry by load state",
+            );
+            for (state, count) in [
+                ("loaded", load.loaded),
+                ("failed", load.failed),
+                ("disabled", load.disabled),
+            ] {
+                let _ = writeln!(out, "ir_registry_apps{{state=\"{}\"}} {}", state, count);
+            }
+
+            header(
+                &mut out,
+                "ir_registry_load_seconds",
+                "gauge",
+                "Time it took to load and verify the registry",
+            );
+            let _ = writeln!(
+                out,
+                "ir_registry_load_seconds {}",
+                load.duration.as_secs_f64()
+            );
+        }
+
+        out
+    }
+}
+
+/// Middleware counting the requests and their durations
+pub async fn track_requests(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response
+{
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => UNMATCHED_ROUTE.to_string(),
+    };
+
+    let response = next.run(request).await;
+
+    metrics.observe_request(
+        &method,
+        &route,
+        response.status().as_u16(),
+        start.elapsed().as_secs_f64(),
+    );
+    response
+}
+
+/// Token verifier counting the results of the one it wraps
+#[derive(Debug)]
+pub struct MeasuredVerifier
+{
+    name: &'static str,
+    inner: Arc<dyn InternalTokenVerifier>,
+    metrics: Arc<Metrics>,
+}
+
+impl MeasuredVerifier
+{
+    pub fn wrap(
+        name: &'static str,
+        inner: Arc<dyn InternalTokenVerifier>,
+        metrics: Arc<Metrics>,
+    ) -> Arc<dyn InternalTokenVerifier>
+    {
+        Arc::new(MeasuredVerifier {
+            name,
+            inner,
+            metrics,
+        })
+    }
+}
+
+impl InternalTokenVerifier for MeasuredVerifier
+{
+    fn verify(&self, token: &[u8]) -> Result<(), RaTlsError>
+    {
+        let ret = self.inner.verify(token);
+        self.metrics.observe_verification(self.name, ret.is_ok());
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::Metrics;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn metrics_hidden_apps()
+    {
+        let metrics = Metrics::new();
+        metrics.served_bytes("public").fetch_add(10, Ordering::Relaxed);
+        metrics.served_bytes("tenant-a").fetch_add(20, Ordering::Relaxed);
+        metrics.observe_request("GET", "/v2/{name}/blobs/{digest}", 200, 0.002);
+
+        let out = metrics.render(None, |_| false);
+        assert!(out.contains("ir_served_bytes_total{app=\"public\"} 10\n"));
+        assert!(out.contains("ir_served_bytes_total{app=\"tenant-a\"} 20\n"));
+
+        let out = metrics.render(None, |app| app == "tenant-a");
+        assert!(out.contains("ir_served_bytes_total{app=\"public\"} 10\n"));
+        assert!(!out.contains("tenant-a"));
+        assert!(out.contains(
+            "ir_http_requests_total{method=\"GET\",route=\"/v2/{name}/blobs/{digest}\",status=\"200\"} 1\n"
+        ));
+    }
+}
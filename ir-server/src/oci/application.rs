@@ -8,6 +8,7 @@ use std::path::{Path, PathBuf};
 
 use super::digest::Digest;
 use super::sha2;
+use super::signature::Signature;
 use super::tag;
 use super::validate::Validate;
 use crate::error::RegistryError;
@@ -16,6 +17,8 @@ use crate::{utils, RegistryResult};
 const OCI_LAYOUT: &str = "oci-layout";
 const INDEX_JSON: &str = "index.json";
 const BLOBS_SUBDIR: &str = "blobs";
+// payloads of signatures made with `ir-sign --format cosign`
+const COSIGN_SIMPLESIGNING: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
 
 macro_rules! err {
     ($($arg:tt)+) => (Err(RegistryError::OciRegistry(format!($($arg)+))))
@@ -37,6 +40,7 @@ pub(super) struct Application
     tags: HashMap<String, Content>,
     manifests: HashMap<Digest, Content>,
     blobs: HashMap<Digest, Content>,
+    signatures: HashMap<Digest, Vec<Signature>>,
 }
 
 impl Application
@@ -116,7 +120,10 @@ impl Application
                 self.manifests.insert(digest, content);
             }
             MediaType::ImageManifest => {
-                self.import_manifest(&path)?;
+                let signatures = self.import_manifest(&path)?;
+                if !signatures.is_empty() {
+                    self.signatures.entry(digest.clone()).or_default().extend(signatures);
+                }
                 self.manifests.insert(digest, content);
             }
             MediaType::ImageConfig
@@ -125,6 +132,9 @@ impl Application
             | MediaType::ImageLayerZstd => {
                 self.blobs.insert(digest, content);
             }
+            MediaType::Other(m) if m == COSIGN_SIMPLESIGNING => {
+                self.blobs.insert(digest, content);
+            }
             m => err!("Unsupported media type: {}", m)?,
         }
 
@@ -148,7 +158,7 @@ impl Application
         Ok(())
     }
 
-    fn import_manifest<T: AsRef<Path>>(&mut self, path: T) -> RegistryResult<()>
+    fn import_manifest<T: AsRef<Path>>(&mut self, path: T) -> RegistryResult<Vec<Signature>>
     {
         let manifest = match ImageManifest::from_file(&path) {
             Ok(i) => i,
@@ -163,8 +173,27 @@ impl Application
         let layers = manifest.layers();
         for desc in layers {
             self.import_descriptor(desc, false)?;
+            if let MediaType::Other(m) = desc.media_type() {
+                if m == COSIGN_SIMPLESIGNING {
+                    self.import_cosign_signature(desc)?;
+                }
+            }
         }
 
+        Signature::from_annotations(manifest.annotations())
+    }
+
+    /// Signatures in a cosign signature manifest count as the ones of the
+    /// manifest they sign
+    fn import_cosign_signature(&mut self, desc: &Descriptor) -> RegistryResult<()>
+    {
+        let digest = Digest::try_from(desc.digest())?;
+        let payload = std::fs::read(self.path.join(BLOBS_SUBDIR).join(digest.to_path()))?;
+        let (signed, signature) = Signature::from_cosign(desc.annotations(), &payload)?;
+
+        let signed = Digest::try_from(signed)?;
+        self.signatures.entry(signed).or_default().push(signature);
+
         Ok(())
     }
 
@@ -191,6 +220,11 @@ impl Application
         &self.blobs
     }
 
+    pub(super) fn get_signatures(&self) -> &HashMap<Digest, Vec<Signature>>
+    {
+        &self.signatures
+    }
+
     pub(super) fn import<T: AsRef<Path>>(path: T) -> RegistryResult<Self>
     {
         let path = path.as_ref().canonicalize()?;
@@ -221,7 +255,7 @@ impl Application
 #[cfg(test)]
 mod tests
 {
-    use super::{Application, RegistryError};
+    use super::{Application, Digest, RegistryError};
 
     #[test]
     fn application_correct()
@@ -229,6 +263,22 @@ mod tests
         assert!(Application::import("tests/applications/correct").is_ok());
     }
 
+    #[test]
+    fn application_cosign_signed()
+    {
+        let app = Application::import("tests/applications/cosign_signed").unwrap();
+
+        // the signature layer counts for the manifest it signs
+        let signed = Digest::try_from(
+            "sha256:2a0b6a18e2af9e8fb0a3f29bcb9221502b5f268eb13df657047918dc9fbc7611",
+        )
+        .unwrap();
+        let signatures = &app.get_signatures()[&signed];
+        assert_eq!(signatures.len(), 1);
+        assert_eq!(signatures[0].expiry, None);
+        assert_eq!(app.get_signatures().len(), 1);
+    }
+
     #[test]
     fn application_missing_index() -> Result<(), String>
     {
@@ -2,6 +2,7 @@ mod application;
 mod digest;
 mod registry;
 mod sha2;
+mod signature;
 mod tag;
 mod validate;
 
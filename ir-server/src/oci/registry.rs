@@ -3,13 +3,16 @@ use log::{debug, error, info, warn};
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::path::Path;
+use std::time::Instant;
 use tokio::fs;
 
 use super::application::{Application, Content};
 use super::digest::Digest;
+use super::signature::Signature;
 use crate::error::RegistryError;
-use crate::registry::{ImageRegistry, Payload};
-use crate::RegistryResult;
+use crate::registry::{ImageRegistry, LoadStats, Payload};
+use crate::revocation::RevocationList;
+use crate::{utils, RegistryResult};
 
 macro_rules! err {
     ($($arg:tt)+) => (Err(RegistryError::OciRegistry(format!($($arg)+))))
@@ -19,16 +22,28 @@ macro_rules! err {
 pub struct Registry
 {
     apps: HashMap<String, Application>,
+    revocation: Option<RevocationList>,
+    stats: LoadStats,
 }
 
 impl Registry
 {
     pub fn import<T: AsRef<Path>>(path: T) -> RegistryResult<Self>
+    {
+        Registry::import_filtered(path, |_| true)
+    }
+
+    /// Import only the applications accepted by the filter
+    pub fn import_filtered<T, F>(path: T, filter: F) -> RegistryResult<Self>
+    where
+        T: AsRef<Path>,
+        F: Fn(&str) -> bool,
     {
         let path = path.as_ref().canonicalize()?;
 
         info!("Loading registry from: \"{}\"", path.display());
 
+        let start = Instant::now();
         let mut reg = Registry::default();
 
         let reg_path = Path::new(&path);
@@ -44,11 +59,20 @@ impl Registry
                     .unwrap_or(OsStr::new(""))
                     .to_string_lossy();
                 debug!("Application \"{}\" found", app_name);
+                if !filter(&app_name) {
+                    info!("Application \"{}\" is disabled, skipping", app_name);
+                    reg.stats.disabled += 1;
+                    continue;
+                }
                 match Application::import(&app_path) {
                     Ok(a) => {
                         reg.apps.insert(app_name.to_string(), a);
+                        reg.stats.loaded += 1;
+                    }
+                    Err(e) => {
+                        error!("Failed to load app \"{}\": {}", app_name, e);
+                        reg.stats.failed += 1;
                     }
-                    Err(e) => error!("Failed to load app \"{}\": {}", app_name, e),
                 };
             } else {
                 warn!(
@@ -58,9 +82,44 @@ impl Registry
             }
         }
 
+        reg.stats.duration = start.elapsed();
+
         Ok(reg)
     }
 
+    /// Stop serving manifests signed with the keys from the revocation list
+    pub fn set_revocation_list(&mut self, revocation: RevocationList)
+    {
+        info!(
+            "Revocation list issued at {} with {} key(s) loaded",
+            revocation.issued,
+            revocation.revoked.len()
+        );
+        self.revocation = Some(revocation);
+    }
+
+    fn is_trusted(&self, app: &Application, content: &Content) -> bool
+    {
+        // unwrap() is safe, contents are only created from correct digests
+        let digest = Digest::try_from(content.digest.as_str()).unwrap();
+        let Some(signatures) = app.get_signatures().get(&digest) else {
+            return true;
+        };
+
+        // the signatures aren't verified here, any of them could have been
+        // added by someone else so a single revoked or expired one is enough
+        // to stop serving the manifest
+        if !all_usable(signatures, self.revocation.as_ref(), utils::unix_time()) {
+            error!(
+                "Manifest \"{}\" has an expired signature or one made with a revoked key",
+                digest
+            );
+            return false;
+        }
+
+        true
+    }
+
     pub fn log_summary(&self)
     {
         info!("Application list with tags:");
@@ -107,6 +166,14 @@ impl Registry
     }
 }
 
+fn all_usable(signatures: &[Signature], revocation: Option<&RevocationList>, now: u64) -> bool
+{
+    signatures.iter().all(|signature| {
+        let revoked = revocation.is_some_and(|r| r.is_revoked(&signature.vendor_pub));
+        !revoked && !signature.is_expired(now)
+    })
+}
+
 #[async_trait]
 impl ImageRegistry for Registry
 {
@@ -128,6 +195,10 @@ impl ImageRegistry for Registry
             Err(_) => app.get_tags().get(reference)?,
         };
 
+        if !self.is_trusted(app, content) {
+            return None;
+        }
+
         Registry::get_payload(content).await
     }
 
@@ -142,4 +213,51 @@ impl ImageRegistry for Registry
 
         Registry::get_payload(content).await
     }
+
+    fn load_stats(&self) -> Option<LoadStats>
+    {
+        Some(self.stats.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{all_usable, Signature};
+    use crate::revocation::RevocationList;
+    use sha2::{Digest, Sha256};
+
+    const REVOKED_PUB: &[u8] = b"revoked vendor key";
+    const FORGED_PUB: &[u8] = b"any other key";
+
+    fn signature(vendor_pub: &[u8], expiry: Option<u64>) -> Signature
+    {
+        Signature {
+            vendor_pub: vendor_pub.to_vec(),
+            expiry,
+        }
+    }
+
+    #[test]
+    fn registry_revoked_with_forged_signature()
+    {
+        let revocation = RevocationList {
+            issued: 0,
+            revoked: vec![hex::encode(Sha256::digest(REVOKED_PUB))],
+        };
+        let signatures = [signature(REVOKED_PUB, None), signature(FORGED_PUB, None)];
+
+        assert!(!all_usable(&signatures, Some(&revocation), 0));
+        assert!(all_usable(&signatures[1..], Some(&revocation), 0));
+        assert!(all_usable(&signatures, None, 0));
+    }
+
+    #[test]
+    fn registry_expired_with_forged_signature()
+    {
+        let signatures = [signature(FORGED_PUB, Some(100)), signature(FORGED_PUB, None)];
+
+        assert!(!all_usable(&signatures, None, 101));
+        assert!(all_usable(&signatures, None, 100));
+    }
 }
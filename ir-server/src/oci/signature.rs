@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use ir_verify::annotation::{annotation, SIGNATURE_EXPIRY, SIGNATURE_TIMESTAMP, VENDORPUB};
+
+use crate::error::RegistryError;
+use crate::RegistryResult;
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(RegistryError::OciRegistry(format!($($arg)+))))
+}
+
+/// Signature metadata of a manifest signed with `ir-sign`, in the manifest
+/// annotations or in a cosign signature manifest. The signature itself is not
+/// verified here, this is left for the clients, it's only used to stop serving
+/// images signed with revoked keys or past their expiry.
+#[derive(Debug, Clone)]
+pub(super) struct Signature
+{
+    pub(super) vendor_pub: Vec<u8>,
+    pub(super) expiry: Option<u64>,
+}
+
+impl Signature
+{
+    /// Read all the signatures of a manifest, slots are consecutive
+    pub(super) fn from_annotations(
+        annotations: &Option<HashMap<String, String>>,
+    ) -> RegistryResult<Vec<Self>>
+    {
+        let mut signatures = Vec::new();
+        let Some(annotations) = annotations else {
+            return Ok(signatures);
+        };
+        while let Some(signature) = Self::from_slot(annotations, signatures.len())? {
+            signatures.push(signature);
+        }
+
+        Ok(signatures)
+    }
+
+    fn from_slot(annotations: &HashMap<String, String>, slot: usize)
+        -> RegistryResult<Option<Self>>
+    {
+        let Some(vendor_pub) = annotations.get(&annotation(slot, VENDORPUB)) else {
+            return Ok(None);
+        };
+
+        let vendor_pub = match hex::decode(vendor_pub) {
+            Ok(v) => v,
+            Err(e) => err!("Incorrect vendor pub annotation: {}", e)?,
+        };
+
+        // the expiry is only signed along with a timestamp
+        let expiry = match annotations.get(&annotation(slot, SIGNATURE_EXPIRY)) {
+            Some(_) if !annotations.contains_key(&annotation(slot, SIGNATURE_TIMESTAMP)) => {
+                err!("Signature expiry without a timestamp")?
+            }
+            Some(e) => Some(e.parse().or(err!("Incorrect signature expiry: {}", e))?),
+            None => None,
+        };
+
+        Ok(Some(Signature { vendor_pub, expiry }))
+    }
+
+    /// Read the signature of a cosign layer: the vendor key is kept in the
+    /// layer annotations, timestamp and expiry in the signed payload. Returns
+    /// the digest of the signed manifest along with the signature.
+    pub(super) fn from_cosign(
+        annotations: &Option<HashMap<String, String>>,
+        payload: &[u8],
+    ) -> RegistryResult<(String, Self)>
+    {
+        let Some(annotations) = annotations else {
+            return err!("Cosign signature without annotations");
+        };
+        let payload: serde_json::Value = match serde_json::from_slice(payload) {
+            Ok(p) => p,
+            Err(e) => err!("Incorrect cosign payload: {}", e)?,
+        };
+        let Some(digest) = payload["critical"]["image"]["docker-manifest-digest"].as_str() else {
+            return err!("Cosign payload without a manifest digest");
+        };
+
+        // same names as the annotations of the native format
+        let mut fields = HashMap::new();
+        if let Some(vendor_pub) = annotations.get(&annotation(0, VENDORPUB)) {
+            fields.insert(annotation(0, VENDORPUB), vendor_pub.clone());
+        }
+        for name in [SIGNATURE_TIMESTAMP, SIGNATURE_EXPIRY] {
+            let key = annotation(0, name);
+            if let Some(value) = payload["optional"][&key].as_str() {
+                fields.insert(key, value.to_string());
+            }
+        }
+
+        match Self::from_slot(&fields, 0)? {
+            Some(signature) => Ok((digest.to_string(), signature)),
+            None => err!("Cosign signature without a vendor key"),
+        }
+    }
+
+    pub(super) fn is_expired(&self, now: u64) -> bool
+    {
+        matches!(self.expiry, Some(expiry) if now > expiry)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::Signature;
+    use ir_verify::annotation::{annotation, VENDORPUB};
+    use std::collections::HashMap;
+
+    const DIGEST: &str = "sha256:2a0b6a18e2af9e8fb0a3f29bcb9221502b5f268eb13df657047918dc9fbc7611";
+
+    fn payload(optional: &str) -> Vec<u8>
+    {
+        format!(
+            r#"{{"critical":{{"image":{{"docker-manifest-digest":"{}"}}}},"optional":{{{}}}}}"#,
+            DIGEST, optional
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn signature_from_cosign()
+    {
+        let annotations = Some(HashMap::from([(annotation(0, VENDORPUB), "0102".to_string())]));
+
+        let (signed, signature) = Signature::from_cosign(
+            &annotations,
+            &payload(
+                r#""com.samsung.islet.image.signature.timestamp":"1700000000",
+                "com.samsung.islet.image.signature.expiry":"1800000000""#,
+            ),
+        )
+        .unwrap();
+        assert_eq!(signed, DIGEST);
+        assert_eq!(signature.vendor_pub, [1, 2]);
+        assert_eq!(signature.expiry, Some(1800000000));
+
+        // the expiry is only signed along with a timestamp
+        let unsigned = payload(r#""com.samsung.islet.image.signature.expiry":"1800000000""#);
+        assert!(Signature::from_cosign(&annotations, &unsigned).is_err());
+
+        // the vendor key is needed to check the revocation
+        assert!(Signature::from_cosign(&Some(HashMap::new()), &payload("")).is_err());
+    }
+}
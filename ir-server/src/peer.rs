@@ -0,0 +1,76 @@
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use std::sync::Arc;
+use tokio::net::{TcpListener, UnixListener};
+
+use crate::config::Protocol;
+use crate::token::Claims;
+
+/// Address of the remote end of a connection as shown in the logs
+pub trait PeerAddress
+{
+    fn peer_address(&self) -> String;
+}
+
+impl PeerAddress for std::net::SocketAddr
+{
+    fn peer_address(&self) -> String
+    {
+        self.to_string()
+    }
+}
+
+impl PeerAddress for tokio::net::unix::SocketAddr
+{
+    // clients rarely bind their end of a Unix socket so it's usually unnamed
+    fn peer_address(&self) -> String
+    {
+        match self.as_pathname() {
+            Some(path) => format!("unix:{}", path.display()),
+            None => "unix".to_string(),
+        }
+    }
+}
+
+/// Client of a connection, available to the handlers and middlewares as
+/// `ConnectInfo<Peer>`
+#[derive(Debug, Clone)]
+pub struct Peer
+{
+    pub address: String,
+    pub protocol: Protocol,
+    /// Claims of the RA-TLS client token that passed the verification
+    pub claims: Option<Arc<Claims>>,
+    /// Subject of the mTLS client certificate that passed the verification
+    pub subject: Option<Arc<str>>,
+}
+
+// plain connections only, the TLS ones are set up after the handshake
+fn plain_peer<L>(stream: IncomingStream<'_, L>) -> Peer
+where
+    L: Listener,
+    L::Addr: PeerAddress,
+{
+    Peer {
+        address: stream.remote_addr().peer_address(),
+        protocol: Protocol::NoTls,
+        claims: None,
+        subject: None,
+    }
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for Peer
+{
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self
+    {
+        plain_peer(stream)
+    }
+}
+
+impl Connected<IncomingStream<'_, UnixListener>> for Peer
+{
+    fn connect_info(stream: IncomingStream<'_, UnixListener>) -> Self
+    {
+        plain_peer(stream)
+    }
+}
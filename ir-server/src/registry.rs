@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use std::time::Duration;
 use tokio::fs;
 
 pub struct Payload
@@ -9,10 +10,26 @@ pub struct Payload
     pub media_type: String,
 }
 
+/// Outcome of loading a registry, exported as metrics
+#[derive(Debug, Clone, Default)]
+pub struct LoadStats
+{
+    pub loaded: usize,
+    pub failed: usize,
+    pub disabled: usize,
+    pub duration: Duration,
+}
+
 #[async_trait]
 pub trait ImageRegistry: Send + Sync
 {
     fn get_tags(&self, app: &str) -> Option<Vec<String>>;
     async fn get_manifest(&self, app: &str, reference: &str) -> Option<Payload>;
     async fn get_blob(&self, app: &str, digest: &str) -> Option<Payload>;
+
+    /// Registries that are not loaded from disk have nothing to report
+    fn load_stats(&self) -> Option<LoadStats>
+    {
+        None
+    }
 }
@@ -0,0 +1,45 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+use crate::error::RegistryError;
+use crate::{utils, RegistryResult};
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(RegistryError::Signature(format!($($arg)+))))
+}
+
+/// List of revoked vendor keys as produced by `ir-sign revoke-key`. Keys are
+/// identified by SHA256 fingerprints of their SPKI DER encoding.
+#[derive(Debug, Deserialize)]
+pub struct RevocationList
+{
+    pub issued: u64,
+    pub revoked: Vec<String>,
+}
+
+impl RevocationList
+{
+    /// Load the list verifying its signature with ROOT-CA public key
+    pub fn load<T: AsRef<Path>>(path: T, signature: T, ca_pub: T) -> RegistryResult<Self>
+    {
+        let json = utils::file_read(path)?;
+        let sign = utils::file_read(signature)?;
+        let ca_pub = utils::file_read(ca_pub)?;
+
+        // the signature may be written in PEM by `ir-sign`
+        let verified = ir_verify::signature_der(&sign)
+            .and_then(|sign| ir_verify::verify(&ca_pub, &json, &sign));
+        if let Err(e) = verified {
+            err!("Revocation list: {}", e)?;
+        }
+
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    pub fn is_revoked(&self, public_der: &[u8]) -> bool
+    {
+        let fingerprint = hex::encode(Sha256::digest(public_der));
+        self.revoked.contains(&fingerprint)
+    }
+}
@@ -1,150 +1,306 @@
-use axum::{extract::Request, Router};
+use axum::{
+    extract::{connect_info::ConnectInfo, Request},
+    serve::Listener,
+    Router,
+};
 use futures_util::pin_mut;
 use hyper::body::Incoming;
 use hyper_util::rt::{TokioExecutor, TokioIo};
-use log::debug;
-use ratls::{ChainVerifier, InternalTokenVerifier, RaTlsCertVeryfier};
-use realm_verifier::{parser_json::parse_value, RealmVerifier};
-use std::{fs::File, io::BufReader, sync::Arc};
-use tokio::net::TcpListener;
+use log::{debug, warn};
+use ratls::{InternalTokenVerifier, RaTlsCertVeryfier, RaTlsError};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinSet;
 use tokio_rustls::rustls::crypto::ring::default_provider;
-use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig as RustlsConfig, ServerConnection};
 use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
 use tower_service::Service;
-use veraison_verifier::VeraisonTokenVerifer;
 
-use crate::config::Config;
+use crate::config::{Protocol, ServerConfig};
+use crate::credentials::TlsCredentials;
+use crate::error::RegistryError;
+use crate::metrics::Metrics;
+use crate::peer::{Peer, PeerAddress};
+use crate::token::Claims;
 use crate::utils;
+use crate::x509;
 use crate::RegistryResult;
 
-enum TLSConfig<'a>
+enum TLSConfig
 {
-    Tls(Arc<ServerConfig>),
-    RaTls(RaTLS<'a>),
+    Tls(Arc<RustlsConfig>),
+    Mtls(Arc<RustlsConfig>),
+    RaTls(Arc<TlsCredentials>),
 }
 
-struct RaTLS<'a>
+impl TLSConfig
 {
-    pub certs: Vec<CertificateDer<'a>>,
-    pub priv_key: PrivateKeyDer<'a>,
-    pub client_token_verifier: Arc<dyn InternalTokenVerifier>,
-}
+    fn protocol(&self) -> Protocol
+    {
+        match self {
+            Self::Tls(_) => Protocol::Tls,
+            Self::Mtls(_) => Protocol::Mtls,
+            Self::RaTls(_) => Protocol::RaTls,
+        }
+    }
 
-impl TLSConfig<'static>
-{
-    pub fn get_rustls_config(&self) -> RegistryResult<Arc<ServerConfig>>
+    /// Config for a single connection, for RA-TLS together with the recorder
+    /// of the client token it verifies
+    pub fn get_rustls_config(
+        &self,
+    ) -> RegistryResult<(Arc<RustlsConfig>, Option<Arc<TokenRecorder>>)>
     {
         match self {
-            Self::Tls(config) => Ok(config.clone()),
-            Self::RaTls(ra_tls) => {
-                let rustls_config = ServerConfig::builder()
+            Self::Tls(config) | Self::Mtls(config) => Ok((config.clone(), None)),
+            Self::RaTls(credentials) => {
+                // the verifier of the moment, reloads don't affect this handshake
+                let recorder = Arc::new(TokenRecorder {
+                    inner: credentials
+                        .token_verifier()
+                        .ok_or("RA-TLS client token verifier not loaded")?,
+                    token: Mutex::new(None),
+                });
+                let rustls_config = RustlsConfig::builder()
                     .with_client_cert_verifier(Arc::new(RaTlsCertVeryfier::from_token_verifier(
-                        ra_tls.client_token_verifier.clone(),
+                        recorder.clone(),
                     )))
-                    .with_single_cert(ra_tls.certs.clone(), ra_tls.priv_key.clone_key())?;
-                Ok(Arc::new(rustls_config))
+                    .with_cert_resolver(credentials.clone());
+                Ok((Arc::new(rustls_config), Some(recorder)))
             }
         }
     }
 }
 
-fn tls_server_config() -> RegistryResult<TLSConfig<'static>>
+/// Keeps the client token that passed the verification, so the requests that
+/// follow on the connection can be tied to the attested realm
+#[derive(Debug)]
+struct TokenRecorder
 {
-    let config = ServerConfig::builder()
+    inner: Arc<dyn InternalTokenVerifier>,
+    token: Mutex<Option<Vec<u8>>>,
+}
+
+impl TokenRecorder
+{
+    fn claims(&self) -> Option<Claims>
+    {
+        let token = self.token.lock().unwrap().take()?;
+        match Claims::parse(&token) {
+            Ok(claims) => Some(claims),
+            Err(e) => {
+                warn!("Can't parse the claims of a verified client token: {}", e);
+                None
+            }
+        }
+    }
+}
+
+impl InternalTokenVerifier for TokenRecorder
+{
+    fn verify(&self, token: &[u8]) -> Result<(), RaTlsError>
+    {
+        self.inner.verify(token)?;
+        *self.token.lock().unwrap() = Some(token.to_vec());
+        Ok(())
+    }
+}
+
+fn tls_server_config(credentials: &Arc<TlsCredentials>) -> TLSConfig
+{
+    let rustls_config = RustlsConfig::builder()
         .with_no_client_auth()
-        .with_single_cert(
-            utils::load_certificates_from_pem(&Config::readu().cert)?,
-            utils::load_private_key_from_file(&Config::readu().key)?,
-        )?;
+        .with_cert_resolver(credentials.clone());
 
-    Ok(TLSConfig::Tls(Arc::new(config)))
+    TLSConfig::Tls(Arc::new(rustls_config))
 }
 
-fn ratls_server_config() -> RegistryResult<TLSConfig<'static>>
+fn mtls_server_config(
+    config: &ServerConfig,
+    credentials: &Arc<TlsCredentials>,
+) -> RegistryResult<TLSConfig>
 {
-    let json_reader = BufReader::new(File::open(&Config::readu().reference_json)?);
-    let mut reference_json: serde_json::Value = serde_json::from_reader(json_reader)?;
-    let reference_measurements = parse_value(reference_json["realm"]["reference-values"].take())?;
-
-    let client_token_verifier = Arc::new(ChainVerifier::new(vec![
-        #[cfg(not(feature = "disable-challenge-veraison"))]
-        Arc::new(VeraisonTokenVerifer::new(
-            &Config::readu().veraison_url,
-            std::fs::read_to_string(&Config::readu().veraison_pubkey)?,
-            None,
-        )?),
-        Arc::new(RealmVerifier::init(reference_measurements.clone())),
-    ]));
-    let certs = utils::load_certificates_from_pem(&Config::readu().cert)?;
-    let priv_key = utils::load_private_key_from_file(&Config::readu().key)?;
-
-    Ok(TLSConfig::RaTls(RaTLS {
-        client_token_verifier,
-        certs,
-        priv_key,
-    }))
+    let client_ca = config.client_ca.as_deref().unwrap_or_default();
+    let mut roots = RootCertStore::empty();
+    for cert in utils::load_certificates_from_pem(client_ca)? {
+        roots.add(cert)?;
+    }
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+        .build()
+        .map_err(|e| RegistryError::Config(format!("Client CA \"{}\": {}", client_ca, e)))?;
+
+    let rustls_config = RustlsConfig::builder()
+        .with_client_cert_verifier(verifier)
+        .with_cert_resolver(credentials.clone());
+
+    Ok(TLSConfig::Mtls(Arc::new(rustls_config)))
 }
 
-pub async fn serve_tls(listener: TcpListener, app: Router) -> RegistryResult<()>
+// the end-entity certificate has been verified by the time this is called
+fn client_subject(connection: &ServerConnection) -> Option<Arc<str>>
+{
+    let cert = connection.peer_certificates()?.first()?;
+    match x509::subject(cert) {
+        Ok(subject) => Some(subject.into()),
+        Err(e) => {
+            warn!(
+                "Can't parse the subject of a verified client certificate: {}",
+                e
+            );
+            None
+        }
+    }
+}
+
+// The provider is process wide, failing means another server instance has
+// already installed it
+fn install_crypto_provider()
+{
+    let _ = default_provider().install_default();
+}
+
+pub async fn serve_tls<L>(
+    listener: L,
+    app: Router,
+    credentials: Arc<TlsCredentials>,
+    metrics: Arc<Metrics>,
+    shutdown: CancellationToken,
+) -> RegistryResult<()>
+where
+    L: Listener,
+    L::Addr: PeerAddress,
 {
     debug!("Initializing TLS");
 
-    default_provider()
-        .install_default()
-        .expect("Could not install CryptoProvider");
+    install_crypto_provider();
+
+    let tls_config = tls_server_config(&credentials);
+    serve_internal(listener, app, tls_config, metrics, shutdown).await
+}
+
+pub async fn serve_mtls<L>(
+    listener: L,
+    app: Router,
+    config: &ServerConfig,
+    credentials: Arc<TlsCredentials>,
+    metrics: Arc<Metrics>,
+    shutdown: CancellationToken,
+) -> RegistryResult<()>
+where
+    L: Listener,
+    L::Addr: PeerAddress,
+{
+    debug!("Initializing mTLS");
 
-    let tls_config = tls_server_config()?;
-    serve_internal(listener, app, tls_config).await
+    install_crypto_provider();
+
+    let tls_config = mtls_server_config(config, &credentials)?;
+    serve_internal(listener, app, tls_config, metrics, shutdown).await
 }
 
-pub async fn serve_ratls(listener: TcpListener, app: Router) -> RegistryResult<()>
+pub async fn serve_ratls<L>(
+    listener: L,
+    app: Router,
+    credentials: Arc<TlsCredentials>,
+    metrics: Arc<Metrics>,
+    shutdown: CancellationToken,
+) -> RegistryResult<()>
+where
+    L: Listener,
+    L::Addr: PeerAddress,
 {
     debug!("Initializing RA-TLS");
 
-    default_provider()
-        .install_default()
-        .expect("Could not install CryptoProvider");
+    install_crypto_provider();
 
-    let tls_config = ratls_server_config()?;
-    serve_internal(listener, app, tls_config).await
+    let tls_config = TLSConfig::RaTls(credentials);
+    serve_internal(listener, app, tls_config, metrics, shutdown).await
 }
 
 // For details on the code see here:
 // https://github.com/tokio-rs/axum/blob/main/examples/low-level-rustls/src/main.rs
-async fn serve_internal(
-    listener: TcpListener,
+async fn serve_internal<L>(
+    mut listener: L,
     app: Router,
-    tls_config: TLSConfig<'static>,
+    tls_config: TLSConfig,
+    metrics: Arc<Metrics>,
+    shutdown: CancellationToken,
 ) -> RegistryResult<()>
+where
+    L: Listener,
+    L::Addr: PeerAddress,
 {
-    pin_mut!(listener);
+    // connections in flight, dropping the set aborts them
+    let mut connections = JoinSet::new();
+    let protocol = tls_config.protocol();
 
     loop {
         let tower_service = app.clone();
 
-        let (cnx, addr) = listener.accept().await?;
-        let tls_acceptor = TlsAcceptor::from(tls_config.get_rustls_config()?);
+        let (cnx, addr) = tokio::select! {
+            accepted = listener.accept() => accepted,
+            // reap the finished ones so the set doesn't grow
+            Some(_) = connections.join_next() => continue,
+            _ = shutdown.cancelled() => break,
+        };
+        let (rustls_config, recorder) = tls_config.get_rustls_config()?;
+        let tls_acceptor = TlsAcceptor::from(rustls_config);
+        let address = addr.peer_address();
+        let protocol = protocol.clone();
+        let shutdown = shutdown.clone();
+        let metrics = metrics.clone();
 
-        tokio::spawn(async move {
-            let Ok(stream) = tls_acceptor.accept(cnx).await else {
-                log::error!("error during tls handshake connection from {}", addr);
+        connections.spawn(async move {
+            let accepted = tls_acceptor.accept(cnx).await;
+            metrics.observe_handshake(protocol.name(), accepted.is_ok());
+            let Ok(stream) = accepted else {
+                log::error!("error during tls handshake connection from {}", address);
                 return;
             };
 
+            let subject = match protocol {
+                Protocol::Mtls => client_subject(stream.get_ref().1),
+                _ => None,
+            };
             let stream = TokioIo::new(stream);
 
-            let hyper_service = hyper::service::service_fn(move |request: Request<Incoming>| {
-                tower_service.clone().call(request)
-            });
+            // the handshake outcome goes along with every request on the connection
+            let peer = Peer {
+                address: address.clone(),
+                protocol,
+                claims: recorder.and_then(|r| r.claims()).map(Arc::new),
+                subject,
+            };
+            let hyper_service =
+                hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                    request.extensions_mut().insert(ConnectInfo(peer.clone()));
+                    tower_service.clone().call(request)
+                });
+
+            let builder = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(stream, hyper_service);
+            pin_mut!(connection);
 
-            let ret = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
-                .serve_connection_with_upgrades(stream, hyper_service)
-                .await;
+            // on shutdown let the response in progress finish, then close
+            let ret = tokio::select! {
+                ret = connection.as_mut() => ret,
+                _ = shutdown.cancelled() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
 
             if let Err(err) = ret {
-                log::warn!("error serving connection from {}: {}", addr, err);
+                log::warn!("error serving connection from {}: {}", address, err);
             }
         });
     }
+
+    debug!(
+        "Not accepting new connections, waiting for {} in flight",
+        connections.len()
+    );
+    while connections.join_next().await.is_some() {}
+
+    Ok(())
 }
@@ -0,0 +1,125 @@
+use rust_rsi::{
+    AttestationClaims, ClaimData, ClaimsMap, CCA_PLAT_IMPLEMENTATION_ID, CCA_PLAT_INSTANCE_ID,
+    CCA_PLAT_PROFILE, CCA_REALM_HASH_ALGO_ID, CCA_REALM_INITIAL_MEASUREMENT,
+    CCA_REALM_PERSONALIZATION_VALUE, CLAIM_COUNT_REALM_EXTENSIBLE_MEASUREMENTS,
+};
+use serde::Serialize;
+
+use crate::error::RegistryError;
+use crate::RegistryResult;
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(RegistryError::Generic(format!($($arg)+))))
+}
+
+/// Identity of an attested realm taken from its CCA token
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Claims
+{
+    pub realm_initial_measurement: String,
+    pub realm_extensible_measurements: Vec<String>,
+    pub realm_personalization_value: String,
+    pub realm_hash_algorithm: String,
+    pub platform_profile: String,
+    pub platform_implementation_id: String,
+    pub platform_instance_id: String,
+}
+
+impl Claims
+{
+    /// Claims of a CCA token (Arm CCA RMM specification), the token has
+    /// already been verified during the handshake, here it's decoded by the
+    /// same parser with no challenge to compare
+    pub fn parse(token: &[u8]) -> RegistryResult<Self>
+    {
+        let attestation: AttestationClaims = match rust_rsi::verify_token(token, None) {
+            Ok(claims) => claims,
+            Err(e) => return err!("Malformed CCA token: {:?}", e),
+        };
+        let realm = &attestation.realm_claims;
+        let platform = &attestation.platform_claims.token_claims;
+
+        // extensible measurements are keyed by their index
+        let realm_extensible_measurements = (0..CLAIM_COUNT_REALM_EXTENSIBLE_MEASUREMENTS)
+            .map(|index| bytes(&realm.measurement_claims, index as u16))
+            .collect::<RegistryResult<_>>()?;
+
+        Ok(Claims {
+            realm_initial_measurement: bytes(
+                &realm.measurement_claims,
+                CCA_REALM_INITIAL_MEASUREMENT,
+            )?,
+            realm_extensible_measurements,
+            realm_personalization_value: bytes(
+                &realm.token_claims,
+                CCA_REALM_PERSONALIZATION_VALUE,
+            )?,
+            realm_hash_algorithm: text(&realm.token_claims, CCA_REALM_HASH_ALGO_ID)?,
+            platform_profile: text(platform, CCA_PLAT_PROFILE)?,
+            platform_implementation_id: bytes(platform, CCA_PLAT_IMPLEMENTATION_ID)?,
+            platform_instance_id: bytes(platform, CCA_PLAT_INSTANCE_ID)?,
+        })
+    }
+}
+
+fn claim(claims: &ClaimsMap, key: u16) -> RegistryResult<&ClaimData>
+{
+    match claims.get(&key) {
+        Some(claim) if claim.present => Ok(&claim.data),
+        _ => err!("Claim {} not found", key),
+    }
+}
+
+// byte strings are compared as hex in the app policies
+fn bytes(claims: &ClaimsMap, key: u16) -> RegistryResult<String>
+{
+    match claim(claims, key)? {
+        ClaimData::Bstr(bytes) => Ok(hex::encode(bytes)),
+        _ => err!("Claim {} is not a byte string", key),
+    }
+}
+
+fn text(claims: &ClaimsMap, key: u16) -> RegistryResult<String>
+{
+    match claim(claims, key)? {
+        ClaimData::Text(text) => Ok(text.clone()),
+        _ => err!("Claim {} is not a text string", key),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::Claims;
+
+    #[test]
+    fn claims_example_token()
+    {
+        let token = std::fs::read("ratls/token.bin").unwrap();
+        let claims = Claims::parse(&token).unwrap();
+
+        assert_eq!(
+            claims.realm_initial_measurement,
+            "08edc51c32a64743ebe0746c79466ad0e6ac84bc2951f53c9220afecf0013d00"
+        );
+        assert_eq!(claims.realm_personalization_value, "00".repeat(64));
+        assert_eq!(
+            claims.realm_extensible_measurements[1],
+            "7d43aefe4c6a955cd0753bccee2e707232d2b44b84c4607ac925597419ac104d"
+        );
+        assert_eq!(claims.realm_hash_algorithm, "sha-256");
+        assert_eq!(claims.platform_profile, "http://arm.com/CCA-SSD/1.0.0");
+        assert_eq!(
+            claims.platform_implementation_id,
+            "7f454c4602010100000000000000000003003e00010000005058000000000000"
+        );
+    }
+
+    #[test]
+    fn claims_truncated_token()
+    {
+        let token = std::fs::read("ratls/token.bin").unwrap();
+        assert!(Claims::parse(&token[..token.len() / 2]).is_err());
+        assert!(Claims::parse(&[]).is_err());
+    }
+}
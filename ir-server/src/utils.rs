@@ -1,5 +1,3 @@
-use std::env;
-use std::fs::canonicalize;
 use std::io::BufReader;
 use std::path::Path;
 use std::{fs::File, io::Read, io::Write};
@@ -12,25 +10,6 @@ macro_rules! err {
     ($($arg:tt)+) => (Err(RegistryError::PrivateKeyParsing(format!($($arg)+))))
 }
 
-pub fn get_crate_root() -> String
-{
-    // try with env variable first, it won't work without cargo run
-    if let Ok(workspace) = env::var("CARGO_MANIFEST_DIR") {
-        return workspace;
-    }
-
-    // tentative, assumes the binary is in target/{build}/ directory
-    let argv0 = env::args().next().unwrap();
-    // unwrap() should be safe as argv0 should exist
-    let mut workspace = canonicalize(Path::new(&argv0)).unwrap();
-    for _ in 1..=3 {
-        workspace.pop();
-    }
-
-    return workspace.to_string_lossy().to_string();
-}
-
-#[allow(dead_code)]
 pub fn file_read<T: AsRef<Path>>(filename: T) -> std::io::Result<Vec<u8>>
 {
     let mut buf = Vec::new();
@@ -73,3 +52,11 @@ where
         _ => err!("More than one PKCS8-encoded private key found in {path}"),
     }
 }
+
+pub fn unix_time() -> u64
+{
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
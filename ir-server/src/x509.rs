@@ -0,0 +1,51 @@
+use x509_cert::der::Decode;
+use x509_cert::Certificate;
+
+use crate::error::RegistryError;
+use crate::RegistryResult;
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(RegistryError::Generic(format!($($arg)+))))
+}
+
+/// Subject of a DER encoded certificate as an RFC 4514 string, e.g.
+/// `CN=client,O=Example\, Inc.,C=PL`. For the usual attributes it's what
+/// `openssl x509 -noout -subject -nameopt RFC2253` prints, `emailAddress` and
+/// `serialNumber` are written as `EMAIL` and `SERIALNUMBER` though.
+pub fn subject(cert: &[u8]) -> RegistryResult<String>
+{
+    match Certificate::from_der(cert) {
+        Ok(cert) => Ok(cert.tbs_certificate.subject.to_string()),
+        Err(e) => err!("Malformed certificate: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::subject;
+    use crate::utils;
+
+    #[test]
+    fn x509_subject()
+    {
+        let certs = utils::load_certificates_from_pem("certs/server.crt").unwrap();
+        assert_eq!(
+            subject(&certs[0]).unwrap(),
+            "O=Internet Widgits Pty Ltd,ST=Karton,C=PL"
+        );
+        assert!(subject(&certs[0][..certs[0].len() / 2]).is_err());
+        assert!(subject(&[]).is_err());
+    }
+
+    #[test]
+    fn x509_subject_escaped()
+    {
+        // multi-valued RDN in the encoding order and a comma in a value
+        let certs = utils::load_certificates_from_pem("certs/client.crt").unwrap();
+        assert_eq!(
+            subject(&certs[0]).unwrap(),
+            "EMAIL=ci@example.com,CN=ci+UID=42,O=Example\\, Inc.,C=PL"
+        );
+    }
+}
@@ -0,0 +1,438 @@
+use log::info;
+use oci_spec::image::{
+    Arch, DescriptorBuilder, ImageIndexBuilder, ImageManifestBuilder, MediaType, OciLayoutBuilder,
+    Os, PlatformBuilder, ANNOTATION_REF_NAME,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use crate::builder::Compression;
+use crate::oci::{BLOBS_SUBDIR, INDEX_JSON, OCI_LAYOUT};
+use crate::{crypto, digest::Digest, error::SignerError, reproducible, utils, SignerResult};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const DOCKER_MANIFEST_JSON: &str = "manifest.json";
+const DIGEST_ALGO: &str = "sha256";
+const OCI_LAYOUT_VERSION: &str = "1.0.0";
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(SignerError::OciRegistry(format!($($arg)+))))
+}
+
+/// Entry of "manifest.json" written by the legacy "docker save"
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerManifest
+{
+    config: String,
+    repo_tags: Option<Vec<String>>,
+    layers: Vec<String>,
+}
+
+/// Part of the image config giving the platform, the whole config is not
+/// parsed as docker ones don't always have all the fields OCI requires
+#[derive(Debug, Deserialize)]
+struct DockerPlatform
+{
+    architecture: Option<String>,
+    os: Option<String>,
+}
+
+/// Reader of a file decompressed with gzip or zstd (detected by magic) if
+/// needed, also returns the compression that was detected
+pub(crate) fn decompress(path: &Path) -> SignerResult<(Box<dyn Read>, Compression)>
+{
+    let mut reader = BufReader::new(File::open(path)?);
+    let magic = reader.fill_buf()?;
+
+    if magic.starts_with(GZIP_MAGIC) {
+        info!("Decompressing gzip archive");
+        Ok((
+            Box::new(flate2::read::GzDecoder::new(reader)),
+            Compression::Gzip,
+        ))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        info!("Decompressing zstd archive");
+        Ok((
+            Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+            Compression::Zstd,
+        ))
+    } else {
+        Ok((Box::new(reader), Compression::None))
+    }
+}
+
+/// Tar archive, plain or compressed with gzip or zstd
+pub(crate) fn open_tar(path: &Path) -> SignerResult<tar::Archive<Box<dyn Read>>>
+{
+    let (reader, _) = decompress(path)?;
+    Ok(tar::Archive::new(reader))
+}
+
+/// Files of the unpacked archive referenced by "manifest.json" with symlinks
+/// resolved, this needs to be done before any of them is moved
+fn resolve_files(
+    unpacked: &Path,
+    manifests: &[DockerManifest],
+) -> SignerResult<HashMap<String, PathBuf>>
+{
+    let root = unpacked.canonicalize()?;
+    let mut files = HashMap::new();
+
+    for docker in manifests {
+        for name in std::iter::once(&docker.config).chain(&docker.layers) {
+            let path = unpacked.join(name).canonicalize()?;
+            if !path.starts_with(&root) {
+                return err!("File \"{}\" points outside of the archive", name);
+            }
+            files.insert(name.clone(), path);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Move a file of the unpacked archive into the blobs, returns its digest and
+/// size. Files can be shared between images or linked to each other so the
+/// ones already moved are remembered.
+fn add_blob(
+    blobs: &Path,
+    path: &Path,
+    added: &mut HashMap<PathBuf, (String, i64)>,
+) -> SignerResult<(String, i64)>
+{
+    if let Some(blob) = added.get(path) {
+        return Ok(blob.clone());
+    }
+
+    let hash = crypto::hash_reader(DIGEST_ALGO, &mut File::open(path)?)?;
+    let digest = Digest::new_unchecked(DIGEST_ALGO.to_string(), hex::encode(hash));
+    let blob_path = blobs.join(digest.to_path());
+    let size = utils::file_len(path)?.try_into().unwrap();
+
+    if !blob_path.exists() {
+        std::fs::rename(path, &blob_path)?;
+    }
+
+    let blob = (String::from(digest), size);
+    added.insert(path.to_path_buf(), blob.clone());
+    Ok(blob)
+}
+
+/// Application name for an archive: its file name without the archive
+/// extensions, e.g. "nginx" for "nginx.tar.gz"
+pub(crate) fn app_name(path: &Path) -> Option<&str>
+{
+    let mut name = path.file_name()?.to_str()?;
+    for ext in [".gz", ".tgz", ".zst", ".tar"] {
+        name = name.strip_suffix(ext).unwrap_or(name);
+    }
+    Some(name)
+}
+
+/// Tag part of a "name:tag" docker reference
+fn docker_tag(repo_tag: &str) -> &str
+{
+    // the name can contain a registry port, the tag can't contain a slash
+    match repo_tag.rsplit_once(':') {
+        Some((_, tag)) if !tag.contains('/') => tag,
+        _ => "latest",
+    }
+}
+
+/// Convert the legacy "docker save" layout (manifest.json, config and
+/// ID/layer.tar files) into an OCI image layout
+fn convert_docker_save(unpacked: &Path, app_dir: &Path) -> SignerResult<()>
+{
+    info!("Converting docker save layout into OCI image layout");
+
+    let manifests: Vec<DockerManifest> =
+        serde_json::from_slice(&utils::file_read(unpacked.join(DOCKER_MANIFEST_JSON))?)?;
+
+    let blobs = app_dir.join(BLOBS_SUBDIR);
+    std::fs::create_dir_all(blobs.join(DIGEST_ALGO))?;
+
+    let files = resolve_files(unpacked, &manifests)?;
+    let mut added = HashMap::new();
+    let mut descriptors = Vec::new();
+    // the repository is dropped, tags of different ones must not collide
+    let mut tagged_from = HashMap::new();
+
+    for docker in manifests {
+        let (config_digest, config_size) = add_blob(&blobs, &files[&docker.config], &mut added)?;
+        let config_path = blobs.join(Digest::try_from(&config_digest)?.to_path());
+        let platform: DockerPlatform = serde_json::from_slice(&utils::file_read(config_path)?)?;
+        let config_desc = DescriptorBuilder::default()
+            .media_type(MediaType::ImageConfig)
+            .digest(config_digest)
+            .size(config_size)
+            .build()?;
+
+        let mut layers = Vec::new();
+        for layer in &docker.layers {
+            // docker save layers are plain tars
+            let (layer_digest, layer_size) = add_blob(&blobs, &files[layer], &mut added)?;
+            layers.push(
+                DescriptorBuilder::default()
+                    .media_type(MediaType::ImageLayer)
+                    .digest(layer_digest)
+                    .size(layer_size)
+                    .build()?,
+            );
+        }
+
+        let manifest = ImageManifestBuilder::default()
+            .schema_version(2u32)
+            .media_type(MediaType::ImageManifest)
+            .config(config_desc)
+            .layers(layers)
+            .build()?;
+        let manifest = reproducible::to_json(&manifest)?;
+        let hash = crypto::hash_reader(DIGEST_ALGO, &mut manifest.as_slice())?;
+        let digest = Digest::new_unchecked(DIGEST_ALGO.to_string(), hex::encode(hash));
+        utils::file_write(blobs.join(digest.to_path()), &manifest)?;
+        info!("Created manifest \"{}\"", digest);
+
+        let mut desc = DescriptorBuilder::default()
+            .media_type(MediaType::ImageManifest)
+            .digest(String::from(digest))
+            .size(i64::try_from(manifest.len()).unwrap())
+            .build()?;
+        if let (Some(architecture), Some(os)) = (platform.architecture, platform.os) {
+            desc.set_platform(Some(
+                PlatformBuilder::default()
+                    .architecture(Arch::from(architecture.as_str()))
+                    .os(Os::from(os.as_str()))
+                    .build()?,
+            ));
+        }
+
+        // one descriptor per tag, untagged images are still kept
+        let repo_tags = docker.repo_tags.unwrap_or_default();
+        if repo_tags.is_empty() {
+            descriptors.push(desc.clone());
+        }
+        for repo_tag in repo_tags {
+            let tag = docker_tag(&repo_tag);
+            if let Some(other) = tagged_from.insert(tag.to_string(), repo_tag.clone()) {
+                return err!(
+                    "Tags \"{}\" and \"{}\" both map to \"{}\", save one repository per archive",
+                    other,
+                    repo_tag,
+                    tag
+                );
+            }
+            info!("Tagging \"{}\" as \"{}\"", repo_tag, tag);
+            let mut tagged = desc.clone();
+            tagged.set_annotations(Some(HashMap::from([(
+                ANNOTATION_REF_NAME.to_string(),
+                tag.to_string(),
+            )])));
+            descriptors.push(tagged);
+        }
+    }
+
+    let index = ImageIndexBuilder::default()
+        .schema_version(2u32)
+        .media_type(MediaType::ImageIndex)
+        .manifests(descriptors)
+        .build()?;
+    reproducible::write_json(app_dir.join(INDEX_JSON), &index)?;
+
+    OciLayoutBuilder::default()
+        .image_layout_version(OCI_LAYOUT_VERSION)
+        .build()?
+        .to_file(app_dir.join(OCI_LAYOUT))?;
+
+    Ok(())
+}
+
+fn unpack(filename: &Path, unpacked: &Path) -> SignerResult<()>
+{
+    open_tar(filename)?.unpack(unpacked)?;
+    Ok(())
+}
+
+fn import_unpacked(unpacked: &Path, app_dir: &Path) -> SignerResult<()>
+{
+    if unpacked.join(OCI_LAYOUT).is_file() {
+        std::fs::rename(unpacked, app_dir)?;
+        return Ok(());
+    }
+
+    if !unpacked.join(DOCKER_MANIFEST_JSON).is_file() {
+        return err!("Archive is neither an OCI image layout nor a docker save output");
+    }
+
+    std::fs::create_dir(app_dir)?;
+    let result = convert_docker_save(unpacked, app_dir);
+    if result.is_err() {
+        let _ = std::fs::remove_dir_all(app_dir);
+    }
+    result
+}
+
+/// Unpack an OCI image layout or a "docker save" archive, plain or compressed,
+/// into a new application directory
+pub(crate) fn import(filename: &Path, app_dir: &Path) -> SignerResult<()>
+{
+    if app_dir.exists() {
+        return err!("Application \"{}\" already exists", app_dir.display());
+    }
+
+    let mut unpacked = app_dir.as_os_str().to_owned();
+    unpacked.push(".unpack");
+    let unpacked = PathBuf::from(unpacked);
+    std::fs::create_dir(&unpacked)?;
+
+    let result = unpack(filename, &unpacked).and_then(|_| import_unpacked(&unpacked, app_dir));
+
+    // renamed to APP_DIR for OCI image layout, leftovers of docker save
+    if unpacked.exists() {
+        std::fs::remove_dir_all(&unpacked)?;
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests
+{
+    use oci_spec::image::{ImageIndex, ImageManifest, ANNOTATION_REF_NAME};
+    use serde_json::json;
+    use std::io::{Read, Write};
+    use std::path::{Path, PathBuf};
+
+    use super::{app_name, decompress, docker_tag, import};
+    use crate::builder::Compression;
+    use crate::digest::Digest;
+    use crate::oci::{BLOBS_SUBDIR, INDEX_JSON, OCI_LAYOUT};
+
+    const CONTENT: &[u8] = b"archive content";
+
+    fn temp_dir(name: &str) -> PathBuf
+    {
+        let dir = std::env::temp_dir().join(format!("ir-sign-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn append(tar: &mut tar::Builder<std::fs::File>, name: &str, data: &[u8])
+    {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, name, data).unwrap();
+    }
+
+    /// "docker save" archive of a single image with given tags
+    fn docker_save(path: &Path, repo_tags: &[&str])
+    {
+        let manifest = json!([{
+            "Config": "config.json",
+            "RepoTags": repo_tags,
+            "Layers": ["abc/layer.tar"],
+        }]);
+        let mut tar = tar::Builder::new(std::fs::File::create(path).unwrap());
+        append(&mut tar, "manifest.json", manifest.to_string().as_bytes());
+        append(&mut tar, "config.json", br#"{"architecture":"arm64","os":"linux"}"#);
+        append(&mut tar, "abc/layer.tar", CONTENT);
+        tar.finish().unwrap();
+    }
+
+    #[test]
+    fn archive_decompress()
+    {
+        let dir = temp_dir("archive-decompress");
+
+        let plain = dir.join("plain");
+        std::fs::write(&plain, CONTENT).unwrap();
+        let gzip = dir.join("gzip");
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&gzip).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(CONTENT).unwrap();
+        encoder.finish().unwrap();
+        let zstd = dir.join("zstd");
+        std::fs::write(&zstd, zstd::encode_all(CONTENT, 0).unwrap()).unwrap();
+
+        for (path, compression) in [
+            (plain, Compression::None),
+            (gzip, Compression::Gzip),
+            (zstd, Compression::Zstd),
+        ] {
+            let (mut reader, detected) = decompress(&path).unwrap();
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content).unwrap();
+            assert_eq!(detected, compression);
+            assert_eq!(content, CONTENT);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn archive_names()
+    {
+        assert_eq!(app_name(Path::new("/tmp/nginx.tar.gz")), Some("nginx"));
+        assert_eq!(app_name(Path::new("nginx.tar.zst")), Some("nginx"));
+        assert_eq!(app_name(Path::new("nginx.tgz")), Some("nginx"));
+        assert_eq!(docker_tag("nginx:v1"), "v1");
+        assert_eq!(docker_tag("localhost:5000/nginx"), "latest");
+        assert_eq!(docker_tag("localhost:5000/nginx:v2"), "v2");
+    }
+
+    #[test]
+    fn archive_docker_save()
+    {
+        let dir = temp_dir("archive-docker-save");
+        let archive = dir.join("image.tar");
+        docker_save(&archive, &["a/img:latest", "localhost:5000/a/img:v1"]);
+
+        let app = dir.join("image");
+        import(&archive, &app).unwrap();
+        assert!(app.join(OCI_LAYOUT).is_file());
+        assert!(!dir.join("image.unpack").exists());
+
+        let index = ImageIndex::from_file(app.join(INDEX_JSON)).unwrap();
+        let mut tags = Vec::new();
+        for desc in index.manifests() {
+            let annotations = desc.annotations().as_ref().unwrap();
+            tags.push(annotations[ANNOTATION_REF_NAME].clone());
+            assert_eq!(desc.digest(), index.manifests()[0].digest());
+        }
+        assert_eq!(tags, ["latest", "v1"]);
+
+        let blob = |digest: &str| {
+            let digest = Digest::try_from(digest).unwrap();
+            app.join(BLOBS_SUBDIR).join(digest.to_path())
+        };
+        let manifest = ImageManifest::from_file(blob(index.manifests()[0].digest())).unwrap();
+        let layer = blob(manifest.layers()[0].digest());
+        assert_eq!(std::fs::read(layer).unwrap(), CONTENT);
+        assert!(blob(manifest.config().digest()).is_file());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn archive_docker_save_tag_collision()
+    {
+        let dir = temp_dir("archive-tag-collision");
+        let archive = dir.join("image.tar");
+        docker_save(&archive, &["a/img:latest", "b/img:latest"]);
+
+        let app = dir.join("image");
+        assert!(import(&archive, &app).is_err());
+        assert!(!app.exists());
+        assert!(!dir.join("image.unpack").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,743 @@
+use clap::ValueEnum;
+use log::info;
+use oci_spec::image::{
+    Arch, ConfigBuilder, Descriptor, DescriptorBuilder, HistoryBuilder, ImageConfigurationBuilder,
+    ImageIndexBuilder, ImageManifest, ImageManifestBuilder, MediaType, OciLayoutBuilder, Os,
+    PlatformBuilder, RootFsBuilder, ANNOTATION_REF_NAME,
+};
+use serde_json::Value;
+use sha2::{Digest as _, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+use crate::cosign;
+use crate::digest::Digest;
+use crate::oci::{self, BLOBS_SUBDIR, INDEX_JSON, OCI_LAYOUT};
+use crate::signature::ImageSignature;
+use crate::transaction::Transaction;
+use crate::{archive, reproducible};
+use crate::{error::SignerError, SignerResult};
+
+const DIGEST_ALGO: &str = "sha256";
+const OCI_LAYOUT_VERSION: &str = "1.0.0";
+const CREATED_BY: &str = "ir-sign build";
+const APPENDED_BY: &str = "ir-sign append-layer";
+// layers don't depend on who built them, nor when (see reproducible::mtime())
+const LAYER_UID: u64 = 0;
+const LAYER_GID: u64 = 0;
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(SignerError::OciRegistry(format!($($arg)+))))
+}
+
+/// Compression of the built layers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Compression
+{
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression
+{
+    fn media_type(&self) -> MediaType
+    {
+        match self {
+            Compression::None => MediaType::ImageLayer,
+            Compression::Gzip => MediaType::ImageLayerGzip,
+            Compression::Zstd => MediaType::ImageLayerZstd,
+        }
+    }
+}
+
+/// Runtime configuration of a built image
+pub(crate) struct ImageSpec<'a>
+{
+    pub entrypoint: &'a [String],
+    pub cmd: &'a [String],
+    /// "NAME=VALUE"
+    pub env: &'a [String],
+    /// "NAME=VALUE"
+    pub labels: &'a [String],
+    pub working_dir: Option<&'a str>,
+    pub architecture: &'a str,
+    pub os: &'a str,
+}
+
+/// Layer written into the blobs
+pub(crate) struct Layer
+{
+    pub descriptor: Descriptor,
+    /// Digest of the uncompressed tar as used in the config "rootfs.diff_ids"
+    pub diff_id: String,
+}
+
+/// Writer computing the digest and size of what goes through it
+struct HashWriter<W: Write>
+{
+    inner: W,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<W: Write> HashWriter<W>
+{
+    fn new(inner: W) -> Self
+    {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            len: 0,
+        }
+    }
+
+    fn finish(self) -> (W, String, u64)
+    {
+        let hash = hex::encode(self.hasher.finalize());
+        let digest = Digest::new_unchecked(DIGEST_ALGO.to_string(), hash);
+        (self.inner, digest.into(), self.len)
+    }
+}
+
+impl<W: Write> Write for HashWriter<W>
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>
+    {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()>
+    {
+        self.inner.flush()
+    }
+}
+
+fn split_pair(pair: &str) -> SignerResult<(String, String)>
+{
+    match pair.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => err!("Expected NAME=VALUE, got \"{}\"", pair),
+    }
+}
+
+fn tar_header(metadata: &std::fs::Metadata, entry_type: tar::EntryType, mtime: u64) -> tar::Header
+{
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_mode(metadata.permissions().mode() & 0o7777);
+    header.set_uid(LAYER_UID);
+    header.set_gid(LAYER_GID);
+    header.set_mtime(mtime);
+    header.set_size(0);
+    header
+}
+
+/// Add a directory to the tar recursively, entries sorted by name and with
+/// normalized owners and times
+fn append_dir<W: Write>(
+    tar: &mut tar::Builder<W>,
+    root: &Path,
+    dir: &Path,
+    mtime: u64,
+) -> SignerResult<()>
+{
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<PathBuf>, _>>()?;
+    entries.sort();
+
+    for path in entries {
+        // unwrap() is safe, all the entries are under the root
+        let name = path.strip_prefix(root).unwrap();
+        let metadata = std::fs::symlink_metadata(&path)?;
+        let file_type = metadata.file_type();
+
+        if file_type.is_dir() {
+            let mut header = tar_header(&metadata, tar::EntryType::Directory, mtime);
+            tar.append_data(&mut header, name, std::io::empty())?;
+            append_dir(tar, root, &path, mtime)?;
+        } else if file_type.is_file() {
+            let mut header = tar_header(&metadata, tar::EntryType::Regular, mtime);
+            header.set_size(metadata.len());
+            tar.append_data(&mut header, name, File::open(&path)?)?;
+        } else if file_type.is_symlink() {
+            let mut header = tar_header(&metadata, tar::EntryType::Symlink, mtime);
+            tar.append_link(&mut header, name, std::fs::read_link(&path)?)?;
+        } else {
+            return err!("Unsupported file type: \"{}\"", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn write_tar<W: Write>(rootfs: &Path, writer: W) -> SignerResult<W>
+{
+    let mut tar = tar::Builder::new(writer);
+    append_dir(&mut tar, rootfs, rootfs, reproducible::mtime()?)?;
+    Ok(tar.into_inner()?)
+}
+
+/// Layer being written, next to the blobs so that a leftover of a failed build
+/// is never taken for one, and on the same filesystem to be renamed into place.
+/// The name is unique so concurrent builds of one application don't collide.
+fn layer_tmp_file(blobs: &Path) -> SignerResult<NamedTempFile>
+{
+    let dir = blobs.parent().unwrap_or(blobs);
+    Ok(tempfile::Builder::new()
+        .prefix("layer")
+        .suffix(".tmp")
+        .tempfile_in(dir)?)
+}
+
+/// Move a layer written into the temporary file to its place in the blobs
+fn store_layer(
+    blobs: &Path,
+    file: HashWriter<NamedTempFile>,
+    diff_id: String,
+    compression: Compression,
+) -> SignerResult<Layer>
+{
+    let (file, digest, size) = file.finish();
+    file.as_file().sync_all()?;
+
+    // blobs are content addressed, an existing one is the same layer
+    let blob_path = blobs.join(Digest::try_from(&digest)?.to_path());
+    file.persist(blob_path).map_err(|e| e.error)?;
+    info!("Created layer \"{}\" ({:?})", digest, compression);
+
+    let descriptor = DescriptorBuilder::default()
+        .media_type(compression.media_type())
+        .digest(digest)
+        .size(i64::try_from(size).unwrap())
+        .build()?;
+
+    Ok(Layer {
+        descriptor,
+        diff_id,
+    })
+}
+
+/// Create a layer from a directory and write it into the blobs, the tar is
+/// compressed and hashed on the fly
+pub(crate) fn create_layer(
+    blobs: &Path,
+    rootfs: &Path,
+    compression: Compression,
+) -> SignerResult<Layer>
+{
+    if !rootfs.is_dir() {
+        return err!("\"{}\" is not a directory", rootfs.display());
+    }
+
+    let file = HashWriter::new(layer_tmp_file(blobs)?);
+
+    let (diff_id, file) = match compression {
+        Compression::None => {
+            let tar = write_tar(rootfs, HashWriter::new(file))?;
+            let (file, diff_id, _) = tar.finish();
+            (diff_id, file)
+        }
+        Compression::Gzip => {
+            let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+            let tar = write_tar(rootfs, HashWriter::new(encoder))?;
+            let (encoder, diff_id, _) = tar.finish();
+            (diff_id, encoder.finish()?)
+        }
+        Compression::Zstd => {
+            let encoder = zstd::stream::write::Encoder::new(file, 0)?;
+            let tar = write_tar(rootfs, HashWriter::new(encoder))?;
+            let (encoder, diff_id, _) = tar.finish();
+            (diff_id, encoder.finish()?)
+        }
+    };
+
+    store_layer(blobs, file, diff_id, compression)
+}
+
+/// Copy a tarball into the blobs as a layer, it's kept compressed as it is
+pub(crate) fn import_layer(blobs: &Path, tarball: &Path) -> SignerResult<Layer>
+{
+    // make sure it is a tar before it becomes a part of an image
+    let mut tar = archive::open_tar(tarball)?;
+    if tar.entries()?.any(|entry| entry.is_err()) {
+        return err!("\"{}\" is not a tar archive", tarball.display());
+    }
+
+    let (mut reader, compression) = archive::decompress(tarball)?;
+    let mut tar = HashWriter::new(std::io::sink());
+    std::io::copy(&mut reader, &mut tar)?;
+    let (_, diff_id, _) = tar.finish();
+
+    let mut file = HashWriter::new(layer_tmp_file(blobs)?);
+    std::io::copy(&mut File::open(tarball)?, &mut file)?;
+
+    store_layer(blobs, file, diff_id, compression)
+}
+
+/// Create an empty application with an OCI image layout
+fn create_app(app: &Path) -> SignerResult<()>
+{
+    info!("Creating application: \"{}\"", app.display());
+
+    std::fs::create_dir_all(app.join(BLOBS_SUBDIR).join(DIGEST_ALGO))?;
+    OciLayoutBuilder::default()
+        .image_layout_version(OCI_LAYOUT_VERSION)
+        .build()?
+        .to_file(app.join(OCI_LAYOUT))?;
+    let index = ImageIndexBuilder::default()
+        .schema_version(2u32)
+        .media_type(MediaType::ImageIndex)
+        .manifests(Vec::new())
+        .build()?;
+    reproducible::write_json(app.join(INDEX_JSON), &index)?;
+
+    Ok(())
+}
+
+/// Point a tag in "index.json" to a manifest, the tag is moved if it exists
+pub(crate) fn tag_manifest(tx: &mut Transaction, tag: &str, mut manifest: Descriptor)
+{
+    let mut annotations = manifest.annotations().clone().unwrap_or_default();
+    annotations.insert(ANNOTATION_REF_NAME.to_string(), tag.to_string());
+    manifest.set_annotations(Some(annotations));
+
+    let mut manifests: Vec<Descriptor> = tx
+        .index()
+        .manifests()
+        .iter()
+        .filter(|desc| {
+            desc.annotations()
+                .as_ref()
+                .and_then(|anns| anns.get(ANNOTATION_REF_NAME))
+                .map(|t| t.as_str())
+                != Some(tag)
+        })
+        .cloned()
+        .collect();
+    manifests.push(manifest);
+    tx.index_mut().set_manifests(manifests);
+
+    info!("Manifest tagged as: \"{}\"", tag);
+}
+
+/// Build an image with a single layer made of a root filesystem directory
+/// and tag it in an application, which is created if needed. Returns the
+/// digest of the manifest.
+pub(crate) fn build_image(
+    app: &Path,
+    tag: &str,
+    rootfs: &Path,
+    spec: &ImageSpec,
+    compression: Compression,
+) -> SignerResult<String>
+{
+    if !app.join(OCI_LAYOUT).is_file() {
+        create_app(app)?;
+    }
+
+    let mut tx = Transaction::open(app, false)?;
+    let layer = create_layer(&tx.blobs(), rootfs, compression)?;
+
+    for pair in spec.env {
+        split_pair(pair)?;
+    }
+    let labels = spec
+        .labels
+        .iter()
+        .map(|l| split_pair(l))
+        .collect::<SignerResult<HashMap<_, _>>>()?;
+
+    let mut config = ConfigBuilder::default();
+    if !spec.env.is_empty() {
+        config = config.env(spec.env.to_vec());
+    }
+    if !labels.is_empty() {
+        config = config.labels(labels);
+    }
+    if !spec.entrypoint.is_empty() {
+        config = config.entrypoint(spec.entrypoint.to_vec());
+    }
+    if !spec.cmd.is_empty() {
+        config = config.cmd(spec.cmd.to_vec());
+    }
+    if let Some(working_dir) = spec.working_dir {
+        config = config.working_dir(working_dir);
+    }
+
+    let created = reproducible::created()?;
+    let mut history = HistoryBuilder::default().created_by(CREATED_BY).build()?;
+    history.set_created(created.clone());
+
+    let mut config = ImageConfigurationBuilder::default()
+        .architecture(Arch::from(spec.architecture))
+        .os(Os::from(spec.os))
+        .config(config.build()?)
+        .rootfs(
+            RootFsBuilder::default()
+                .typ("layers")
+                .diff_ids(vec![layer.diff_id.clone()])
+                .build()?,
+        )
+        .history(vec![history])
+        .build()?;
+    config.set_created(created);
+    let (config_digest, config_size) =
+        tx.write_blob(DIGEST_ALGO, &reproducible::to_json(&config)?)?;
+
+    let manifest = ImageManifestBuilder::default()
+        .schema_version(2u32)
+        .media_type(MediaType::ImageManifest)
+        .config(
+            DescriptorBuilder::default()
+                .media_type(MediaType::ImageConfig)
+                .digest(config_digest)
+                .size(config_size)
+                .build()?,
+        )
+        .layers(vec![layer.descriptor])
+        .build()?;
+    let (manifest_digest, manifest_size) =
+        tx.write_blob(DIGEST_ALGO, &reproducible::to_json(&manifest)?)?;
+    info!("Created manifest \"{}\"", manifest_digest);
+
+    let descriptor = DescriptorBuilder::default()
+        .media_type(MediaType::ImageManifest)
+        .digest(manifest_digest.clone())
+        .size(manifest_size)
+        .platform(
+            PlatformBuilder::default()
+                .architecture(Arch::from(spec.architecture))
+                .os(Os::from(spec.os))
+                .build()?,
+        )
+        .build()?;
+    tag_manifest(&mut tx, tag, descriptor);
+    tx.commit()?;
+
+    Ok(manifest_digest)
+}
+
+/// Add a layer to the config "rootfs.diff_ids" and "history". The config is
+/// not parsed with oci-spec as docker ones don't always have all the fields
+/// OCI requires.
+fn append_config(config: &mut Value, diff_id: &str) -> SignerResult<()>
+{
+    let Some(config) = config.as_object_mut() else {
+        return err!("Config is not a JSON object");
+    };
+
+    let diff_ids = config
+        .get_mut("rootfs")
+        .and_then(|rootfs| rootfs.get_mut("diff_ids"))
+        .and_then(|diff_ids| diff_ids.as_array_mut());
+    let Some(diff_ids) = diff_ids else {
+        return err!("Config has no \"rootfs.diff_ids\"");
+    };
+    diff_ids.push(Value::from(diff_id));
+
+    let created = reproducible::created()?;
+    let mut history = HistoryBuilder::default().created_by(APPENDED_BY).build()?;
+    history.set_created(created.clone());
+    let history_entries = config
+        .entry("history")
+        .or_insert_with(|| Value::Array(Vec::new()));
+    let Some(history_entries) = history_entries.as_array_mut() else {
+        return err!("Config \"history\" is not an array");
+    };
+    history_entries.push(serde_json::to_value(history)?);
+
+    if let Some(created) = created {
+        config.insert("created".to_string(), Value::from(created));
+    }
+
+    Ok(())
+}
+
+/// Append a layer made of a directory or a tarball to an image. With a tag the
+/// new image is tagged and the old one kept, otherwise the new image replaces
+/// the old one in all the indexes. Signatures of the old image are dropped as
+/// the config they cover changes, so is its cosign signature manifest when the
+/// old image is replaced. Returns the digest of the new manifest.
+pub(crate) fn append_layer(
+    app: &Path,
+    reference: &str,
+    path: &Path,
+    tag: Option<&str>,
+    compression: Compression,
+) -> SignerResult<String>
+{
+    let digest = oci::find_manifest_by_reference(app, reference)?;
+    let mut tx = Transaction::open(app, false)?;
+    let mut manifest = ImageManifest::from_reader(tx.read_blob(&digest)?.as_slice())?;
+
+    let layer = if path.is_dir() {
+        create_layer(&tx.blobs(), path, compression)?
+    } else if path.is_file() {
+        import_layer(&tx.blobs(), path)?
+    } else {
+        return err!(
+            "\"{}\" is neither a directory nor a tarball",
+            path.display()
+        );
+    };
+
+    let mut config: Value = serde_json::from_slice(&tx.read_blob(manifest.config().digest())?)?;
+    append_config(&mut config, &layer.diff_id)?;
+    // the old config is kept, other images can share it
+    let (config_digest, config_size) =
+        tx.write_blob(DIGEST_ALGO, &reproducible::to_json(&config)?)?;
+    info!("Created config \"{}\"", config_digest);
+
+    let mut config_desc = manifest.config().clone();
+    config_desc.set_digest(config_digest);
+    config_desc.set_size(config_size);
+    manifest.set_config(config_desc);
+
+    let mut layers = manifest.layers().clone();
+    layers.push(layer.descriptor);
+    manifest.set_layers(layers);
+
+    if let Some(annotations) = manifest.annotations_mut() {
+        let count = annotations.len();
+        ImageSignature::remove_all(annotations);
+        if annotations.len() != count {
+            info!("Signatures of the image dropped, it needs to be signed again");
+        }
+        if annotations.is_empty() {
+            manifest.set_annotations(None);
+        }
+    }
+
+    let (manifest_digest, manifest_size) =
+        tx.write_blob(DIGEST_ALGO, &reproducible::to_json(&manifest)?)?;
+    info!("Created manifest \"{}\"", manifest_digest);
+
+    match tag {
+        Some(tag) => {
+            // keep the platform and annotations of the old image
+            let descriptor = tx
+                .index()
+                .manifests()
+                .iter()
+                .find(|desc| desc.digest() == &digest)
+                .cloned();
+            let mut descriptor = match descriptor {
+                Some(descriptor) => descriptor,
+                None => DescriptorBuilder::default()
+                    .media_type(MediaType::ImageManifest)
+                    .digest(manifest_digest.clone())
+                    .size(manifest_size)
+                    .build()?,
+            };
+            descriptor.set_digest(manifest_digest.clone());
+            descriptor.set_size(manifest_size);
+            tag_manifest(&mut tx, tag, descriptor);
+        }
+        None => {
+            oci::replace_manifest(&mut tx, &digest, &manifest_digest, manifest_size)?;
+            if cosign::remove_signature(&mut tx, &digest)? {
+                info!("Cosign signature of the image dropped, it needs to be signed again");
+            }
+        }
+    }
+    tx.commit()?;
+
+    Ok(manifest_digest)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use oci_spec::image::{ImageConfiguration, ImageManifest, MediaType};
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+
+    use super::{append_layer, build_image, Compression, ImageSpec, DIGEST_ALGO};
+    use crate::cosign;
+    use crate::crypto::{self, KeyAlgorithm};
+    use crate::digest::Digest;
+    use crate::oci::{self, BLOBS_SUBDIR};
+    use crate::reproducible;
+    use crate::signature::ImageSignature;
+    use crate::transaction::Transaction;
+
+    const SPEC: ImageSpec<'static> = ImageSpec {
+        entrypoint: &[],
+        cmd: &[],
+        env: &[],
+        labels: &[],
+        working_dir: Some("/app"),
+        architecture: "arm64",
+        os: "linux",
+    };
+
+    fn temp_dir(name: &str) -> PathBuf
+    {
+        let dir = std::env::temp_dir().join(format!("ir-sign-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("rootfs").join("bin")).unwrap();
+        std::fs::write(dir.join("rootfs").join("bin").join("app"), b"binary").unwrap();
+        dir
+    }
+
+    fn blob(app: &Path, digest: &str) -> PathBuf
+    {
+        app.join(BLOBS_SUBDIR)
+            .join(Digest::try_from(digest).unwrap().to_path())
+    }
+
+    fn layer_files(app: &Path, manifest: &ImageManifest, layer: usize) -> Vec<String>
+    {
+        let desc = &manifest.layers()[layer];
+        let file = std::fs::File::open(blob(app, desc.digest())).unwrap();
+        let reader: Box<dyn Read> = match desc.media_type() {
+            MediaType::ImageLayerGzip => Box::new(flate2::read::GzDecoder::new(file)),
+            MediaType::ImageLayerZstd => Box::new(zstd::stream::read::Decoder::new(file).unwrap()),
+            _ => Box::new(file),
+        };
+        tar::Archive::new(reader)
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn builder_build_image()
+    {
+        let dir = temp_dir("builder-build");
+        let rootfs = dir.join("rootfs");
+
+        for (app, compression, media_type) in [
+            ("plain", Compression::None, MediaType::ImageLayer),
+            ("gzip", Compression::Gzip, MediaType::ImageLayerGzip),
+            ("zstd", Compression::Zstd, MediaType::ImageLayerZstd),
+        ] {
+            let app = dir.join(app);
+            let digest = build_image(&app, "v1", &rootfs, &SPEC, compression).unwrap();
+            assert_eq!(oci::find_manifest_by_reference(&app, "v1").unwrap(), digest);
+
+            let manifest = ImageManifest::from_file(blob(&app, &digest)).unwrap();
+            assert_eq!(manifest.layers().len(), 1);
+            assert_eq!(manifest.layers()[0].media_type(), &media_type);
+            assert_eq!(layer_files(&app, &manifest, 0), ["bin", "bin/app"]);
+
+            let config = ImageConfiguration::from_file(blob(&app, manifest.config().digest()));
+            let config = config.unwrap();
+            assert_eq!(config.rootfs().diff_ids().len(), 1);
+            let working_dir = config.config().as_ref().unwrap().working_dir().clone();
+            assert_eq!(working_dir.as_deref(), Some("/app"));
+
+            // no temporary layer is left, only the blobs are in the blobs directory
+            for entry in std::fs::read_dir(&app).unwrap() {
+                let name = entry.unwrap().file_name().into_string().unwrap();
+                assert!(!name.ends_with(".tmp"), "leftover \"{}\"", name);
+            }
+            for entry in std::fs::read_dir(app.join(BLOBS_SUBDIR).join(DIGEST_ALGO)).unwrap() {
+                let name = entry.unwrap().file_name().into_string().unwrap();
+                Digest::try_from(format!("{}:{}", DIGEST_ALGO, name).as_str()).unwrap();
+            }
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Sign an image with a fake signature and add annotations of its own
+    fn annotate(app: &Path, digest: &str) -> String
+    {
+        let mut tx = Transaction::open(app, false).unwrap();
+        let mut manifest = ImageManifest::from_reader(tx.read_blob(digest).unwrap().as_slice());
+        let manifest = manifest.as_mut().unwrap();
+
+        let mut annotations = HashMap::from([
+            ("com.samsung.islet.image.description".to_string(), "app".to_string()),
+            ("org.opencontainers.image.source".to_string(), "git".to_string()),
+        ]);
+        let signature = ImageSignature {
+            config_sign: vec![1],
+            vendor_pub: vec![2],
+            vendor_pub_sign: vec![3],
+            timestamp: None,
+            expiry: None,
+            algorithm: None,
+        };
+        signature.write(&mut annotations, 0);
+        signature.write(&mut annotations, 1);
+        manifest.set_annotations(Some(annotations));
+
+        let json = reproducible::to_json(manifest).unwrap();
+        let (new_digest, size) = tx.write_blob(DIGEST_ALGO, &json).unwrap();
+        oci::replace_manifest(&mut tx, digest, &new_digest, size).unwrap();
+        tx.commit().unwrap();
+        new_digest
+    }
+
+    #[test]
+    fn builder_append_layer()
+    {
+        let dir = temp_dir("builder-append");
+        let app = dir.join("app");
+        let digest = build_image(&app, "v1", &dir.join("rootfs"), &SPEC, Compression::None);
+        let digest = annotate(&app, &digest.unwrap());
+        let signed = ImageManifest::from_file(blob(&app, &digest)).unwrap();
+
+        let extra = dir.join("extra");
+        std::fs::create_dir_all(&extra).unwrap();
+        std::fs::write(extra.join("data"), b"data").unwrap();
+
+        // tagged: the old image is kept as it was
+        let tagged = append_layer(&app, "v1", &extra, Some("v2"), Compression::Zstd).unwrap();
+        assert_eq!(oci::find_manifest_by_reference(&app, "v1").unwrap(), digest);
+        assert_eq!(oci::find_manifest_by_reference(&app, "v2").unwrap(), tagged);
+        assert!(blob(&app, &digest).is_file());
+
+        let manifest = ImageManifest::from_file(blob(&app, &tagged)).unwrap();
+        assert_eq!(manifest.layers().len(), 2);
+        assert_eq!(manifest.layers()[0], signed.layers()[0]);
+        assert_eq!(layer_files(&app, &manifest, 1), ["data"]);
+        let config = ImageConfiguration::from_file(blob(&app, manifest.config().digest()));
+        let config = config.unwrap();
+        assert_eq!(config.rootfs().diff_ids().len(), 2);
+        assert_eq!(config.history().len(), 2);
+
+        // the signatures are dropped, the other annotations kept
+        let annotations = manifest.annotations().as_ref().unwrap();
+        let mut keys: Vec<&str> = annotations.keys().map(|k| k.as_str()).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "com.samsung.islet.image.description",
+                "org.opencontainers.image.source",
+            ]
+        );
+
+        // untagged: the image is replaced along with its cosign signature
+        let key = crypto::generate_key(KeyAlgorithm::EcdsaP256).unwrap();
+        let mut tx = Transaction::open(&app, false).unwrap();
+        cosign::sign_manifest(&mut tx, &digest, "app:v1", key.as_ref(), &[4], None).unwrap();
+        tx.commit().unwrap();
+        assert!(cosign::is_signed(app.join(BLOBS_SUBDIR), &digest).unwrap());
+        let sig_tag = format!("{}.sig", digest.replace(':', "-"));
+        let sig = oci::find_manifest_by_reference(&app, &sig_tag).unwrap();
+
+        let replaced = append_layer(&app, "v1", &extra, None, Compression::None).unwrap();
+        assert_eq!(oci::find_manifest_by_reference(&app, "v1").unwrap(), replaced);
+        assert!(!blob(&app, &digest).exists());
+        assert!(!cosign::is_signed(app.join(BLOBS_SUBDIR), &digest).unwrap());
+        assert!(!blob(&app, &sig).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
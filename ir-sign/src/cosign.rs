@@ -0,0 +1,395 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ir_verify::annotation::{annotation, SIGNATURE_EXPIRY, SIGNATURE_TIMESTAMP};
+use log::{error, info};
+use oci_spec::image::{
+    Descriptor, DescriptorBuilder, ImageIndex, ImageManifest, ImageManifestBuilder, MediaType,
+    ANNOTATION_REF_NAME,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::crypto::PrivateKey;
+use crate::oci::{VerifyPolicy, INDEX_JSON};
+use crate::signature::ImageSignature;
+use crate::transaction::Transaction;
+use crate::{crypto, digest::Digest, error::SignerError, utils, SignerResult};
+
+// https://github.com/sigstore/cosign/blob/main/specs/SIGNATURE_SPEC.md
+const SIMPLESIGNING_MEDIA_TYPE: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+const SIMPLESIGNING_TYPE: &str = "cosign container image signature";
+const ANNOTATION_COSIGN_SIGNATURE: &str = "dev.cosignproject.cosign/signature";
+const COSIGN_DIGEST_ALGO: &str = "sha256";
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(SignerError::OciRegistry(format!($($arg)+))))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SimpleSigning
+{
+    critical: Critical,
+    optional: Option<Map<String, Value>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Critical
+{
+    identity: Identity,
+    image: Image,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Identity
+{
+    #[serde(rename = "docker-reference")]
+    docker_reference: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Image
+{
+    #[serde(rename = "docker-manifest-digest")]
+    docker_manifest_digest: String,
+}
+
+/// Signature read from a cosign layer along with the payload it signs
+struct CosignSignature
+{
+    signature: ImageSignature,
+    payload: Vec<u8>,
+}
+
+impl AsRef<ImageSignature> for CosignSignature
+{
+    fn as_ref(&self) -> &ImageSignature
+    {
+        &self.signature
+    }
+}
+
+/// Tag under which cosign looks for signatures of a given manifest
+fn signature_tag(digest: &Digest) -> String
+{
+    format!("{}-{}.sig", digest.algo(), digest.hash())
+}
+
+/// Whether a tag is the one of a cosign signature manifest
+pub(crate) fn is_signature_tag(tag: &str) -> bool
+{
+    match tag.strip_suffix(".sig").and_then(|t| t.split_once('-')) {
+        Some((algo, hash)) => Digest::try_from(format!("{}:{}", algo, hash)).is_ok(),
+        None => false,
+    }
+}
+
+fn is_tagged(desc: &Descriptor, tag: &str) -> bool
+{
+    match desc.annotations() {
+        Some(anns) => anns.get(ANNOTATION_REF_NAME).map(|t| t.as_str()) == Some(tag),
+        None => false,
+    }
+}
+
+fn find_tag(index: &ImageIndex, tag: &str) -> Option<Descriptor>
+{
+    index
+        .manifests()
+        .iter()
+        .find(|desc| is_tagged(desc, tag))
+        .cloned()
+}
+
+fn read_blob(blobs: &Path, digest: &str) -> SignerResult<Vec<u8>>
+{
+    let digest = Digest::try_from(digest)?;
+    let data = utils::file_read(blobs.join(digest.to_path()))?;
+
+    let hash = crypto::hash_reader(digest.algo(), &mut data.as_slice())?;
+    if hash != hex::decode(digest.hash().as_bytes())? {
+        err!("Hash mismatch for blob: \"{}\"", digest)?;
+    }
+
+    Ok(data)
+}
+
+fn optional_str(optional: &Option<Map<String, Value>>, name: &str) -> Option<String>
+{
+    optional
+        .as_ref()
+        .and_then(|o| o.get(name))
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_string())
+}
+
+/// Sign a manifest in the cosign simple signing format. The manifest itself is
+/// left untouched, the signature is kept in a separate manifest tagged with
+/// "sha256-DIGEST.sig", one layer per signer.
+pub(crate) fn sign_manifest(
+    tx: &mut Transaction,
+    digest: &str,
+    docker_reference: &str,
+    v_prv: &dyn PrivateKey,
+    vendor_pub_signature: &[u8],
+    expiry: Option<u64>,
+) -> SignerResult<()>
+{
+    let v_pub = v_prv.public_key();
+    let v_pub_u8 = v_pub.to_der()?;
+
+    let manifest_digest = Digest::try_from(digest)?;
+    let tag = signature_tag(&manifest_digest);
+
+    // create and sign the payload
+    let mut signature = ImageSignature {
+        config_sign: Vec::new(),
+        vendor_pub: v_pub_u8,
+        vendor_pub_sign: vendor_pub_signature.to_vec(),
+        timestamp: Some(utils::unix_time().to_string()),
+        expiry: expiry.map(|e| e.to_string()),
+        algorithm: Some(v_prv.algorithm().to_string()),
+    };
+
+    // kept in the signed payload so they can't be altered, same names as in
+    // the manifest annotations of the native format
+    let mut optional = Map::new();
+    for (name, value) in [
+        (SIGNATURE_TIMESTAMP, &signature.timestamp),
+        (SIGNATURE_EXPIRY, &signature.expiry),
+    ] {
+        if let Some(v) = value {
+            optional.insert(annotation(0, name), Value::String(v.clone()));
+        }
+    }
+
+    let payload = SimpleSigning {
+        critical: Critical {
+            identity: Identity {
+                docker_reference: docker_reference.to_string(),
+            },
+            image: Image {
+                docker_manifest_digest: manifest_digest.to_string(),
+            },
+            kind: SIMPLESIGNING_TYPE.to_string(),
+        },
+        optional: Some(optional),
+    };
+    let payload = serde_json::to_vec(&payload)?;
+    signature.config_sign = v_prv.sign_sha256(&payload)?;
+
+    let (payload_digest, payload_size) = tx.write_blob(COSIGN_DIGEST_ALGO, &payload)?;
+    let mut annotations = HashMap::new();
+    annotations.insert(
+        ANNOTATION_COSIGN_SIGNATURE.to_string(),
+        STANDARD.encode(&signature.config_sign),
+    );
+    signature.write_vendor(&mut annotations);
+    let layer = DescriptorBuilder::default()
+        .media_type(MediaType::Other(SIMPLESIGNING_MEDIA_TYPE.to_string()))
+        .digest(payload_digest)
+        .size(payload_size)
+        .annotations(annotations)
+        .build()?;
+
+    // keep layers signed with other keys
+    let mut stale = HashSet::<String>::new();
+    let mut layers = Vec::new();
+    if let Some(desc) = find_tag(tx.index(), &tag) {
+        info!(
+            "Updating existing signature manifest: \"{}\"",
+            desc.digest()
+        );
+        let old_manifest = ImageManifest::from_reader(tx.read_blob(desc.digest())?.as_slice())?;
+        stale.insert(desc.digest().to_string());
+        stale.insert(old_manifest.config().digest().to_string());
+        for old_layer in old_manifest.layers() {
+            stale.insert(old_layer.digest().to_string());
+            let same_key = match old_layer.annotations() {
+                Some(anns) => match ImageSignature::read_vendor(anns) {
+                    Ok(old) => old.vendor_pub == signature.vendor_pub,
+                    Err(_) => false,
+                },
+                None => false,
+            };
+            if !same_key {
+                layers.push(old_layer.clone());
+            }
+        }
+    }
+    layers.push(layer);
+
+    // blobs of the replaced signature manifest are removed on commit, unless
+    // they're kept or written again
+    for layer in &layers {
+        stale.remove(layer.digest());
+    }
+    for digest in stale {
+        tx.remove(&digest);
+    }
+
+    // config in the same form as created by cosign
+    let diff_ids: Vec<&String> = layers.iter().map(|l| l.digest()).collect();
+    let config = json!({
+        "architecture": "",
+        "os": "",
+        "config": {},
+        "rootfs": { "type": "layers", "diff_ids": diff_ids },
+    });
+    let (config_digest, config_size) =
+        tx.write_blob(COSIGN_DIGEST_ALGO, &serde_json::to_vec(&config)?)?;
+    let config = DescriptorBuilder::default()
+        .media_type(MediaType::ImageConfig)
+        .digest(config_digest)
+        .size(config_size)
+        .build()?;
+
+    let manifest = ImageManifestBuilder::default()
+        .schema_version(2u32)
+        .media_type(MediaType::ImageManifest)
+        .config(config)
+        .layers(layers)
+        .build()?;
+    let (sig_digest, sig_size) =
+        tx.write_blob(COSIGN_DIGEST_ALGO, &serde_json::to_vec(&manifest)?)?;
+
+    // tag the signature manifest in index.json
+    let mut annotations = HashMap::new();
+    annotations.insert(ANNOTATION_REF_NAME.to_string(), tag.clone());
+    let sig_desc = DescriptorBuilder::default()
+        .media_type(MediaType::ImageManifest)
+        .digest(sig_digest)
+        .size(sig_size)
+        .annotations(annotations)
+        .build()?;
+
+    let mut manifests: Vec<Descriptor> = tx
+        .index()
+        .manifests()
+        .iter()
+        .filter(|desc| !is_tagged(desc, &tag))
+        .cloned()
+        .collect();
+    manifests.push(sig_desc);
+    tx.index_mut().set_manifests(manifests);
+
+    info!("Signature manifest tagged as: \"{}\"", tag);
+
+    Ok(())
+}
+
+/// Drop the cosign signature manifest of a manifest that's going away, its tag
+/// and blobs are removed with the transaction. Returns whether there was one.
+pub(crate) fn remove_signature(tx: &mut Transaction, digest: &str) -> SignerResult<bool>
+{
+    let tag = signature_tag(&Digest::try_from(digest)?);
+    let Some(desc) = find_tag(tx.index(), &tag) else {
+        return Ok(false);
+    };
+
+    let manifest = ImageManifest::from_reader(tx.read_blob(desc.digest())?.as_slice())?;
+    tx.remove(desc.digest());
+    tx.remove(manifest.config().digest());
+    for layer in manifest.layers() {
+        tx.remove(layer.digest());
+    }
+
+    let manifests: Vec<Descriptor> = tx
+        .index()
+        .manifests()
+        .iter()
+        .filter(|desc| !is_tagged(desc, &tag))
+        .cloned()
+        .collect();
+    tx.index_mut().set_manifests(manifests);
+
+    Ok(true)
+}
+
+/// Whether a manifest has a cosign signature manifest, valid or not
+pub(crate) fn is_signed<T: AsRef<Path>>(blobs: T, digest: &str) -> SignerResult<bool>
+{
+    let index_path = blobs.as_ref().join("..").join(INDEX_JSON);
+    let tag = signature_tag(&Digest::try_from(digest)?);
+    let index = ImageIndex::from_file(&index_path)?;
+
+    Ok(find_tag(&index, &tag).is_some())
+}
+
+fn read_signature(
+    blobs: &Path,
+    layer: &Descriptor,
+    manifest_digest: &str,
+) -> SignerResult<CosignSignature>
+{
+    let Some(annotations) = layer.annotations() else {
+        return err!("Layer does not contain annotations");
+    };
+    let Some(sign) = annotations.get(ANNOTATION_COSIGN_SIGNATURE) else {
+        return err!("Missing \"{}\" annotation", ANNOTATION_COSIGN_SIGNATURE);
+    };
+    let mut signature = ImageSignature::read_vendor(annotations)?;
+
+    // make sure the payload refers to the manifest being verified
+    let payload = read_blob(blobs, layer.digest())?;
+    let simple: SimpleSigning = serde_json::from_slice(&payload)?;
+    if simple.critical.kind != SIMPLESIGNING_TYPE {
+        err!("Unsupported payload type: \"{}\"", simple.critical.kind)?;
+    }
+    if simple.critical.image.docker_manifest_digest != manifest_digest {
+        err!(
+            "Payload signs a different manifest: \"{}\"",
+            simple.critical.image.docker_manifest_digest
+        )?;
+    }
+
+    signature.config_sign = STANDARD.decode(sign)?;
+    signature.timestamp = optional_str(&simple.optional, &annotation(0, SIGNATURE_TIMESTAMP));
+    signature.expiry = optional_str(&simple.optional, &annotation(0, SIGNATURE_EXPIRY));
+
+    Ok(CosignSignature { signature, payload })
+}
+
+/// Verify the cosign signatures of a manifest, the signature manifest is
+/// looked up by its "sha256-DIGEST.sig" tag
+pub(crate) fn verify_manifest<T: AsRef<Path>>(
+    blobs: T,
+    digest: &str,
+    ca_pub: &[u8],
+    policy: &VerifyPolicy,
+) -> SignerResult<()>
+{
+    let blobs = blobs.as_ref();
+    let index_path = blobs.join("..").join(INDEX_JSON);
+
+    let manifest_digest = Digest::try_from(digest)?;
+    let tag = signature_tag(&manifest_digest);
+
+    let index = ImageIndex::from_file(&index_path)?;
+    let Some(desc) = find_tag(&index, &tag) else {
+        return err!("No cosign signature found, missing tag: \"{}\"", tag);
+    };
+    info!("Found signature manifest: \"{}\"", desc.digest());
+
+    let manifest: ImageManifest = serde_json::from_slice(&read_blob(blobs, desc.digest())?)?;
+    let mut signatures = Vec::new();
+    for (layer_no, layer) in manifest.layers().iter().enumerate() {
+        if *layer.media_type() != MediaType::Other(SIMPLESIGNING_MEDIA_TYPE.to_string()) {
+            continue;
+        }
+        match read_signature(blobs, layer, &manifest_digest.to_string()) {
+            Ok(signature) => signatures.push(signature),
+            Err(e) => error!("Signature layer {} skipped: {}", layer_no, e),
+        }
+    }
+
+    policy.enforce(&signatures, |s| {
+        policy.verify_vendor(&s.signature, ca_pub)?;
+        let v_pub = crypto::import_public_as(s.signature.algorithm()?, &s.signature.vendor_pub)?;
+        v_pub
+            .verify_sha256(&s.payload, &s.signature.config_sign)
+            .or(err!("Payload signature verification failed"))
+    })
+}
@@ -1,68 +1,211 @@
-use p384::ecdsa::{
-    signature::{DigestSigner, Signer},
-    Signature, SigningKey,
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use clap::ValueEnum;
+use p384::ecdsa::signature::{
+    hazmat::{PrehashSigner, PrehashVerifier},
+    DigestSigner, DigestVerifier, RandomizedDigestSigner, RandomizedSigner, SignatureEncoding,
+    Signer, Verifier,
 };
-use p384::ecdsa::{
-    signature::{DigestVerifier, Verifier},
-    VerifyingKey,
-};
-use rand_core::OsRng;
+use p384::pkcs8::{DecodePrivateKey, EncodePrivateKey};
+use pkcs8::{pkcs5::pbes2, EncryptedPrivateKeyInfo, PrivateKeyInfo};
+use rand_core::{OsRng, RngCore};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::traits::PublicKeyParts;
 use sec1::{DecodeEcPrivateKey, EncodeEcPrivateKey};
+use serde_json::{json, Value};
 use sha2::{digest::DynDigest, Digest, Sha256, Sha384, Sha512};
 use spki::{der::Encode, DecodePublicKey, EncodePublicKey};
+use std::fmt::Display;
 use std::io;
+use std::str::FromStr;
 
-use super::{error::SignerError, SignerResult};
+use super::passphrase::{self, PassphraseSource};
+use super::{error::SignerError, format, SignerResult};
 
 const READ_BUF_SIZE: usize = 4096;
+const RSA_BITS: usize = 3072;
+const PKCS11_SCHEME: &str = "pkcs11:";
+// log2(N) of 14 is what `openssl pkcs8 -scrypt` uses, higher costs exceed the
+// default memory limit of openssl which then can't decrypt the key
+const SCRYPT_LOG_N: u8 = 14;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+const PBKDF2_ITERATIONS: u32 = 600_000;
+const AES256_KEY_LEN: usize = 32;
 
 macro_rules! err {
     ($($arg:tt)+) => (Err(SignerError::Crypto(format!($($arg)+))))
 }
 
-pub(crate) fn import_private(input: &[u8]) -> SignerResult<SigningKey>
+/// Supported signature algorithms, the hash function is fixed per algorithm:
+/// SHA384 for ECDSA P384, SHA256 for ECDSA P256 and RSA-PSS, none for Ed25519
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KeyAlgorithm
 {
-    Ok(SigningKey::from_sec1_der(input)?)
+    EcdsaP384,
+    EcdsaP256,
+    Ed25519,
+    RsaPss,
 }
 
-pub(crate) fn import_public(input: &[u8]) -> SignerResult<VerifyingKey>
+impl KeyAlgorithm
 {
-    Ok(VerifyingKey::from_public_key_der(input)?)
+    const ALL: [KeyAlgorithm; 4] = [
+        KeyAlgorithm::EcdsaP384,
+        KeyAlgorithm::EcdsaP256,
+        KeyAlgorithm::Ed25519,
+        KeyAlgorithm::RsaPss,
+    ];
+
+    fn generate(&self) -> SignerResult<Box<dyn PrivateKey>>
+    {
+        Ok(match self {
+            KeyAlgorithm::EcdsaP384 => {
+                Box::new(P384PrivateKey(p384::ecdsa::SigningKey::random(&mut OsRng)))
+            }
+            KeyAlgorithm::EcdsaP256 => {
+                Box::new(P256PrivateKey(p256::ecdsa::SigningKey::random(&mut OsRng)))
+            }
+            KeyAlgorithm::Ed25519 => Box::new(Ed25519PrivateKey(
+                ed25519_dalek::SigningKey::generate(&mut OsRng),
+            )),
+            KeyAlgorithm::RsaPss => Box::new(RsaPssPrivateKey(rsa::RsaPrivateKey::new(
+                &mut OsRng, RSA_BITS,
+            )?)),
+        })
+    }
+
+    /// Private keys are accepted in PKCS#8, for ECDSA also in SEC1 and for RSA
+    /// in PKCS#1. RSA keys can have either rsaEncryption or id-RSASSA-PSS
+    /// algorithm.
+    fn import_private(&self, input: &[u8]) -> SignerResult<Box<dyn PrivateKey>>
+    {
+        Ok(match self {
+            KeyAlgorithm::EcdsaP384 => Box::new(P384PrivateKey(
+                match p384::ecdsa::SigningKey::from_pkcs8_der(input) {
+                    Ok(key) => key,
+                    Err(_) => p384::ecdsa::SigningKey::from_sec1_der(input)?,
+                },
+            )),
+            KeyAlgorithm::EcdsaP256 => Box::new(P256PrivateKey(
+                match p256::ecdsa::SigningKey::from_pkcs8_der(input) {
+                    Ok(key) => key,
+                    Err(_) => p256::ecdsa::SigningKey::from_sec1_der(input)?,
+                },
+            )),
+            KeyAlgorithm::Ed25519 => Box::new(Ed25519PrivateKey(
+                ed25519_dalek::SigningKey::from_pkcs8_der(input)?,
+            )),
+            KeyAlgorithm::RsaPss => Box::new(RsaPssPrivateKey(rsa_private_key(input)?)),
+        })
+    }
+
+    fn import_public(&self, input: &[u8]) -> SignerResult<Box<dyn PublicKey>>
+    {
+        Ok(match self {
+            KeyAlgorithm::EcdsaP384 => Box::new(P384PublicKey(
+                p384::ecdsa::VerifyingKey::from_public_key_der(input)?,
+            )),
+            KeyAlgorithm::EcdsaP256 => Box::new(P256PublicKey(
+                p256::ecdsa::VerifyingKey::from_public_key_der(input)?,
+            )),
+            KeyAlgorithm::Ed25519 => Box::new(Ed25519PublicKey(
+                ed25519_dalek::VerifyingKey::from_public_key_der(input)?,
+            )),
+            KeyAlgorithm::RsaPss => Box::new(RsaPssPublicKey(
+                ir_verify::rsa_public_key(input).or(err!("Malformed RSA public key"))?,
+            )),
+        })
+    }
 }
 
-pub(crate) fn export_private(key: &SigningKey) -> SignerResult<Vec<u8>>
+impl KeyAlgorithm
 {
-    let bytes = key.to_sec1_der()?.to_bytes();
-    Ok(bytes.to_vec())
+    /// JWS "alg" of signatures made with the algorithm
+    pub fn jws_alg(&self) -> &'static str
+    {
+        match self {
+            KeyAlgorithm::EcdsaP384 => "ES384",
+            KeyAlgorithm::EcdsaP256 => "ES256",
+            KeyAlgorithm::Ed25519 => "EdDSA",
+            KeyAlgorithm::RsaPss => "PS256",
+        }
+    }
 }
 
-pub(crate) fn export_public(key: &VerifyingKey) -> SignerResult<Vec<u8>>
+impl Display for KeyAlgorithm
 {
-    let bytes = key.to_public_key_der()?;
-    Ok(bytes.to_vec())
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        let name = match self {
+            KeyAlgorithm::EcdsaP384 => "ecdsa-p384",
+            KeyAlgorithm::EcdsaP256 => "ecdsa-p256",
+            KeyAlgorithm::Ed25519 => "ed25519",
+            KeyAlgorithm::RsaPss => "rsa-pss",
+        };
+        write!(f, "{}", name)
+    }
 }
 
-pub(crate) fn generate_key() -> SigningKey
+impl FromStr for KeyAlgorithm
 {
-    SigningKey::random(&mut OsRng)
+    type Err = SignerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match KeyAlgorithm::ALL.iter().find(|a| a.to_string() == s) {
+            Some(a) => Ok(*a),
+            None => err!("Unsupported key algorithm: {}", s),
+        }
+    }
+}
+
+/// Key derivation function used to encrypt private keys, the cipher is always
+/// AES-256-CBC (PBES2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Kdf
+{
+    Scrypt,
+    /// PBKDF2 with HMAC-SHA256
+    Pbkdf2,
 }
 
-pub(crate) fn extract_public(key: &SigningKey) -> VerifyingKey
+pub(crate) trait PrivateKey
 {
-    *key.verifying_key()
+    fn algorithm(&self) -> KeyAlgorithm;
+    fn public_key(&self) -> Box<dyn PublicKey>;
+    /// SEC1 for ECDSA keys, PKCS#8 for the others
+    fn to_der(&self) -> SignerResult<Vec<u8>>;
+    fn to_pkcs8_der(&self) -> SignerResult<Vec<u8>>;
+    fn sign(&self, msg: &[u8]) -> SignerResult<Vec<u8>>;
+    fn sign_reader(&self, reader: &mut dyn io::Read) -> SignerResult<Vec<u8>>;
+
+    /// Where the algorithm allows to choose the hash function sign with
+    /// SHA256 (what cosign expects by default)
+    fn sign_sha256(&self, msg: &[u8]) -> SignerResult<Vec<u8>>
+    {
+        self.sign(msg)
+    }
 }
 
-pub(crate) fn sign(key: &SigningKey, msg: &[u8]) -> SignerResult<Vec<u8>>
+pub(crate) trait PublicKey
 {
-    let sign: Signature = key.try_sign(msg)?;
-    let mut vec = Vec::new();
-    sign.to_der().encode_to_vec(&mut vec)?;
-    Ok(vec)
+    fn algorithm(&self) -> KeyAlgorithm;
+    /// SPKI
+    fn to_der(&self) -> SignerResult<Vec<u8>>;
+    /// JWK (RFC 7517) with the key parameters only
+    fn to_jwk(&self) -> SignerResult<Value>;
+    fn verify(&self, msg: &[u8], signature: &[u8]) -> SignerResult<()>;
+    fn verify_reader(&self, reader: &mut dyn io::Read, signature: &[u8]) -> SignerResult<()>;
+
+    fn verify_sha256(&self, msg: &[u8], signature: &[u8]) -> SignerResult<()>
+    {
+        self.verify(msg, signature)
+    }
 }
 
-pub(crate) fn sign_reader<T: io::Read>(key: &SigningKey, reader: &mut T) -> SignerResult<Vec<u8>>
+fn digest_reader<D: Digest>(reader: &mut dyn io::Read) -> SignerResult<D>
 {
-    let mut hasher = Sha384::new();
+    let mut hasher = D::new();
     let mut buf = vec![0u8; READ_BUF_SIZE];
     loop {
         let n = reader.read(buf.as_mut_slice())?;
@@ -71,37 +214,407 @@ pub(crate) fn sign_reader<T: io::Read>(key: &SigningKey, reader: &mut T) -> Sign
         }
         Digest::update(&mut hasher, &buf[0..n]);
     }
-    let sign: Signature = key.sign_digest(hasher);
-    let mut vec = Vec::new();
-    sign.to_der().encode_to_vec(&mut vec)?;
-    Ok(vec)
+    Ok(hasher)
+}
+
+// ECDSA signatures are DER encoded, same for both curves
+macro_rules! ecdsa_key {
+    ($prv:ident, $pub:ident, $curve:ident, $hash:ty, $algo:expr, $crv:literal) => {
+        struct $prv($curve::ecdsa::SigningKey);
+        struct $pub($curve::ecdsa::VerifyingKey);
+
+        impl $prv
+        {
+            fn encode(sign: $curve::ecdsa::Signature) -> SignerResult<Vec<u8>>
+            {
+                let mut vec = Vec::new();
+                sign.to_der().encode_to_vec(&mut vec)?;
+                Ok(vec)
+            }
+        }
+
+        impl PrivateKey for $prv
+        {
+            fn algorithm(&self) -> KeyAlgorithm
+            {
+                $algo
+            }
+
+            fn public_key(&self) -> Box<dyn PublicKey>
+            {
+                Box::new($pub(*self.0.verifying_key()))
+            }
+
+            fn to_der(&self) -> SignerResult<Vec<u8>>
+            {
+                let bytes = self.0.to_sec1_der()?.to_bytes();
+                Ok(bytes.to_vec())
+            }
+
+            fn to_pkcs8_der(&self) -> SignerResult<Vec<u8>>
+            {
+                let bytes = self.0.to_pkcs8_der()?.to_bytes();
+                Ok(bytes.to_vec())
+            }
+
+            fn sign(&self, msg: &[u8]) -> SignerResult<Vec<u8>>
+            {
+                Self::encode(self.0.try_sign(msg)?)
+            }
+
+            fn sign_reader(&self, reader: &mut dyn io::Read) -> SignerResult<Vec<u8>>
+            {
+                let hasher = digest_reader::<$hash>(reader)?;
+                Self::encode(self.0.sign_digest(hasher))
+            }
+
+            fn sign_sha256(&self, msg: &[u8]) -> SignerResult<Vec<u8>>
+            {
+                Self::encode(self.0.sign_prehash(&Sha256::digest(msg))?)
+            }
+        }
+
+        impl PublicKey for $pub
+        {
+            fn algorithm(&self) -> KeyAlgorithm
+            {
+                $algo
+            }
+
+            fn to_der(&self) -> SignerResult<Vec<u8>>
+            {
+                let bytes = self.0.to_public_key_der()?;
+                Ok(bytes.to_vec())
+            }
+
+            fn to_jwk(&self) -> SignerResult<Value>
+            {
+                let point = self.0.to_encoded_point(false);
+                let (Some(x), Some(y)) = (point.x(), point.y()) else {
+                    return err!("Public key is the identity point");
+                };
+                Ok(json!({
+                    "kty": "EC",
+                    "crv": $crv,
+                    "x": URL_SAFE_NO_PAD.encode(x),
+                    "y": URL_SAFE_NO_PAD.encode(y),
+                }))
+            }
+
+            fn verify(&self, msg: &[u8], signature: &[u8]) -> SignerResult<()>
+            {
+                let sign = $curve::ecdsa::Signature::from_der(signature)?;
+                self.0.verify(msg, &sign)?;
+                Ok(())
+            }
+
+            fn verify_reader(&self, reader: &mut dyn io::Read, signature: &[u8])
+                -> SignerResult<()>
+            {
+                let sign = $curve::ecdsa::Signature::from_der(signature)?;
+                let hasher = digest_reader::<$hash>(reader)?;
+                self.0.verify_digest(hasher, &sign)?;
+                Ok(())
+            }
+
+            fn verify_sha256(&self, msg: &[u8], signature: &[u8]) -> SignerResult<()>
+            {
+                let sign = $curve::ecdsa::Signature::from_der(signature)?;
+                self.0.verify_prehash(&Sha256::digest(msg), &sign)?;
+                Ok(())
+            }
+        }
+    };
+}
+
+ecdsa_key!(
+    P384PrivateKey,
+    P384PublicKey,
+    p384,
+    Sha384,
+    KeyAlgorithm::EcdsaP384,
+    "P-384"
+);
+ecdsa_key!(
+    P256PrivateKey,
+    P256PublicKey,
+    p256,
+    Sha256,
+    KeyAlgorithm::EcdsaP256,
+    "P-256"
+);
+
+// Ed25519 has no streaming variant (other than Ed25519ph which is rarely
+// supported), the whole message is read into memory
+struct Ed25519PrivateKey(ed25519_dalek::SigningKey);
+struct Ed25519PublicKey(ed25519_dalek::VerifyingKey);
+
+impl PrivateKey for Ed25519PrivateKey
+{
+    fn algorithm(&self) -> KeyAlgorithm
+    {
+        KeyAlgorithm::Ed25519
+    }
+
+    fn public_key(&self) -> Box<dyn PublicKey>
+    {
+        Box::new(Ed25519PublicKey(self.0.verifying_key()))
+    }
+
+    fn to_der(&self) -> SignerResult<Vec<u8>>
+    {
+        self.to_pkcs8_der()
+    }
+
+    fn to_pkcs8_der(&self) -> SignerResult<Vec<u8>>
+    {
+        let bytes = self.0.to_pkcs8_der()?.to_bytes();
+        Ok(bytes.to_vec())
+    }
+
+    fn sign(&self, msg: &[u8]) -> SignerResult<Vec<u8>>
+    {
+        Ok(self.0.try_sign(msg)?.to_vec())
+    }
+
+    fn sign_reader(&self, reader: &mut dyn io::Read) -> SignerResult<Vec<u8>>
+    {
+        let mut msg = Vec::new();
+        reader.read_to_end(&mut msg)?;
+        self.sign(&msg)
+    }
 }
 
-pub(crate) fn verify(key: &VerifyingKey, msg: &[u8], signature: &[u8]) -> SignerResult<()>
+impl PublicKey for Ed25519PublicKey
 {
-    let sign = Signature::from_der(signature)?;
-    key.verify(msg, &sign)?;
-    Ok(())
+    fn algorithm(&self) -> KeyAlgorithm
+    {
+        KeyAlgorithm::Ed25519
+    }
+
+    fn to_der(&self) -> SignerResult<Vec<u8>>
+    {
+        let bytes = self.0.to_public_key_der()?;
+        Ok(bytes.to_vec())
+    }
+
+    fn to_jwk(&self) -> SignerResult<Value>
+    {
+        Ok(json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": URL_SAFE_NO_PAD.encode(self.0.as_bytes()),
+        }))
+    }
+
+    fn verify(&self, msg: &[u8], signature: &[u8]) -> SignerResult<()>
+    {
+        let sign = ed25519_dalek::Signature::from_slice(signature)?;
+        self.0.verify(msg, &sign)?;
+        Ok(())
+    }
+
+    fn verify_reader(&self, reader: &mut dyn io::Read, signature: &[u8]) -> SignerResult<()>
+    {
+        let mut msg = Vec::new();
+        reader.read_to_end(&mut msg)?;
+        self.verify(&msg, signature)
+    }
 }
 
-pub(crate) fn verify_reader<T: io::Read>(
-    key: &VerifyingKey,
-    reader: &mut T,
-    signature: &[u8],
-) -> SignerResult<()>
+// PKCS#8 keys written by `openssl genpkey -algorithm RSA-PSS` have the
+// id-RSASSA-PSS algorithm that the rsa crate doesn't accept, the key itself is
+// the same PKCS#1 one as under rsaEncryption
+fn rsa_private_key(input: &[u8]) -> SignerResult<rsa::RsaPrivateKey>
 {
-    let sign = Signature::from_der(signature)?;
-    let mut hasher = Sha384::new();
-    let mut buf = vec![0u8; READ_BUF_SIZE];
-    loop {
-        let n = reader.read(buf.as_mut_slice())?;
-        if n == 0 {
-            break;
+    if let Ok(info) = PrivateKeyInfo::try_from(input) {
+        if info.algorithm.oid == ir_verify::RSASSA_PSS_OID {
+            return rsa::RsaPrivateKey::from_pkcs1_der(info.private_key)
+                .or(err!("Malformed RSA-PSS private key"));
         }
-        Digest::update(&mut hasher, &buf[0..n]);
     }
-    key.verify_digest(hasher, &sign)?;
-    Ok(())
+
+    match rsa::RsaPrivateKey::from_pkcs8_der(input) {
+        Ok(key) => Ok(key),
+        Err(_) => rsa::RsaPrivateKey::from_pkcs1_der(input).or(err!("Malformed RSA private key")),
+    }
+}
+
+// RSA-PSS with SHA256 and salt of the hash length (openssl defaults)
+struct RsaPssPrivateKey(rsa::RsaPrivateKey);
+struct RsaPssPublicKey(rsa::RsaPublicKey);
+
+impl PrivateKey for RsaPssPrivateKey
+{
+    fn algorithm(&self) -> KeyAlgorithm
+    {
+        KeyAlgorithm::RsaPss
+    }
+
+    fn public_key(&self) -> Box<dyn PublicKey>
+    {
+        Box::new(RsaPssPublicKey(self.0.to_public_key()))
+    }
+
+    fn to_der(&self) -> SignerResult<Vec<u8>>
+    {
+        self.to_pkcs8_der()
+    }
+
+    fn to_pkcs8_der(&self) -> SignerResult<Vec<u8>>
+    {
+        let bytes = self.0.to_pkcs8_der()?.to_bytes();
+        Ok(bytes.to_vec())
+    }
+
+    fn sign(&self, msg: &[u8]) -> SignerResult<Vec<u8>>
+    {
+        let key = rsa::pss::SigningKey::<Sha256>::new(self.0.clone());
+        Ok(key.try_sign_with_rng(&mut OsRng, msg)?.to_vec())
+    }
+
+    fn sign_reader(&self, reader: &mut dyn io::Read) -> SignerResult<Vec<u8>>
+    {
+        let key = rsa::pss::SigningKey::<Sha256>::new(self.0.clone());
+        let hasher = digest_reader::<Sha256>(reader)?;
+        Ok(key.try_sign_digest_with_rng(&mut OsRng, hasher)?.to_vec())
+    }
+}
+
+impl PublicKey for RsaPssPublicKey
+{
+    fn algorithm(&self) -> KeyAlgorithm
+    {
+        KeyAlgorithm::RsaPss
+    }
+
+    fn to_der(&self) -> SignerResult<Vec<u8>>
+    {
+        let bytes = self.0.to_public_key_der()?;
+        Ok(bytes.to_vec())
+    }
+
+    fn to_jwk(&self) -> SignerResult<Value>
+    {
+        Ok(json!({
+            "kty": "RSA",
+            "n": URL_SAFE_NO_PAD.encode(self.0.n().to_bytes_be()),
+            "e": URL_SAFE_NO_PAD.encode(self.0.e().to_bytes_be()),
+        }))
+    }
+
+    fn verify(&self, msg: &[u8], signature: &[u8]) -> SignerResult<()>
+    {
+        let key = rsa::pss::VerifyingKey::<Sha256>::new(self.0.clone());
+        let sign = rsa::pss::Signature::try_from(signature)?;
+        key.verify(msg, &sign)?;
+        Ok(())
+    }
+
+    fn verify_reader(&self, reader: &mut dyn io::Read, signature: &[u8]) -> SignerResult<()>
+    {
+        let key = rsa::pss::VerifyingKey::<Sha256>::new(self.0.clone());
+        let sign = rsa::pss::Signature::try_from(signature)?;
+        let hasher = digest_reader::<Sha256>(reader)?;
+        key.verify_digest(hasher, &sign)?;
+        Ok(())
+    }
+}
+
+/// Detect the algorithm of a private key
+pub(crate) fn import_private(input: &[u8]) -> SignerResult<Box<dyn PrivateKey>>
+{
+    for algorithm in KeyAlgorithm::ALL {
+        if let Ok(key) = algorithm.import_private(input) {
+            return Ok(key);
+        }
+    }
+    err!("Unsupported or malformed private key")
+}
+
+/// Load a private key from a file or from a token when given a PKCS#11 URI,
+/// the latter requires the "pkcs11" feature. The passphrase of an encrypted
+/// key is taken from a given source.
+pub(crate) fn load_private(
+    key: &str,
+    source: &PassphraseSource,
+) -> SignerResult<Box<dyn PrivateKey>>
+{
+    if key.starts_with(PKCS11_SCHEME) {
+        #[cfg(feature = "pkcs11")]
+        return crate::pkcs11::open_private(key);
+        #[cfg(not(feature = "pkcs11"))]
+        return err!("PKCS#11 URI given but ir-sign was built without the pkcs11 feature");
+    }
+
+    let input = format::read_der(key)?;
+    match EncryptedPrivateKeyInfo::try_from(input.as_slice()) {
+        Ok(encrypted) => {
+            let passphrase = passphrase::read(key, source)?;
+            let decrypted = encrypted
+                .decrypt(passphrase)
+                .or(err!("Wrong passphrase or malformed key: \"{}\"", key))?;
+            import_private(decrypted.as_bytes())
+        }
+        Err(_) => import_private(&input),
+    }
+}
+
+/// Encrypt a private key as PKCS#8 EncryptedPrivateKeyInfo, the same as
+/// `openssl pkcs8 -topk8 -v2 aes-256-cbc [-scrypt]`
+pub(crate) fn encrypt_private(
+    key: &dyn PrivateKey,
+    kdf: Kdf,
+    passphrase: &str,
+) -> SignerResult<Vec<u8>>
+{
+    let mut salt = [0u8; 16];
+    let mut iv = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut iv);
+
+    let params = match kdf {
+        Kdf::Scrypt => {
+            let scrypt = scrypt::Params::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P, AES256_KEY_LEN)
+                .or(err!("Incorrect scrypt parameters"))?;
+            pbes2::Parameters::scrypt_aes256cbc(scrypt, &salt, &iv)
+        }
+        Kdf::Pbkdf2 => pbes2::Parameters::pbkdf2_sha256_aes256cbc(PBKDF2_ITERATIONS, &salt, &iv),
+    }
+    .or(err!("Incorrect PBES2 parameters"))?;
+
+    let der = key.to_pkcs8_der()?;
+    let info = PrivateKeyInfo::try_from(der.as_slice())?;
+    let encrypted = info.encrypt_with_params(params, passphrase)?;
+
+    Ok(encrypted.as_bytes().to_vec())
+}
+
+/// Detect the algorithm of a public key
+pub(crate) fn import_public(input: &[u8]) -> SignerResult<Box<dyn PublicKey>>
+{
+    for algorithm in KeyAlgorithm::ALL {
+        if let Ok(key) = algorithm.import_public(input) {
+            return Ok(key);
+        }
+    }
+    err!("Unsupported or malformed public key")
+}
+
+/// Import a public key that has to be of a given algorithm
+pub(crate) fn import_public_as(
+    algorithm: KeyAlgorithm,
+    input: &[u8],
+) -> SignerResult<Box<dyn PublicKey>>
+{
+    algorithm
+        .import_public(input)
+        .or(err!("Public key is not a valid {} key", algorithm))
+}
+
+pub(crate) fn generate_key(algorithm: KeyAlgorithm) -> SignerResult<Box<dyn PrivateKey>>
+{
+    algorithm.generate()
 }
 
 pub(crate) fn hash_reader<T: io::Read>(algo: &str, reader: &mut T) -> SignerResult<Vec<u8>>
@@ -125,3 +638,118 @@ pub(crate) fn hash_reader<T: io::Read>(algo: &str, reader: &mut T) -> SignerResu
 
     Ok(hasher.finalize().to_vec())
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::{
+        encrypt_private, generate_key, import_private, import_public, import_public_as,
+        load_private, Kdf, KeyAlgorithm,
+    };
+    use crate::passphrase::PassphraseSource;
+    use std::os::fd::AsRawFd;
+
+    const MSG: &[u8] = b"image config";
+
+    fn roundtrip(algorithm: KeyAlgorithm)
+    {
+        let key = generate_key(algorithm).unwrap();
+        let key = import_private(&key.to_der().unwrap()).unwrap();
+        assert_eq!(key.algorithm(), algorithm);
+        assert_eq!(import_private(&key.to_pkcs8_der().unwrap()).unwrap().algorithm(), algorithm);
+
+        let public_der = key.public_key().to_der().unwrap();
+        let public = import_public(&public_der).unwrap();
+        assert_eq!(public.algorithm(), algorithm);
+
+        let sign = key.sign(MSG).unwrap();
+        public.verify(MSG, &sign).unwrap();
+        assert!(public.verify(b"other", &sign).is_err());
+        // what ir-server and ir-client use
+        ir_verify::verify(&public_der, MSG, &sign).unwrap();
+
+        let sign = key.sign_reader(&mut &MSG[..]).unwrap();
+        public.verify_reader(&mut &MSG[..], &sign).unwrap();
+        public.verify(MSG, &sign).unwrap();
+
+        let sign = key.sign_sha256(MSG).unwrap();
+        public.verify_sha256(MSG, &sign).unwrap();
+    }
+
+    #[test]
+    fn crypto_roundtrip_ecdsa_p384()
+    {
+        roundtrip(KeyAlgorithm::EcdsaP384);
+    }
+
+    #[test]
+    fn crypto_roundtrip_ecdsa_p256()
+    {
+        roundtrip(KeyAlgorithm::EcdsaP256);
+    }
+
+    #[test]
+    fn crypto_roundtrip_ed25519()
+    {
+        roundtrip(KeyAlgorithm::Ed25519);
+    }
+
+    #[test]
+    fn crypto_rsa_pss_oid()
+    {
+        // `openssl genpkey -algorithm RSA-PSS`, id-RSASSA-PSS in PKCS#8 and SPKI
+        let key = import_private(include_bytes!("../tests/rsa-pss.prv")).unwrap();
+        assert_eq!(key.algorithm(), KeyAlgorithm::RsaPss);
+
+        let public_der = key.public_key().to_der().unwrap();
+        let public = import_public_as(KeyAlgorithm::RsaPss, &public_der).unwrap();
+        let sign = key.sign(MSG).unwrap();
+        public.verify(MSG, &sign).unwrap();
+        ir_verify::verify(&public_der, MSG, &sign).unwrap();
+
+        let pss_public = import_public(include_bytes!("../../ir-verify/tests/rsa-pss.pub.der"));
+        assert_eq!(pss_public.unwrap().algorithm(), KeyAlgorithm::RsaPss);
+    }
+
+    #[test]
+    fn crypto_algorithm_names()
+    {
+        for algorithm in KeyAlgorithm::ALL {
+            assert_eq!(algorithm.to_string().parse::<KeyAlgorithm>().unwrap(), algorithm);
+        }
+        assert!("ecdsa-p521".parse::<KeyAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn crypto_encrypted_pkcs8()
+    {
+        let dir = std::env::temp_dir().join(format!("ir-sign-crypto-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("key.prv");
+        let path = path.to_str().unwrap();
+
+        // what --passphrase-fd gets, each descriptor is read once
+        std::fs::write(dir.join("passphrase"), "secret\n").unwrap();
+        let passphrase = std::fs::File::open(dir.join("passphrase")).unwrap();
+        let source = PassphraseSource::Fd(passphrase.as_raw_fd());
+
+        let key = generate_key(KeyAlgorithm::EcdsaP384).unwrap();
+        for kdf in [Kdf::Scrypt, Kdf::Pbkdf2] {
+            let encrypted = encrypt_private(key.as_ref(), kdf, "secret").unwrap();
+            assert!(import_private(&encrypted).is_err());
+            std::fs::write(path, &encrypted).unwrap();
+
+            let decrypted = load_private(path, &source).unwrap();
+            assert_eq!(decrypted.to_der().unwrap(), key.to_der().unwrap());
+
+            let encrypted = encrypt_private(key.as_ref(), kdf, "other").unwrap();
+            std::fs::write(path, &encrypted).unwrap();
+            assert!(load_private(path, &source).is_err());
+        }
+
+        // not a descriptor
+        assert!(load_private(path, &PassphraseSource::Fd(-1)).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -6,8 +6,14 @@ pub enum SignerError
     Spki(spki::Error),
     Ecdsa(p384::ecdsa::Error),
     Der(sec1::der::Error),
+    Pkcs8(p384::pkcs8::Error),
+    Rsa(rsa::Error),
     OciSpec(oci_spec::OciSpecError),
+    SerdeJson(serde_json::Error),
     FromHex(hex::FromHexError),
+    Base64(base64::DecodeError),
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(cryptoki::error::Error),
     Crypto(String),
     OciRegistry(String),
     Generic(String),
@@ -25,8 +31,14 @@ impl std::fmt::Display for SignerError
             SignerError::Spki(e) => write!(f, "SpkiError({:?})", e),
             SignerError::Ecdsa(e) => write!(f, "EcdsaError({:?})", e),
             SignerError::Der(e) => write!(f, "DerError({:?})", e),
+            SignerError::Pkcs8(e) => write!(f, "Pkcs8Error({:?})", e),
+            SignerError::Rsa(e) => write!(f, "RsaError({:?})", e),
             SignerError::OciSpec(e) => write!(f, "OciSpecError({:?})", e),
+            SignerError::SerdeJson(e) => write!(f, "SerdeJsonError({:?})", e),
             SignerError::FromHex(e) => write!(f, "FromHexError({:?})", e),
+            SignerError::Base64(e) => write!(f, "Base64Error({:?})", e),
+            #[cfg(feature = "pkcs11")]
+            SignerError::Pkcs11(e) => write!(f, "Pkcs11Error({:?})", e),
             SignerError::Crypto(s) => write!(f, "CryptoError({})", s),
             SignerError::OciRegistry(s) => write!(f, "OciRegistryError({})", s),
             SignerError::Generic(s) => write!(f, "GenericError({})", s),
@@ -77,6 +89,22 @@ impl From<sec1::der::Error> for SignerError
     }
 }
 
+impl From<p384::pkcs8::Error> for SignerError
+{
+    fn from(value: p384::pkcs8::Error) -> Self
+    {
+        Self::Pkcs8(value)
+    }
+}
+
+impl From<rsa::Error> for SignerError
+{
+    fn from(value: rsa::Error) -> Self
+    {
+        Self::Rsa(value)
+    }
+}
+
 impl From<oci_spec::OciSpecError> for SignerError
 {
     fn from(value: oci_spec::OciSpecError) -> Self
@@ -85,6 +113,14 @@ impl From<oci_spec::OciSpecError> for SignerError
     }
 }
 
+impl From<serde_json::Error> for SignerError
+{
+    fn from(value: serde_json::Error) -> Self
+    {
+        Self::SerdeJson(value)
+    }
+}
+
 impl From<hex::FromHexError> for SignerError
 {
     fn from(value: hex::FromHexError) -> Self
@@ -93,6 +129,23 @@ impl From<hex::FromHexError> for SignerError
     }
 }
 
+impl From<base64::DecodeError> for SignerError
+{
+    fn from(value: base64::DecodeError) -> Self
+    {
+        Self::Base64(value)
+    }
+}
+
+#[cfg(feature = "pkcs11")]
+impl From<cryptoki::error::Error> for SignerError
+{
+    fn from(value: cryptoki::error::Error) -> Self
+    {
+        Self::Pkcs11(value)
+    }
+}
+
 impl From<&'static str> for SignerError
 {
     fn from(value: &'static str) -> Self
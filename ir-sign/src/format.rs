@@ -0,0 +1,108 @@
+use clap::ValueEnum;
+use spki::der::pem::{self, LineEnding};
+use std::path::Path;
+
+use crate::crypto::{KeyAlgorithm, PublicKey};
+use crate::revocation::fingerprint;
+use crate::{error::SignerError, utils, SignerResult};
+
+pub(crate) const PEM_PRIVATE_KEY: &str = "PRIVATE KEY";
+pub(crate) const PEM_EC_PRIVATE_KEY: &str = "EC PRIVATE KEY";
+pub(crate) const PEM_ENCRYPTED_PRIVATE_KEY: &str = "ENCRYPTED PRIVATE KEY";
+pub(crate) const PEM_PUBLIC_KEY: &str = "PUBLIC KEY";
+// there is no standard label for bare signatures
+pub(crate) const PEM_SIGNATURE: &str = ir_verify::PEM_SIGNATURE;
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(SignerError::Crypto(format!($($arg)+))))
+}
+
+/// Format of written keys and signatures, DER and PEM are detected on input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum FileFormat
+{
+    Der,
+    Pem,
+}
+
+/// Format of written public keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum PublicFormat
+{
+    Der,
+    Pem,
+    /// JSON Web Key
+    Jwk,
+}
+
+/// PEM label of a private key as written by `PrivateKey::to_der()`
+pub(crate) fn private_label(algorithm: KeyAlgorithm, encrypted: bool) -> &'static str
+{
+    match (algorithm, encrypted) {
+        (_, true) => PEM_ENCRYPTED_PRIVATE_KEY,
+        (KeyAlgorithm::EcdsaP384 | KeyAlgorithm::EcdsaP256, false) => PEM_EC_PRIVATE_KEY,
+        (_, false) => PEM_PRIVATE_KEY,
+    }
+}
+
+/// Read a key or a signature, PEM files are decoded to DER whatever the label
+pub(crate) fn read_der<T: AsRef<Path>>(path: T) -> SignerResult<Vec<u8>>
+{
+    let path = path.as_ref();
+    let input = utils::file_read(path)?;
+    let start = input
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(0);
+
+    if !input[start..].starts_with(b"-----BEGIN ") {
+        return Ok(input);
+    }
+
+    match pem::decode_vec(&input[start..]) {
+        Ok((_label, der)) => Ok(der),
+        Err(e) => err!("Malformed PEM file \"{}\": {}", path.display(), e),
+    }
+}
+
+/// Write a DER encoded key or signature in a given format
+pub(crate) fn write<T: AsRef<Path>>(
+    path: T,
+    der: &[u8],
+    label: &str,
+    format: FileFormat,
+) -> SignerResult<()>
+{
+    match format {
+        FileFormat::Der => utils::file_write(path, der)?,
+        FileFormat::Pem => {
+            let pem = pem::encode_string(label, LineEnding::LF, der)
+                .or(err!("Can't encode \"{}\" as PEM", label))?;
+            utils::file_write(path, pem.as_bytes())?
+        }
+    }
+    Ok(())
+}
+
+/// Write a public key, JWK gets the revocation list fingerprint as "kid"
+pub(crate) fn write_public<T: AsRef<Path>>(
+    path: T,
+    key: &dyn PublicKey,
+    format: PublicFormat,
+) -> SignerResult<()>
+{
+    let der = key.to_der()?;
+    match format {
+        PublicFormat::Der => return write(path, &der, PEM_PUBLIC_KEY, FileFormat::Der),
+        PublicFormat::Pem => return write(path, &der, PEM_PUBLIC_KEY, FileFormat::Pem),
+        PublicFormat::Jwk => (),
+    }
+
+    let mut jwk = key.to_jwk()?;
+    jwk["kid"] = fingerprint(&der)?.into();
+    jwk["use"] = "sig".into();
+    jwk["alg"] = key.algorithm().jws_alg().into();
+    utils::file_write(path, &serde_json::to_vec_pretty(&jwk)?)?;
+
+    Ok(())
+}
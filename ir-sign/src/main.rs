@@ -1,8 +1,20 @@
+mod archive;
+mod builder;
+mod cosign;
 mod crypto;
 mod digest;
 mod error;
+mod format;
 mod oci;
+mod passphrase;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+mod report;
+mod reproducible;
+mod revocation;
+mod signature;
 mod subcmds;
+mod transaction;
 mod utils;
 
 use clap::{Parser, Subcommand};
@@ -15,35 +27,67 @@ struct Cli
 {
     #[command(subcommand)]
     command: Commands,
+
+    /// Read passphrases of encrypted keys from this environment variable
+    /// instead of prompting for them
+    #[arg(long, global = true, conflicts_with = "passphrase_fd")]
+    passphrase_env: Option<String>,
+
+    /// Read passphrases of encrypted keys from this file descriptor instead of
+    /// prompting for them
+    #[arg(long, global = true)]
+    passphrase_fd: Option<i32>,
+
+    /// Write JSON of built and signed images in a canonical form (sorted keys,
+    /// no whitespace), also turned on by SOURCE_DATE_EPOCH which is used for
+    /// layer and image times
+    #[arg(long, global = true)]
+    reproducible: bool,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands
 {
-    /// Generate ECDSA P384 private/public key
+    /// Generate a private key
     GenKey
     {
         /// Path to the output file
         #[arg(short, long)]
         output: String,
+
+        /// Key algorithm: ecdsa-p384, ecdsa-p256, ed25519 or rsa-pss
+        #[arg(short, long, default_value = "ecdsa-p384")]
+        algorithm: crypto::KeyAlgorithm,
+
+        /// Encrypt the key (PKCS#8) with a passphrase using a given KDF
+        #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "scrypt")]
+        encrypt: Option<crypto::Kdf>,
+
+        /// Output format: der or pem
+        #[arg(long, value_enum, default_value_t = format::FileFormat::Der)]
+        format: format::FileFormat,
     },
 
     /// Extract public key from a private one
     ExtractPublic
     {
-        /// Path to the private key
+        /// Path to the private key or its PKCS#11 URI
         #[arg(short, long)]
         input: String,
 
         /// Path to the public key
         #[arg(short, long)]
         output: String,
+
+        /// Output format: der, pem or jwk
+        #[arg(long, value_enum, default_value_t = format::PublicFormat::Der)]
+        format: format::PublicFormat,
     },
 
     /// Sign a file
     Sign
     {
-        /// Path to the private key
+        /// Path to the private key or its PKCS#11 URI
         #[arg(short, long)]
         key: String,
 
@@ -54,6 +98,10 @@ enum Commands
         /// Path to the signature file
         #[arg(short, long)]
         signature: String,
+
+        /// Output format of the signature: der or pem
+        #[arg(long, value_enum, default_value_t = format::FileFormat::Der)]
+        format: format::FileFormat,
     },
 
     /// Verify a signature
@@ -88,7 +136,7 @@ enum Commands
         #[arg(short, long)]
         digest: String,
 
-        /// Path to the vendor private key
+        /// Path to the vendor private key or its PKCS#11 URI
         #[arg(short, long)]
         vendor_prv: String,
 
@@ -99,6 +147,10 @@ enum Commands
         /// Path to the root-ca public-key
         #[arg(short, long)]
         ca_pub: String,
+
+        /// Number of days the signature stays valid (no expiry by default)
+        #[arg(short, long)]
+        expiry_days: Option<u64>,
     },
 
     /// Rehash a file and rename it
@@ -137,7 +189,7 @@ enum Commands
         #[arg(short = 'd', long)]
         reference: String,
 
-        /// Path to the vendor private key
+        /// Path to the vendor private key or its PKCS#11 URI
         #[arg(short, long)]
         vendor_prv: String,
 
@@ -149,9 +201,21 @@ enum Commands
         #[arg(short, long)]
         ca_pub: Option<String>,
 
-        /// Path to the root-ca private key
+        /// Path to the root-ca private key or its PKCS#11 URI
         #[arg(short = 'x', long)]
         ca_prv: Option<String>,
+
+        /// Number of days the signature stays valid (no expiry by default)
+        #[arg(short, long)]
+        expiry_days: Option<u64>,
+
+        /// Signature format
+        #[arg(long, value_enum, default_value_t = subcmds::SignatureFormat::Native)]
+        format: subcmds::SignatureFormat,
+
+        /// Print the planned digest changes without writing anything
+        #[arg(long)]
+        dry_run: bool,
     },
 
     /// Extract and sign the image/config from a specific manifest and fix the application
@@ -165,7 +229,8 @@ enum Commands
         #[arg(short, long, default_value = "../registry")]
         registry: String,
 
-        /// Unpacked TAR filename with the OCI image to extract
+        /// TAR file (plain, gzip or zstd) with the OCI image or docker save output
+        /// to extract
         #[arg(short, long)]
         filename: String,
 
@@ -177,7 +242,7 @@ enum Commands
         #[arg(short = 'd', long)]
         reference: String,
 
-        /// Path to the vendor private key
+        /// Path to the vendor private key or its PKCS#11 URI
         #[arg(short, long)]
         vendor_prv: String,
 
@@ -189,9 +254,145 @@ enum Commands
         #[arg(short, long)]
         ca_pub: Option<String>,
 
-        /// Path to the root-ca private key
+        /// Path to the root-ca private key or its PKCS#11 URI
         #[arg(short = 'x', long)]
         ca_prv: Option<String>,
+
+        /// Number of days the signature stays valid (no expiry by default)
+        #[arg(short, long)]
+        expiry_days: Option<u64>,
+
+        /// Signature format
+        #[arg(long, value_enum, default_value_t = subcmds::SignatureFormat::Native)]
+        format: subcmds::SignatureFormat,
+    },
+
+    /// Sign all the tags matching a pattern in every application of the registry
+    /// You need to pass one of the two:
+    ///   1. VENDOR_PUB_SIGNATURE and CA_PUB
+    ///   2. CA_PRV
+    #[command(verbatim_doc_comment)]
+    SignRegistry
+    {
+        /// Path to the registry
+        #[arg(short, long, default_value = "../registry")]
+        registry: String,
+
+        /// Tags to sign, "*" and "?" wildcards are supported
+        #[arg(short = 'p', long, default_value = "*")]
+        tag_pattern: String,
+
+        /// Path to the vendor private key or its PKCS#11 URI
+        #[arg(short, long)]
+        vendor_prv: String,
+
+        /// Path to the signature of vendor public key signed by root-ca
+        #[arg(short = 's', long)]
+        vendor_pub_signature: Option<String>,
+
+        /// Path to the root-ca public-key
+        #[arg(short, long)]
+        ca_pub: Option<String>,
+
+        /// Path to the root-ca private key or its PKCS#11 URI
+        #[arg(short = 'x', long)]
+        ca_prv: Option<String>,
+
+        /// Number of days the signature stays valid (no expiry by default)
+        #[arg(short, long)]
+        expiry_days: Option<u64>,
+
+        /// Signature format
+        #[arg(long, value_enum, default_value_t = subcmds::SignatureFormat::Native)]
+        format: subcmds::SignatureFormat,
+
+        /// Print the planned digest changes without writing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Build an image from a root filesystem directory and tag it in the
+    /// registry, the application is created if it doesn't exist
+    Build
+    {
+        /// Path to the registry
+        #[arg(short, long, default_value = "../registry")]
+        registry: String,
+
+        /// Name of the application in registry
+        #[arg(short, long, default_value = "com.samsung.example.app")]
+        app: String,
+
+        /// Tag of the built image (moved if it already exists)
+        #[arg(short, long)]
+        tag: String,
+
+        /// Path to the root filesystem directory
+        #[arg(short = 'i', long)]
+        rootfs: String,
+
+        /// Entrypoint, one argument per option
+        #[arg(long, allow_hyphen_values = true)]
+        entrypoint: Vec<String>,
+
+        /// Default arguments of the entrypoint, one argument per option
+        #[arg(long, allow_hyphen_values = true)]
+        cmd: Vec<String>,
+
+        /// Environment variable as NAME=VALUE, can be given multiple times
+        #[arg(long)]
+        env: Vec<String>,
+
+        /// Label as NAME=VALUE, can be given multiple times
+        #[arg(long)]
+        label: Vec<String>,
+
+        /// Working directory of the entrypoint
+        #[arg(long)]
+        workdir: Option<String>,
+
+        /// CPU architecture of the image (GOARCH)
+        #[arg(long, default_value = "arm64")]
+        architecture: String,
+
+        /// Operating system of the image (GOOS)
+        #[arg(long, default_value = "linux")]
+        os: String,
+
+        /// Layer compression
+        #[arg(long, value_enum, default_value_t = builder::Compression::Gzip)]
+        compression: builder::Compression,
+    },
+
+    /// Append a layer made of a directory or a tarball to an image, the image
+    /// needs to be signed again afterwards
+    AppendLayer
+    {
+        /// Path to the registry
+        #[arg(short, long, default_value = "../registry")]
+        registry: String,
+
+        /// Name of the application in registry
+        #[arg(short, long, default_value = "com.samsung.example.app")]
+        app: String,
+
+        /// Reference of the manifest to append the layer to
+        #[arg(short = 'd', long)]
+        reference: String,
+
+        /// Path to a directory or a tarball (plain, gzip or zstd) with the layer
+        #[arg(short = 'i', long)]
+        layer: String,
+
+        /// Tag of the new image (moved if it already exists), without it the
+        /// new image replaces the old one
+        #[arg(short, long)]
+        tag: Option<String>,
+
+        /// Compression of a layer made of a directory, tarballs are kept as
+        /// they are
+        #[arg(long, value_enum, default_value_t = builder::Compression::Gzip)]
+        compression: builder::Compression,
     },
 
     /// Verify the image/config from a specific manifest
@@ -212,6 +413,103 @@ enum Commands
         /// Path to the root-ca public-key
         #[arg(short, long)]
         ca_pub: String,
+
+        /// Path to the revocation list of vendor keys
+        #[arg(short = 'l', long)]
+        revocation_list: Option<String>,
+
+        /// Path to the signature of revocation list signed by root-ca
+        #[arg(short = 'g', long)]
+        revocation_signature: Option<String>,
+
+        /// Path to a trusted vendor public-key, can be given multiple times
+        /// (any vendor key signed by root-ca is accepted by default)
+        #[arg(short, long)]
+        trusted_key: Vec<String>,
+
+        /// Number of valid signatures made with distinct vendor keys required
+        #[arg(short = 'n', long, default_value_t = 1)]
+        threshold: usize,
+
+        /// Only count signatures with an expiry, signatures made without a
+        /// timestamp carry none
+        #[arg(long)]
+        require_expiry: bool,
+
+        /// Signature format
+        #[arg(long, value_enum, default_value_t = subcmds::SignatureFormat::Native)]
+        format: subcmds::SignatureFormat,
+    },
+
+    /// Verify all the tags matching a pattern in every application of the
+    /// registry and report which verified, failed or are unsigned as JSON
+    VerifyRegistry
+    {
+        /// Path to the registry
+        #[arg(short, long, default_value = "../registry")]
+        registry: String,
+
+        /// Tags to verify, "*" and "?" wildcards are supported
+        #[arg(short = 'p', long, default_value = "*")]
+        tag_pattern: String,
+
+        /// Path to the root-ca public-key
+        #[arg(short, long)]
+        ca_pub: String,
+
+        /// Path to the revocation list of vendor keys
+        #[arg(short = 'l', long)]
+        revocation_list: Option<String>,
+
+        /// Path to the signature of revocation list signed by root-ca
+        #[arg(short = 'g', long)]
+        revocation_signature: Option<String>,
+
+        /// Path to a trusted vendor public-key, can be given multiple times
+        /// (any vendor key signed by root-ca is accepted by default)
+        #[arg(short, long)]
+        trusted_key: Vec<String>,
+
+        /// Number of valid signatures made with distinct vendor keys required
+        #[arg(short = 'n', long, default_value_t = 1)]
+        threshold: usize,
+
+        /// Only count signatures with an expiry, signatures made without a
+        /// timestamp carry none
+        #[arg(long)]
+        require_expiry: bool,
+
+        /// Signature format
+        #[arg(long, value_enum, default_value_t = subcmds::SignatureFormat::Native)]
+        format: subcmds::SignatureFormat,
+
+        /// Path to the JSON report (stdout by default)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Add a vendor public key to the revocation list and sign it
+    RevokeKey
+    {
+        /// Path to the vendor public key to revoke
+        #[arg(short, long)]
+        key: String,
+
+        /// Path to the revocation list (created if it doesn't exist)
+        #[arg(short = 'l', long)]
+        revocation_list: String,
+
+        /// Path to the signature of revocation list
+        #[arg(short = 'g', long)]
+        revocation_signature: String,
+
+        /// Path to the root-ca private key or its PKCS#11 URI
+        #[arg(short = 'x', long)]
+        ca_prv: String,
+
+        /// Output format of the revocation list signature: der or pem
+        #[arg(long, value_enum, default_value_t = format::FileFormat::Der)]
+        format: format::FileFormat,
     },
 }
 
@@ -221,14 +519,34 @@ fn main() -> SignerResult<()>
 
     let cli = Cli::parse();
 
+    reproducible::set_enabled(cli.reproducible);
+    // fail early rather than halfway through writing an image
+    reproducible::source_date_epoch()?;
+
+    passphrase::set_source(match (cli.passphrase_env, cli.passphrase_fd) {
+        (Some(var), _) => passphrase::PassphraseSource::Env(var),
+        (None, Some(fd)) => passphrase::PassphraseSource::Fd(fd),
+        (None, None) => passphrase::PassphraseSource::Prompt,
+    })?;
+
     match cli.command {
-        Commands::GenKey { output } => subcmds::cmd_generate_key(&output)?,
-        Commands::ExtractPublic { input, output } => subcmds::cmd_extract_public(&input, &output)?,
+        Commands::GenKey {
+            output,
+            algorithm,
+            encrypt,
+            format,
+        } => subcmds::cmd_generate_key(&output, algorithm, encrypt, format)?,
+        Commands::ExtractPublic {
+            input,
+            output,
+            format,
+        } => subcmds::cmd_extract_public(&input, &output, format)?,
         Commands::Sign {
             key,
             file,
             signature,
-        } => subcmds::cmd_sign_buf(&key, &file, &signature)?,
+            format,
+        } => subcmds::cmd_sign_buf(&key, &file, &signature, format)?,
         Commands::Verify {
             key,
             signature,
@@ -241,6 +559,7 @@ fn main() -> SignerResult<()>
             vendor_prv,
             vendor_pub_signature,
             ca_pub,
+            expiry_days,
         } => subcmds::cmd_sign_config(
             &registry,
             &app,
@@ -248,6 +567,7 @@ fn main() -> SignerResult<()>
             &vendor_prv,
             &vendor_pub_signature,
             &ca_pub,
+            expiry_days,
         )?,
         Commands::RehashFile {
             registry,
@@ -262,14 +582,22 @@ fn main() -> SignerResult<()>
             vendor_pub_signature,
             ca_pub,
             ca_prv,
+            expiry_days,
+            format,
+            dry_run,
         } => subcmds::cmd_sign_image(
             &registry,
             &app,
             &reference,
-            &vendor_prv,
-            vendor_pub_signature.as_deref(),
-            ca_pub.as_deref(),
-            ca_prv.as_deref(),
+            &subcmds::SigningKeys {
+                vendor_prv: &vendor_prv,
+                vendor_pub_signature: vendor_pub_signature.as_deref(),
+                ca_pub: ca_pub.as_deref(),
+                ca_prv: ca_prv.as_deref(),
+            },
+            expiry_days,
+            format,
+            dry_run,
         )?,
         Commands::ExtractSignImage {
             registry,
@@ -280,22 +608,152 @@ fn main() -> SignerResult<()>
             vendor_pub_signature,
             ca_pub,
             ca_prv,
+            expiry_days,
+            format,
         } => subcmds::cmd_extract_sign_image(
             &registry,
             &filename,
             app.as_deref(),
             &reference,
-            &vendor_prv,
-            vendor_pub_signature.as_deref(),
-            ca_pub.as_deref(),
-            ca_prv.as_deref(),
+            &subcmds::SigningKeys {
+                vendor_prv: &vendor_prv,
+                vendor_pub_signature: vendor_pub_signature.as_deref(),
+                ca_pub: ca_pub.as_deref(),
+                ca_prv: ca_prv.as_deref(),
+            },
+            expiry_days,
+            format,
+        )?,
+        Commands::SignRegistry {
+            registry,
+            tag_pattern,
+            vendor_prv,
+            vendor_pub_signature,
+            ca_pub,
+            ca_prv,
+            expiry_days,
+            format,
+            dry_run,
+        } => subcmds::cmd_sign_registry(
+            &registry,
+            &tag_pattern,
+            &subcmds::SigningKeys {
+                vendor_prv: &vendor_prv,
+                vendor_pub_signature: vendor_pub_signature.as_deref(),
+                ca_pub: ca_pub.as_deref(),
+                ca_prv: ca_prv.as_deref(),
+            },
+            expiry_days,
+            format,
+            dry_run,
+        )?,
+        Commands::Build {
+            registry,
+            app,
+            tag,
+            rootfs,
+            entrypoint,
+            cmd,
+            env,
+            label,
+            workdir,
+            architecture,
+            os,
+            compression,
+        } => subcmds::cmd_build(
+            &registry,
+            &app,
+            &tag,
+            &rootfs,
+            &builder::ImageSpec {
+                entrypoint: &entrypoint,
+                cmd: &cmd,
+                env: &env,
+                labels: &label,
+                working_dir: workdir.as_deref(),
+                architecture: &architecture,
+                os: &os,
+            },
+            compression,
+        )?,
+        Commands::AppendLayer {
+            registry,
+            app,
+            reference,
+            layer,
+            tag,
+            compression,
+        } => subcmds::cmd_append_layer(
+            &registry,
+            &app,
+            &reference,
+            &layer,
+            tag.as_deref(),
+            compression,
         )?,
         Commands::VerifyImage {
             registry,
             app,
             reference,
             ca_pub,
-        } => subcmds::cmd_verify_image(&registry, &app, &reference, &ca_pub)?,
+            revocation_list,
+            revocation_signature,
+            trusted_key,
+            threshold,
+            require_expiry,
+            format,
+        } => subcmds::cmd_verify_image(
+            &registry,
+            &app,
+            &reference,
+            &subcmds::TrustPolicy {
+                ca_pub: &ca_pub,
+                revocation_list: revocation_list.as_deref(),
+                revocation_signature: revocation_signature.as_deref(),
+                trusted_keys: &trusted_key,
+                threshold,
+                require_expiry,
+            },
+            format,
+        )?,
+        Commands::VerifyRegistry {
+            registry,
+            tag_pattern,
+            ca_pub,
+            revocation_list,
+            revocation_signature,
+            trusted_key,
+            threshold,
+            require_expiry,
+            format,
+            output,
+        } => subcmds::cmd_verify_registry(
+            &registry,
+            &tag_pattern,
+            &subcmds::TrustPolicy {
+                ca_pub: &ca_pub,
+                revocation_list: revocation_list.as_deref(),
+                revocation_signature: revocation_signature.as_deref(),
+                trusted_keys: &trusted_key,
+                threshold,
+                require_expiry,
+            },
+            format,
+            output.as_deref(),
+        )?,
+        Commands::RevokeKey {
+            key,
+            revocation_list,
+            revocation_signature,
+            ca_prv,
+            format,
+        } => subcmds::cmd_revoke_key(
+            &key,
+            &revocation_list,
+            &revocation_signature,
+            &ca_prv,
+            format,
+        )?,
     }
 
     Ok(())
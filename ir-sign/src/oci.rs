@@ -1,23 +1,25 @@
-use log::info;
+use log::{error, info};
 use oci_spec::image::{ImageIndex, ImageManifest, MediaType, ANNOTATION_REF_NAME};
 use std::collections::HashMap;
 use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 
-use crate::{crypto, digest::Digest, error::SignerError, utils, SignerResult};
+use crate::cosign;
+use crate::crypto::PrivateKey;
+use crate::revocation::RevocationList;
+use crate::signature::ImageSignature;
+use crate::transaction::Transaction;
+use crate::{crypto, digest::Digest, error::SignerError, reproducible, utils, SignerResult};
 
-const ANNOTATION_SIGNATURE: &str = "com.samsung.islet.image.signature";
-const ANNOTATION_VENDORPUB: &str = "com.samsung.islet.image.vendorpub";
-const ANNOTATION_VENDORPUB_SIGNATURE: &str = "com.samsung.islet.image.vendorpub.signature";
-
-const INDEX_JSON: &str = "index.json";
+pub(crate) const INDEX_JSON: &str = "index.json";
+pub(crate) const BLOBS_SUBDIR: &str = "blobs";
+pub(crate) const OCI_LAYOUT: &str = "oci-layout";
+const JSON_PROBE_LEN: u64 = 64;
 
 macro_rules! err {
     ($($arg:tt)+) => (Err(SignerError::OciRegistry(format!($($arg)+))))
 }
-macro_rules! er {
-    ($($arg:tt)+) => (SignerError::OciRegistry(format!($($arg)+)))
-}
 
 pub(crate) fn find_manifest_by_reference(app: &Path, reference: &str) -> SignerResult<String>
 {
@@ -43,49 +45,179 @@ pub(crate) fn find_manifest_by_reference(app: &Path, reference: &str) -> SignerR
     Ok(digest.unwrap().to_string())
 }
 
+/// Applications in a registry, directories with an OCI image layout
+pub(crate) fn list_apps(registry: &Path) -> SignerResult<Vec<String>>
+{
+    let mut apps = Vec::new();
+
+    for entry in std::fs::read_dir(registry)? {
+        let path = entry?.path();
+        if !path.join(OCI_LAYOUT).is_file() {
+            info!("Skipping \"{}\", not an OCI image layout", path.display());
+            continue;
+        }
+        if let Some(name) = path.file_name() {
+            apps.push(name.to_string_lossy().to_string());
+        }
+    }
+
+    apps.sort();
+    Ok(apps)
+}
+
+/// Tags of an application, without the ones of cosign signature manifests
+pub(crate) fn list_tags(app: &Path) -> SignerResult<Vec<String>>
+{
+    let index = ImageIndex::from_file(app.join(INDEX_JSON))?;
+    let mut tags = Vec::new();
+
+    for desc in index.manifests() {
+        let tag = desc
+            .annotations()
+            .as_ref()
+            .and_then(|anns| anns.get(ANNOTATION_REF_NAME));
+        match tag {
+            Some(tag) if !cosign::is_signature_tag(tag) => tags.push(tag.clone()),
+            _ => (),
+        }
+    }
+
+    Ok(tags)
+}
+
+/// Requirements for an image to be considered verified
+pub(crate) struct VerifyPolicy<'a>
+{
+    /// Minimal number of valid signatures made with distinct keys
+    pub threshold: usize,
+    /// Vendor public keys whose signatures count, any key signed by ROOT-CA if empty
+    pub trusted: Vec<Vec<u8>>,
+    pub revocation: Option<&'a RevocationList>,
+    /// Reject signatures without an expiry, including the ones made without
+    /// a timestamp which can't carry one
+    pub require_expiry: bool,
+}
+
+impl VerifyPolicy<'_>
+{
+    /// Verify everything about a signature but the signed content itself:
+    /// revocation, expiry and the vendor_pub signed by ROOT-CA
+    pub fn verify_vendor(&self, signature: &ImageSignature, ca_pub: &[u8]) -> SignerResult<()>
+    {
+        // reject retired vendor keys before anything else
+        if let Some(revocation) = self.revocation {
+            if revocation.is_revoked(&signature.vendor_pub)? {
+                err!("Vendor pub key has been revoked")?;
+            }
+        }
+
+        if self.require_expiry && signature.timestamp.is_none() {
+            err!("Signature without a timestamp, an expiry is required")?;
+        }
+        match signature.expiry()? {
+            Some(expiry) if utils::unix_time() > expiry => {
+                err!("Signature expired at {}", expiry)?
+            }
+            None if self.require_expiry => err!("Signature without an expiry")?,
+            _ => (),
+        }
+
+        let c_pub = crypto::import_public(ca_pub)?;
+        c_pub
+            .verify(&signature.vendor_pub, &signature.vendor_pub_sign)
+            .or(err!("Vendor pub signature verification failed"))?;
+
+        Ok(())
+    }
+
+    /// Count the distinct trusted keys with valid signatures against the
+    /// threshold, `verify` is called for every signature that may count
+    pub fn enforce<S, F>(&self, signatures: &[S], verify: F) -> SignerResult<()>
+    where
+        S: AsRef<ImageSignature>,
+        F: Fn(&S) -> SignerResult<()>,
+    {
+        if signatures.is_empty() {
+            err!("Manifest does not contain signatures")?;
+        }
+
+        let mut verified = Vec::<&[u8]>::new();
+        for (slot, s) in signatures.iter().enumerate() {
+            let signature = s.as_ref();
+            if !self.trusted.is_empty() && !self.trusted.contains(&signature.vendor_pub) {
+                info!(
+                    "Signature in slot {} made with an untrusted key, skipping",
+                    slot
+                );
+                continue;
+            }
+            if verified.contains(&signature.vendor_pub.as_slice()) {
+                continue;
+            }
+            match verify(s) {
+                Ok(()) => {
+                    info!("Signature in slot {} verified", slot);
+                    verified.push(&signature.vendor_pub);
+                }
+                Err(e) => error!("Signature in slot {} rejected: {}", slot, e),
+            }
+        }
+
+        if verified.len() < self.threshold {
+            err!(
+                "Only {} valid signature(s) found, {} required",
+                verified.len(),
+                self.threshold
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
 pub(crate) fn verify_vendor_pub_signature(
-    vendor_prv: &[u8],
+    v_prv: &dyn PrivateKey,
     vendor_pub_signature: &[u8],
     ca_pub: &[u8],
 ) -> SignerResult<()>
 {
-    let v_prv = crypto::import_private(vendor_prv)?;
-    let v_pub = crypto::extract_public(&v_prv);
-    let v_pub_u8 = crypto::export_public(&v_pub)?;
+    let v_pub = v_prv.public_key();
+    let v_pub_u8 = v_pub.to_der()?;
     let c_pub = crypto::import_public(ca_pub)?;
 
     // verify the vendor_pub_signature with public ca key
-    crypto::verify(&c_pub, &v_pub_u8, vendor_pub_signature)
+    c_pub
+        .verify(&v_pub_u8, vendor_pub_signature)
         .or(err!("Vendor pub signature verification failed"))?;
 
     Ok(())
 }
 
-pub(crate) fn sign_vendor_pub(vendor_prv: &[u8], ca_prv: &[u8]) -> SignerResult<Vec<u8>>
+pub(crate) fn sign_vendor_pub(
+    v_prv: &dyn PrivateKey,
+    c_prv: &dyn PrivateKey,
+) -> SignerResult<Vec<u8>>
 {
-    let v_prv = crypto::import_private(vendor_prv)?;
-    let v_pub = crypto::extract_public(&v_prv);
-    let v_pub_u8 = crypto::export_public(&v_pub)?;
-    let c_prv = crypto::import_private(ca_prv)?;
+    let v_pub = v_prv.public_key();
+    let v_pub_u8 = v_pub.to_der()?;
 
     // sign the vendor verification/public key with private ca key
-    let v_sign = crypto::sign(&c_prv, &v_pub_u8)?;
+    let v_sign = c_prv.sign(&v_pub_u8)?;
 
     Ok(v_sign)
 }
 
-pub(crate) fn sign_config<T: AsRef<Path>>(
-    blobs: T,
+/// Manifest with the config signature added to its annotations
+fn signed_manifest(
+    blobs: &Path,
     digest: &str,
-    vendor_prv: &[u8],
+    v_prv: &dyn PrivateKey,
     vendor_pub_signature: &[u8],
-) -> SignerResult<()>
+    expiry: Option<u64>,
+) -> SignerResult<ImageManifest>
 {
-    let blobs = blobs.as_ref();
-
-    let v_prv = crypto::import_private(vendor_prv)?;
-    let v_pub = crypto::extract_public(&v_prv);
-    let v_pub_u8 = crypto::export_public(&v_pub)?;
+    let v_pub = v_prv.public_key();
+    let v_pub_u8 = v_pub.to_der()?;
 
     // load manifest
     let manifest_digest = Digest::try_from(digest)?;
@@ -98,8 +230,26 @@ pub(crate) fn sign_config<T: AsRef<Path>>(
     let config_path = blobs.join(config_digest.to_path());
 
     // sign the config with vendor key
-    let mut config = File::open(config_path)?;
-    let config_sign = crypto::sign_reader(&v_prv, &mut config)?;
+    let mut signature = ImageSignature {
+        config_sign: Vec::new(),
+        vendor_pub: v_pub_u8,
+        vendor_pub_sign: vendor_pub_signature.to_vec(),
+        timestamp: Some(utils::unix_time().to_string()),
+        expiry: expiry.map(|e| e.to_string()),
+        algorithm: Some(v_prv.algorithm().to_string()),
+    };
+    signature.config_sign = match signature.envelope(&config_digest.to_string())? {
+        Some(envelope) => {
+            // the config itself is not signed then, make sure it's the right one
+            let mut config = File::open(config_path)?;
+            let config_hash = crypto::hash_reader(config_digest.algo(), &mut config)?;
+            if config_hash != hex::decode(config_digest.hash().as_bytes())? {
+                err!("Config hash mismatch")?;
+            }
+            v_prv.sign(&envelope)?
+        }
+        None => v_prv.sign_reader(&mut File::open(config_path)?)?,
+    };
 
     // get/create annotations
     let annotations = manifest.annotations_mut();
@@ -112,19 +262,98 @@ pub(crate) fn sign_config<T: AsRef<Path>>(
         Some(a) => a,
     };
 
-    // ammend annotations
-    annotations.insert(ANNOTATION_SIGNATURE.to_string(), hex::encode(config_sign));
-    annotations.insert(ANNOTATION_VENDORPUB.to_string(), hex::encode(v_pub_u8));
-    annotations.insert(
-        ANNOTATION_VENDORPUB_SIGNATURE.to_string(),
-        hex::encode(vendor_pub_signature),
-    );
+    // ammend annotations, keep signatures made with other keys
+    let signatures = ImageSignature::read_all(annotations)?;
+    let slot = ImageSignature::find_slot(&signatures, &signature.vendor_pub);
+    info!("Writing signature into slot {}", slot);
+    signature.write(annotations, slot);
+
+    Ok(manifest)
+}
+
+/// Sign the config in place, the manifest needs to be rehashed afterwards
+pub(crate) fn sign_config<T: AsRef<Path>>(
+    blobs: T,
+    digest: &str,
+    v_prv: &dyn PrivateKey,
+    vendor_pub_signature: &[u8],
+    expiry: Option<u64>,
+) -> SignerResult<()>
+{
+    let blobs = blobs.as_ref();
+    let manifest_digest = Digest::try_from(digest)?;
+    let manifest = signed_manifest(blobs, digest, v_prv, vendor_pub_signature, expiry)?;
+    reproducible::write_json(blobs.join(manifest_digest.to_path()), &manifest)?;
+
+    Ok(())
+}
+
+/// Sign the config as a part of a transaction. The signed manifest is written
+/// as a new blob and all the indexes referencing it are replaced up to
+/// "index.json", returns the digest of the signed manifest.
+pub(crate) fn sign_image(
+    tx: &mut Transaction,
+    digest: &str,
+    v_prv: &dyn PrivateKey,
+    vendor_pub_signature: &[u8],
+    expiry: Option<u64>,
+) -> SignerResult<String>
+{
+    let manifest = signed_manifest(&tx.blobs(), digest, v_prv, vendor_pub_signature, expiry)?;
+    let manifest_digest = Digest::try_from(digest)?;
+    let manifest = reproducible::to_json(&manifest)?;
+    let (signed_digest, signed_len) = tx.write_blob(manifest_digest.algo(), &manifest)?;
+
+    if signed_digest == digest {
+        info!("Manifest does not require renaming");
+        return Ok(signed_digest);
+    }
+
+    info!("Signed manifest: \"{}\"", signed_digest);
+    replace_manifest(tx, digest, &signed_digest, signed_len)?;
+
+    Ok(signed_digest)
+}
+
+/// Replace a manifest with another one in all the indexes referencing it up to
+/// "index.json", the old manifest is removed
+pub(crate) fn replace_manifest(
+    tx: &mut Transaction,
+    from: &str,
+    to: &str,
+    len: i64,
+) -> SignerResult<()>
+{
+    tx.replace(from, to);
 
-    manifest.to_file_pretty(&manifest_path)?;
+    info!("Updating all indexes referencing the manifest");
+    let mut index = tx.index().clone();
+    replace_in_index(tx, &mut index, from, to, len)?;
+    *tx.index_mut() = index;
 
     Ok(())
 }
 
+/// Rewrite a JSON blob (manifest, index or config) with reproducible::to_json(),
+/// other blobs are left untouched
+fn canonicalize_json(path: &Path) -> SignerResult<()>
+{
+    // don't read whole layers just to find out they're not JSON
+    let mut start = Vec::new();
+    File::open(path)?
+        .take(JSON_PROBE_LEN)
+        .read_to_end(&mut start)?;
+    if start.iter().find(|b| !b.is_ascii_whitespace()) != Some(&b'{') {
+        return Ok(());
+    }
+
+    let data = utils::file_read(path)?;
+    match serde_json::from_slice::<serde_json::Value>(&data) {
+        Ok(value) => reproducible::write_json(path, &value),
+        Err(_) => Ok(()),
+    }
+}
+
 pub(crate) fn rehash_rename_file<T: AsRef<Path>>(
     blobs: T,
     digest: &str,
@@ -133,6 +362,12 @@ pub(crate) fn rehash_rename_file<T: AsRef<Path>>(
     let blobs = blobs.as_ref();
     let digest = Digest::try_from(digest)?;
     let path = blobs.join(digest.to_path());
+
+    // JSON written by other tools is rewritten the way ir-sign writes it
+    if reproducible::is_enabled() {
+        canonicalize_json(&path)?;
+    }
+
     let mut file = File::open(&path)?;
 
     let hash = crypto::hash_reader(digest.algo(), &mut file)?;
@@ -149,103 +384,58 @@ pub(crate) fn rehash_rename_file<T: AsRef<Path>>(
     }
 }
 
-enum IndexSource<'a>
-{
-    Path(&'a Path),
-    Digest(&'a str),
-}
-
-fn replace_hash_index_inner(
-    blobs: &Path,
-    index: IndexSource,
+/// Replace references to a manifest in an index and the nested indexes, the
+/// modified nested indexes are written as new blobs. Returns whether the index
+/// was modified.
+fn replace_in_index(
+    tx: &mut Transaction,
+    index: &mut ImageIndex,
     signed_from: &str,
     signed_to: &str,
     signed_len: i64,
-) -> SignerResult<Option<(String, i64)>>
+) -> SignerResult<bool>
 {
-    let current_path = match index {
-        IndexSource::Path(path) => path.to_owned(),
-        IndexSource::Digest(digest) => {
-            let current_digest = Digest::try_from(digest)?;
-            blobs.join(current_digest.to_path())
-        }
-    };
-
-    let mut image_index = ImageIndex::from_file(&current_path)?;
-    let mut manifests = image_index.manifests().clone();
-
+    let mut manifests = index.manifests().clone();
     let mut modified: bool = false;
 
     for descriptor in &mut manifests {
         match descriptor.media_type() {
             MediaType::ImageIndex => {
-                let new_info = replace_hash_index_inner(
-                    blobs,
-                    IndexSource::Digest(descriptor.digest()),
-                    signed_from,
-                    signed_to,
-                    signed_len,
-                )?;
-                if let Some((new_digest, new_len)) = new_info {
+                let nested_digest = descriptor.digest().clone();
+                let data = tx.read_blob(&nested_digest)?;
+                let mut nested = ImageIndex::from_reader(data.as_slice())?;
+                if replace_in_index(tx, &mut nested, signed_from, signed_to, signed_len)? {
+                    let algo = Digest::try_from(&nested_digest)?.algo().to_string();
+                    let nested = reproducible::to_json(&nested)?;
+                    let (new_digest, new_len) = tx.write_blob(&algo, &nested)?;
+                    info!("Index \"{}\" rehashed to \"{}\"", nested_digest, new_digest);
+                    tx.replace(&nested_digest, &new_digest);
                     descriptor.set_digest(new_digest);
                     descriptor.set_size(new_len);
                     modified = true;
                 }
             }
-            MediaType::ImageManifest => {
-                if descriptor.digest() == signed_from {
-                    descriptor.set_digest(signed_to.to_string());
-                    descriptor.set_size(signed_len);
-                    modified = true;
-                }
+            MediaType::ImageManifest if descriptor.digest() == signed_from => {
+                descriptor.set_digest(signed_to.to_string());
+                descriptor.set_size(signed_len);
+                modified = true;
             }
             _ => (),
         }
     }
 
     if modified {
-        image_index.set_manifests(manifests);
-        image_index.to_file_pretty(&current_path)?;
-
-        // rename only if given as digest, don't rename index.json
-        if let IndexSource::Digest(digest) = index {
-            let new_info = rehash_rename_file(blobs, digest)?;
-            return Ok(new_info);
-        }
+        index.set_manifests(manifests);
     }
 
-    Ok(None)
-}
-
-pub(crate) fn replace_hash_index<T: AsRef<Path>>(
-    blobs: T,
-    signed_from: &str,
-    signed_to: &str,
-) -> SignerResult<()>
-{
-    let blobs_path = blobs.as_ref();
-    let index_path = blobs_path.join("..").join(INDEX_JSON);
-
-    let signed_digest = Digest::try_from(signed_to)?;
-    let signed_path = blobs_path.join(signed_digest.to_path());
-    let signed_len = utils::file_len(&signed_path)?.try_into().unwrap();
-
-    // start the recursion from "index.json"
-    replace_hash_index_inner(
-        blobs_path,
-        IndexSource::Path(&index_path),
-        signed_from,
-        signed_to,
-        signed_len,
-    )?;
-
-    Ok(())
+    Ok(modified)
 }
 
 pub(crate) fn verify_config<T: AsRef<Path>>(
     blobs: T,
     digest: &str,
     ca_pub: &[u8],
+    policy: &VerifyPolicy,
 ) -> SignerResult<()>
 {
     let blobs = blobs.as_ref();
@@ -274,31 +464,182 @@ pub(crate) fn verify_config<T: AsRef<Path>>(
     let Some(annotations) = annotations else {
         return err!("Manifest does not contain annotations");
     };
-    let config_sign = annotations
-        .get(ANNOTATION_SIGNATURE)
-        .ok_or(er!("Missing SIGNATURE annotation"))?;
-    let v_pub = annotations
-        .get(ANNOTATION_VENDORPUB)
-        .ok_or(er!("Missing VENDORPUB annotation"))?;
-    let v_pub_sig = annotations
-        .get(ANNOTATION_VENDORPUB_SIGNATURE)
-        .ok_or(er!("Missing VENDORPUB_SIGNATURE annotation"))?;
-
-    // all annotations are hex strings, convert to something usable
-    let config_sig = hex::decode(config_sign.as_bytes())?;
-    let v_pub_u8 = hex::decode(v_pub.as_bytes())?;
-    let v_pub_sig = hex::decode(v_pub_sig.as_bytes())?;
-
-    // verify the vendor_pub key
-    let c_pub = crypto::import_public(ca_pub)?;
-    crypto::verify(&c_pub, &v_pub_u8, &v_pub_sig)
-        .or(err!("Vendor pub signature verification failed"))?;
+    let signatures = ImageSignature::read_all(annotations)?;
+    policy.enforce(&signatures, |signature| {
+        verify_signature(&config_path, &config_digest, signature, ca_pub, policy)
+    })
+}
+
+/// Whether a manifest has signature annotations, valid or not
+pub(crate) fn is_signed<T: AsRef<Path>>(blobs: T, digest: &str) -> SignerResult<bool>
+{
+    let manifest_digest = Digest::try_from(digest)?;
+    let manifest = ImageManifest::from_file(blobs.as_ref().join(manifest_digest.to_path()))?;
+
+    match manifest.annotations() {
+        Some(annotations) => Ok(!ImageSignature::read_all(annotations)?.is_empty()),
+        None => Ok(false),
+    }
+}
+
+fn verify_signature(
+    config_path: &Path,
+    config_digest: &Digest,
+    signature: &ImageSignature,
+    ca_pub: &[u8],
+    policy: &VerifyPolicy,
+) -> SignerResult<()>
+{
+    policy.verify_vendor(signature, ca_pub)?;
 
     // verify the config signature
-    let v_pub = crypto::import_public(&v_pub_u8)?;
-    let mut config = File::open(&config_path)?;
-    crypto::verify_reader(&v_pub, &mut config, &config_sig)
-        .or(err!("Config signature verification failed"))?;
+    let v_pub = crypto::import_public_as(signature.algorithm()?, &signature.vendor_pub)?;
+    let verified = match signature.envelope(&config_digest.to_string())? {
+        Some(envelope) => v_pub.verify(&envelope, &signature.config_sign),
+        None => v_pub.verify_reader(&mut File::open(config_path)?, &signature.config_sign),
+    };
+    verified.or(err!("Config signature verification failed"))?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::{sign_vendor_pub, VerifyPolicy};
+    use crate::crypto::{self, KeyAlgorithm, PrivateKey};
+    use crate::revocation::RevocationList;
+    use crate::signature::ImageSignature;
+
+    fn vendor_signature(vendor: &dyn PrivateKey, ca: &dyn PrivateKey) -> ImageSignature
+    {
+        ImageSignature {
+            config_sign: Vec::new(),
+            vendor_pub: vendor.public_key().to_der().unwrap(),
+            vendor_pub_sign: sign_vendor_pub(vendor, ca).unwrap(),
+            timestamp: None,
+            expiry: None,
+            algorithm: None,
+        }
+    }
+
+    fn policy(revocation: Option<&RevocationList>) -> VerifyPolicy<'_>
+    {
+        VerifyPolicy {
+            threshold: 1,
+            trusted: Vec::new(),
+            revocation,
+            require_expiry: false,
+        }
+    }
+
+    #[test]
+    fn oci_verify_vendor()
+    {
+        let ca = crypto::generate_key(KeyAlgorithm::EcdsaP384).unwrap();
+        let ca_pub = ca.public_key().to_der().unwrap();
+        let vendor = crypto::generate_key(KeyAlgorithm::EcdsaP256).unwrap();
+        let mut signature = vendor_signature(vendor.as_ref(), ca.as_ref());
+
+        policy(None).verify_vendor(&signature, &ca_pub).unwrap();
+
+        // vendor key signed by another CA
+        let other = crypto::generate_key(KeyAlgorithm::EcdsaP384).unwrap();
+        let other_pub = other.public_key().to_der().unwrap();
+        assert!(policy(None).verify_vendor(&signature, &other_pub).is_err());
+
+        signature.expiry = Some((crate::utils::unix_time() + 3600).to_string());
+        policy(None).verify_vendor(&signature, &ca_pub).unwrap();
+        signature.expiry = Some("1".to_string());
+        assert!(policy(None).verify_vendor(&signature, &ca_pub).is_err());
+    }
+
+    #[test]
+    fn oci_verify_vendor_require_expiry()
+    {
+        let ca = crypto::generate_key(KeyAlgorithm::EcdsaP384).unwrap();
+        let ca_pub = ca.public_key().to_der().unwrap();
+        let vendor = crypto::generate_key(KeyAlgorithm::EcdsaP384).unwrap();
+        let mut signature = vendor_signature(vendor.as_ref(), ca.as_ref());
+        let mut policy = policy(None);
+        policy.require_expiry = true;
+
+        // signatures without a timestamp cover the config alone
+        assert!(policy.verify_vendor(&signature, &ca_pub).is_err());
+
+        signature.timestamp = Some(crate::utils::unix_time().to_string());
+        assert!(policy.verify_vendor(&signature, &ca_pub).is_err());
+
+        signature.expiry = Some((crate::utils::unix_time() + 3600).to_string());
+        policy.verify_vendor(&signature, &ca_pub).unwrap();
+    }
+
+    #[test]
+    fn oci_verify_vendor_revoked()
+    {
+        let ca = crypto::generate_key(KeyAlgorithm::EcdsaP384).unwrap();
+        let ca_pub = ca.public_key().to_der().unwrap();
+        let vendor = crypto::generate_key(KeyAlgorithm::EcdsaP384).unwrap();
+        let signature = vendor_signature(vendor.as_ref(), ca.as_ref());
+
+        let mut revocation = RevocationList::default();
+        let unrelated = crypto::generate_key(KeyAlgorithm::Ed25519).unwrap();
+        revocation
+            .revoke(&unrelated.public_key().to_der().unwrap())
+            .unwrap();
+        policy(Some(&revocation))
+            .verify_vendor(&signature, &ca_pub)
+            .unwrap();
+
+        revocation.revoke(&signature.vendor_pub).unwrap();
+        assert!(policy(Some(&revocation))
+            .verify_vendor(&signature, &ca_pub)
+            .is_err());
+    }
+
+    fn with_key(vendor_pub: &[u8]) -> ImageSignature
+    {
+        ImageSignature {
+            config_sign: Vec::new(),
+            vendor_pub: vendor_pub.to_vec(),
+            vendor_pub_sign: Vec::new(),
+            timestamp: None,
+            expiry: None,
+            algorithm: None,
+        }
+    }
+
+    #[test]
+    fn oci_enforce_threshold()
+    {
+        let accept = |_: &ImageSignature| Ok(());
+        let signatures = vec![with_key(b"a"), with_key(b"b"), with_key(b"a")];
+
+        let mut policy = policy(None);
+        assert!(policy.enforce::<ImageSignature, _>(&[], accept).is_err());
+
+        policy.threshold = 2;
+        policy.enforce(&signatures, accept).unwrap();
+
+        // the same key counts once
+        policy.threshold = 3;
+        assert!(policy.enforce(&signatures, accept).is_err());
+
+        // rejected signatures don't count
+        policy.threshold = 2;
+        let reject_b = |s: &ImageSignature| match s.vendor_pub.as_slice() {
+            b"b" => Err(crate::error::SignerError::Generic("rejected".to_string())),
+            _ => Ok(()),
+        };
+        assert!(policy.enforce(&signatures, reject_b).is_err());
+
+        // only trusted keys count
+        policy.threshold = 1;
+        policy.trusted = vec![b"c".to_vec()];
+        assert!(policy.enforce(&signatures, accept).is_err());
+        policy.trusted = vec![b"b".to_vec()];
+        policy.enforce(&signatures, accept).unwrap();
+        policy.threshold = 2;
+        assert!(policy.enforce(&signatures, accept).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::AsRawFd;
+use std::sync::{Mutex, OnceLock};
+
+use crate::{error::SignerError, SignerResult};
+
+const TTY: &str = "/dev/tty";
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(SignerError::Crypto(format!($($arg)+))))
+}
+
+/// Where passphrases of encrypted private keys are taken from, the same
+/// passphrase is used for all the keys unless prompted for
+#[derive(Debug, Clone)]
+pub(crate) enum PassphraseSource
+{
+    Prompt,
+    Env(String),
+    Fd(i32),
+}
+
+static SOURCE: OnceLock<PassphraseSource> = OnceLock::new();
+// a pipe can be read only once, keep what was read for other keys
+static FROM_FD: Mutex<Option<HashMap<i32, String>>> = Mutex::new(None);
+
+/// Set the source given on the command line, once
+pub(crate) fn set_source(source: PassphraseSource) -> SignerResult<()>
+{
+    SOURCE
+        .set(source)
+        .or(err!("Passphrase source is already set"))
+}
+
+/// Source set with `set_source()`, prompting if none was
+pub(crate) fn source() -> &'static PassphraseSource
+{
+    SOURCE.get_or_init(|| PassphraseSource::Prompt)
+}
+
+fn trim_line(line: &str) -> String
+{
+    line.trim_end_matches(['\r', '\n']).to_string()
+}
+
+/// Read a line from the terminal with echo turned off
+fn prompt(message: &str) -> SignerResult<String>
+{
+    let Ok(mut tty) = OpenOptions::new().read(true).write(true).open(TTY) else {
+        return err!(
+            "No terminal to ask for a passphrase, use --passphrase-env or --passphrase-fd"
+        );
+    };
+    write!(tty, "{}", message)?;
+    tty.flush()?;
+
+    let fd = tty.as_raw_fd();
+    let mut original = std::mem::MaybeUninit::<libc::termios>::uninit();
+    // SAFETY: tcgetattr() fills the struct on success, it's not used otherwise
+    let original = unsafe {
+        if libc::tcgetattr(fd, original.as_mut_ptr()) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        original.assume_init()
+    };
+    let mut silent = original;
+    silent.c_lflag &= !libc::ECHO;
+    silent.c_lflag |= libc::ECHONL;
+
+    // SAFETY: fd stays open for the lifetime of tty, restore echo even if
+    // reading fails
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+    let mut line = String::new();
+    let result = BufReader::new(&tty).read_line(&mut line);
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    result?;
+
+    Ok(trim_line(&line))
+}
+
+fn read_fd(fd: i32) -> SignerResult<String>
+{
+    if fd < 0 {
+        return err!("Incorrect passphrase descriptor: {}", fd);
+    }
+
+    let mut from_fd = FROM_FD.lock().unwrap();
+    let from_fd = from_fd.get_or_insert_with(HashMap::new);
+    if let Some(passphrase) = from_fd.get(&fd) {
+        return Ok(passphrase.clone());
+    }
+
+    // opened anew rather than taking over the descriptor, which may not even
+    // be open or may belong to something else
+    let path = format!("/proc/self/fd/{}", fd);
+    let Ok(file) = File::open(&path) else {
+        return err!("Can't read the passphrase from descriptor {}", fd);
+    };
+    let mut line = String::new();
+    BufReader::new(file).read_line(&mut line)?;
+
+    Ok(from_fd.entry(fd).or_insert(trim_line(&line)).clone())
+}
+
+/// Passphrase to decrypt a given key
+pub(crate) fn read(key: &str, source: &PassphraseSource) -> SignerResult<String>
+{
+    match source {
+        PassphraseSource::Prompt => prompt(&format!("Passphrase for \"{}\": ", key)),
+        PassphraseSource::Env(var) => {
+            std::env::var(var).or(err!("Passphrase variable {} is not set", var))
+        }
+        PassphraseSource::Fd(fd) => read_fd(*fd),
+    }
+}
+
+/// Passphrase to encrypt a new key, asked twice when prompted for
+pub(crate) fn read_new(key: &str, source: &PassphraseSource) -> SignerResult<String>
+{
+    let passphrase = match source {
+        PassphraseSource::Prompt => {
+            let passphrase = prompt(&format!("New passphrase for \"{}\": ", key))?;
+            if prompt("Repeat the passphrase: ")? != passphrase {
+                return err!("Passphrases don't match");
+            }
+            passphrase
+        }
+        _ => read(key, source)?,
+    };
+
+    if passphrase.is_empty() {
+        return err!("Passphrase can't be empty");
+    }
+
+    Ok(passphrase)
+}
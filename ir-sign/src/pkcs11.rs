@@ -0,0 +1,372 @@
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::rsa::{PkcsMgfType, PkcsPssParams};
+use cryptoki::mechanism::{Mechanism, MechanismType};
+use cryptoki::object::{Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::slot::Slot;
+use cryptoki::types::AuthPin;
+use log::info;
+use spki::der::{asn1::OctetStringRef, Decode, Encode};
+use spki::EncodePublicKey;
+use std::io;
+
+use crate::crypto::{self, KeyAlgorithm, PrivateKey, PublicKey};
+use crate::{error::SignerError, utils, SignerResult};
+
+const URI_SCHEME: &str = "pkcs11:";
+const MODULE_PATH_ENV: &str = "PKCS11_MODULE_PATH";
+const PIN_ENV: &str = "PKCS11_PIN";
+
+// DER encoded curve OIDs as found in CKA_EC_PARAMS
+const OID_SECP384R1: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+const OID_PRIME256V1: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(SignerError::Crypto(format!($($arg)+))))
+}
+
+/// Key reference as defined by RFC 7512, only the attributes needed to find a
+/// key pair are supported, e.g.:
+/// pkcs11:token=ca;object=root-ca?module-path=/usr/lib/softhsm/libsofthsm2.so&pin-source=file:pin
+/// The module path can also be given with PKCS11_MODULE_PATH and the PIN with
+/// PKCS11_PIN environment variables.
+#[derive(Debug, Default)]
+struct Pkcs11Uri
+{
+    token: Option<String>,
+    object: Option<String>,
+    id: Option<Vec<u8>>,
+    slot_id: Option<u64>,
+    module_path: Option<String>,
+    pin_value: Option<String>,
+    pin_source: Option<String>,
+}
+
+fn percent_decode(value: &str) -> SignerResult<Vec<u8>>
+{
+    let mut decoded = Vec::new();
+    let mut bytes = value.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let digits: Vec<u8> = bytes.by_ref().take(2).collect();
+            decoded.extend(hex::decode(digits).or(err!("Malformed PKCS#11 URI: {}", value))?);
+        } else {
+            decoded.push(b);
+        }
+    }
+    Ok(decoded)
+}
+
+fn percent_decode_str(value: &str) -> SignerResult<String>
+{
+    String::from_utf8(percent_decode(value)?).or(err!("Malformed PKCS#11 URI: {}", value))
+}
+
+impl Pkcs11Uri
+{
+    fn parse(uri: &str) -> SignerResult<Self>
+    {
+        let Some(uri) = uri.strip_prefix(URI_SCHEME) else {
+            return err!("Not a PKCS#11 URI: {}", uri);
+        };
+        let (path, query) = uri.split_once('?').unwrap_or((uri, ""));
+
+        let mut parsed = Pkcs11Uri::default();
+        let path = path.split(';').map(|a| (a, false));
+        let query = query.split('&').map(|a| (a, true));
+        for (attribute, in_query) in path.chain(query) {
+            if attribute.is_empty() {
+                continue;
+            }
+            let Some((name, value)) = attribute.split_once('=') else {
+                return err!("Malformed PKCS#11 URI attribute: {}", attribute);
+            };
+            match (name, in_query) {
+                ("token", false) => parsed.token = Some(percent_decode_str(value)?),
+                ("object", false) => parsed.object = Some(percent_decode_str(value)?),
+                ("id", false) => parsed.id = Some(percent_decode(value)?),
+                ("slot-id", false) => {
+                    parsed.slot_id = Some(value.parse().or(err!("Incorrect slot-id: {}", value))?)
+                }
+                ("type", false) if value == "private" || value == "public" => (),
+                ("module-path", true) => parsed.module_path = Some(percent_decode_str(value)?),
+                ("pin-value", true) => parsed.pin_value = Some(percent_decode_str(value)?),
+                ("pin-source", true) => parsed.pin_source = Some(percent_decode_str(value)?),
+                _ => return err!("Unsupported PKCS#11 URI attribute: {}", attribute),
+            }
+        }
+
+        if parsed.object.is_none() && parsed.id.is_none() {
+            return err!("PKCS#11 URI needs to identify the key with object or id");
+        }
+
+        Ok(parsed)
+    }
+
+    fn module_path(&self) -> SignerResult<String>
+    {
+        match &self.module_path {
+            Some(m) => Ok(m.clone()),
+            None => std::env::var(MODULE_PATH_ENV).or(err!(
+                "PKCS#11 module not given in the URI nor in {}",
+                MODULE_PATH_ENV
+            )),
+        }
+    }
+
+    /// No PIN means the session is not logged in
+    fn pin(&self) -> SignerResult<Option<String>>
+    {
+        if let Some(pin) = &self.pin_value {
+            return Ok(Some(pin.clone()));
+        }
+        if let Some(source) = &self.pin_source {
+            let path = source.strip_prefix("file:").unwrap_or(source);
+            let pin = String::from_utf8(utils::file_read(path)?)
+                .or(err!("PIN in \"{}\" is not a valid string", path))?;
+            return Ok(Some(pin.trim_end_matches(['\r', '\n']).to_string()));
+        }
+        Ok(std::env::var(PIN_ENV).ok())
+    }
+
+    fn find_slot(&self, pkcs11: &Pkcs11) -> SignerResult<Slot>
+    {
+        for slot in pkcs11.get_slots_with_token()? {
+            if self.slot_id.is_some_and(|id| id != slot.id()) {
+                continue;
+            }
+            if let Some(token) = &self.token {
+                if pkcs11.get_token_info(slot)?.label() != token {
+                    continue;
+                }
+            }
+            return Ok(slot);
+        }
+        err!("No PKCS#11 token matches the URI")
+    }
+
+    fn find_object(&self, session: &Session, class: ObjectClass) -> SignerResult<ObjectHandle>
+    {
+        let mut template = vec![Attribute::Class(class)];
+        if let Some(object) = &self.object {
+            template.push(Attribute::Label(object.as_bytes().to_vec()));
+        }
+        if let Some(id) = &self.id {
+            template.push(Attribute::Id(id.clone()));
+        }
+
+        let class = if class == ObjectClass::PRIVATE_KEY {
+            "private"
+        } else {
+            "public"
+        };
+        match session.find_objects(&template)?.as_slice() {
+            [object] => Ok(*object),
+            [] => err!("No PKCS#11 {} key matches the URI", class),
+            _ => err!("More than one PKCS#11 {} key matches the URI", class),
+        }
+    }
+}
+
+/// Algorithm and SPKI DER of a public key object
+fn read_public(session: &Session, object: ObjectHandle) -> SignerResult<(KeyAlgorithm, Vec<u8>)>
+{
+    let attributes = session.get_attributes(
+        object,
+        &[
+            AttributeType::KeyType,
+            AttributeType::EcParams,
+            AttributeType::EcPoint,
+            AttributeType::Modulus,
+            AttributeType::PublicExponent,
+        ],
+    )?;
+
+    let mut key_type = None;
+    let (mut params, mut point, mut modulus, mut exponent) = (None, None, None, None);
+    for attribute in attributes {
+        match attribute {
+            Attribute::KeyType(t) => key_type = Some(t),
+            Attribute::EcParams(p) => params = Some(p),
+            Attribute::EcPoint(p) => point = Some(p),
+            Attribute::Modulus(m) => modulus = Some(m),
+            Attribute::PublicExponent(e) => exponent = Some(e),
+            _ => (),
+        }
+    }
+
+    // CKA_EC_POINT should be DER encoded, some modules return it raw though
+    let point = point.unwrap_or_default();
+    let point = match OctetStringRef::from_der(&point) {
+        Ok(octets) => octets.as_bytes().to_vec(),
+        Err(_) => point,
+    };
+
+    let public = match (key_type, params) {
+        (Some(KeyType::EC), Some(params)) if params == OID_SECP384R1 => (
+            KeyAlgorithm::EcdsaP384,
+            p384::ecdsa::VerifyingKey::from_sec1_bytes(&point)?.to_public_key_der()?,
+        ),
+        (Some(KeyType::EC), Some(params)) if params == OID_PRIME256V1 => (
+            KeyAlgorithm::EcdsaP256,
+            p256::ecdsa::VerifyingKey::from_sec1_bytes(&point)?.to_public_key_der()?,
+        ),
+        (Some(KeyType::EC_EDWARDS), _) => {
+            let bytes = point
+                .try_into()
+                .or(err!("Only Ed25519 Edwards keys are supported"))?;
+            (
+                KeyAlgorithm::Ed25519,
+                ed25519_dalek::VerifyingKey::from_bytes(&bytes)?.to_public_key_der()?,
+            )
+        }
+        (Some(KeyType::RSA), _) => {
+            let (Some(n), Some(e)) = (modulus, exponent) else {
+                return err!("RSA public key without modulus or exponent");
+            };
+            let n = rsa::BigUint::from_bytes_be(&n);
+            let e = rsa::BigUint::from_bytes_be(&e);
+            (
+                KeyAlgorithm::RsaPss,
+                rsa::RsaPublicKey::new(n, e)?.to_public_key_der()?,
+            )
+        }
+        _ => return err!("Unsupported PKCS#11 key type"),
+    };
+
+    Ok((public.0, public.1.to_vec()))
+}
+
+/// Private key kept on a token, signing is done by the PKCS#11 module and the
+/// key can't be exported
+struct Pkcs11PrivateKey
+{
+    session: Session,
+    key: ObjectHandle,
+    algorithm: KeyAlgorithm,
+    public: Vec<u8>,
+}
+
+impl Pkcs11PrivateKey
+{
+    fn hash(&self) -> Option<&'static str>
+    {
+        match self.algorithm {
+            KeyAlgorithm::EcdsaP384 => Some("sha384"),
+            KeyAlgorithm::EcdsaP256 | KeyAlgorithm::RsaPss => Some("sha256"),
+            KeyAlgorithm::Ed25519 => None,
+        }
+    }
+
+    /// CKM_ECDSA and CKM_RSA_PKCS_PSS sign a hash computed here, the result is
+    /// encoded the same way as for keys kept in files
+    fn sign_prehash(&self, hash: &[u8]) -> SignerResult<Vec<u8>>
+    {
+        let mut vec = Vec::new();
+        match self.algorithm {
+            KeyAlgorithm::EcdsaP384 => {
+                let raw = self.session.sign(&Mechanism::Ecdsa, self.key, hash)?;
+                let sign = p384::ecdsa::Signature::from_slice(&raw)?;
+                sign.to_der().encode_to_vec(&mut vec)?;
+            }
+            KeyAlgorithm::EcdsaP256 => {
+                let raw = self.session.sign(&Mechanism::Ecdsa, self.key, hash)?;
+                let sign = p256::ecdsa::Signature::from_slice(&raw)?;
+                sign.to_der().encode_to_vec(&mut vec)?;
+            }
+            KeyAlgorithm::RsaPss => {
+                let params = PkcsPssParams {
+                    hash_alg: MechanismType::SHA256,
+                    mgf: PkcsMgfType::MGF1_SHA256,
+                    s_len: (hash.len() as u64).into(),
+                };
+                vec = self
+                    .session
+                    .sign(&Mechanism::RsaPkcsPss(params), self.key, hash)?;
+            }
+            KeyAlgorithm::Ed25519 => err!("Ed25519 signs the whole message")?,
+        }
+        Ok(vec)
+    }
+}
+
+impl PrivateKey for Pkcs11PrivateKey
+{
+    fn algorithm(&self) -> KeyAlgorithm
+    {
+        self.algorithm
+    }
+
+    fn public_key(&self) -> Box<dyn PublicKey>
+    {
+        // unwrap() is safe, the public key has been imported when opening
+        crypto::import_public_as(self.algorithm, &self.public).unwrap()
+    }
+
+    fn to_der(&self) -> SignerResult<Vec<u8>>
+    {
+        err!("Private key can't be exported from a PKCS#11 token")
+    }
+
+    fn to_pkcs8_der(&self) -> SignerResult<Vec<u8>>
+    {
+        self.to_der()
+    }
+
+    fn sign(&self, msg: &[u8]) -> SignerResult<Vec<u8>>
+    {
+        self.sign_reader(&mut &msg[..])
+    }
+
+    fn sign_reader(&self, mut reader: &mut dyn io::Read) -> SignerResult<Vec<u8>>
+    {
+        match self.hash() {
+            Some(algo) => self.sign_prehash(&crypto::hash_reader(algo, &mut reader)?),
+            None => {
+                let mut msg = Vec::new();
+                reader.read_to_end(&mut msg)?;
+                Ok(self.session.sign(&Mechanism::Eddsa, self.key, &msg)?)
+            }
+        }
+    }
+
+    fn sign_sha256(&self, msg: &[u8]) -> SignerResult<Vec<u8>>
+    {
+        match self.algorithm {
+            KeyAlgorithm::EcdsaP384 => {
+                self.sign_prehash(&crypto::hash_reader("sha256", &mut &msg[..])?)
+            }
+            _ => self.sign(msg),
+        }
+    }
+}
+
+/// Open a session with the token and find the key pair referenced by the URI
+pub(crate) fn open_private(uri: &str) -> SignerResult<Box<dyn PrivateKey>>
+{
+    let uri = Pkcs11Uri::parse(uri)?;
+    let module_path = uri.module_path()?;
+    info!("Loading PKCS#11 module: \"{}\"", module_path);
+
+    let pkcs11 = Pkcs11::new(&module_path)?;
+    pkcs11.initialize(CInitializeArgs::OsThreads)?;
+
+    let slot = uri.find_slot(&pkcs11)?;
+    let session = pkcs11.open_ro_session(slot)?;
+    if let Some(pin) = uri.pin()? {
+        session.login(UserType::User, Some(&AuthPin::new(pin)))?;
+    }
+
+    let key = uri.find_object(&session, ObjectClass::PRIVATE_KEY)?;
+    let public = uri.find_object(&session, ObjectClass::PUBLIC_KEY)?;
+    let (algorithm, public) = read_public(&session, public)?;
+    crypto::import_public_as(algorithm, &public)?;
+    info!("Using {} key from PKCS#11 slot {}", algorithm, slot.id());
+
+    Ok(Box::new(Pkcs11PrivateKey {
+        session,
+        key,
+        algorithm,
+        public,
+    }))
+}
@@ -0,0 +1,68 @@
+use serde::Serialize;
+
+use crate::subcmds::SignatureFormat;
+
+/// Outcome of verifying a single tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ImageStatus
+{
+    Verified,
+    Failed,
+    Unsigned,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ImageReport
+{
+    pub app: String,
+    /// None when the application itself couldn't be read
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+    pub status: ImageStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Machine readable result of verify-registry
+#[derive(Debug, Serialize)]
+pub(crate) struct VerifyReport
+{
+    pub registry: String,
+    pub format: SignatureFormat,
+    pub verified: usize,
+    pub failed: usize,
+    pub unsigned: usize,
+    pub images: Vec<ImageReport>,
+}
+
+impl VerifyReport
+{
+    pub fn new(registry: &str, format: SignatureFormat) -> Self
+    {
+        Self {
+            registry: registry.to_string(),
+            format,
+            verified: 0,
+            failed: 0,
+            unsigned: 0,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, image: ImageReport)
+    {
+        match image.status {
+            ImageStatus::Verified => self.verified += 1,
+            ImageStatus::Failed => self.failed += 1,
+            ImageStatus::Unsigned => self.unsigned += 1,
+        }
+        self.images.push(image);
+    }
+
+    /// Whether every image in the registry verified
+    pub fn is_success(&self) -> bool
+    {
+        self.failed == 0 && self.unsigned == 0
+    }
+}
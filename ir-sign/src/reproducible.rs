@@ -0,0 +1,159 @@
+use serde::Serialize;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::{error::SignerError, utils, SignerResult};
+
+// https://reproducible-builds.org/specs/source-date-epoch/
+const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(SignerError::Generic(format!($($arg)+))))
+}
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Turn the reproducible mode on, it's also on when SOURCE_DATE_EPOCH is set
+pub(crate) fn set_enabled(enabled: bool)
+{
+    let _ = ENABLED.set(enabled);
+}
+
+pub(crate) fn is_enabled() -> bool
+{
+    *ENABLED.get().unwrap_or(&false) || std::env::var_os(SOURCE_DATE_EPOCH).is_some()
+}
+
+/// Value of SOURCE_DATE_EPOCH if set
+pub(crate) fn source_date_epoch() -> SignerResult<Option<u64>>
+{
+    parse_epoch(std::env::var(SOURCE_DATE_EPOCH).ok().as_deref())
+}
+
+fn parse_epoch(epoch: Option<&str>) -> SignerResult<Option<u64>>
+{
+    match epoch {
+        Some(epoch) => match epoch.trim().parse() {
+            Ok(epoch) => Ok(Some(epoch)),
+            Err(_) => err!(
+                "{} is not a unix timestamp: \"{}\"",
+                SOURCE_DATE_EPOCH,
+                epoch
+            ),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Modification time of files put into layers
+pub(crate) fn mtime() -> SignerResult<u64>
+{
+    Ok(source_date_epoch()?.unwrap_or(0))
+}
+
+/// Creation time of built images, none unless SOURCE_DATE_EPOCH is set as the
+/// current time would make every build different
+pub(crate) fn created() -> SignerResult<Option<String>>
+{
+    Ok(source_date_epoch()?.map(ir_verify::time::rfc3339))
+}
+
+/// JSON as written into the image layout. Keys are sorted so the output
+/// depends on the content only, in reproducible mode it's also compact so
+/// different pretty printers can't make a difference.
+pub(crate) fn to_json<T: Serialize>(value: &T) -> SignerResult<Vec<u8>>
+{
+    let value = serde_json::to_value(value)?;
+    if is_enabled() {
+        Ok(serde_json::to_vec(&value)?)
+    } else {
+        Ok(serde_json::to_vec_pretty(&value)?)
+    }
+}
+
+pub(crate) fn write_json<T: Serialize, P: AsRef<Path>>(path: P, value: &T) -> SignerResult<()>
+{
+    utils::file_write(path, &to_json(value)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use serde_json::json;
+
+    use super::{created, mtime, parse_epoch, source_date_epoch, to_json, SOURCE_DATE_EPOCH};
+    use crate::builder::{self, Compression, ImageSpec};
+
+    /// Sets SOURCE_DATE_EPOCH and puts the previous value back when dropped
+    struct EpochGuard(Option<std::ffi::OsString>);
+
+    impl EpochGuard
+    {
+        fn set(epoch: &str) -> Self
+        {
+            let previous = std::env::var_os(SOURCE_DATE_EPOCH);
+            std::env::set_var(SOURCE_DATE_EPOCH, epoch);
+            Self(previous)
+        }
+    }
+
+    impl Drop for EpochGuard
+    {
+        fn drop(&mut self)
+        {
+            match &self.0 {
+                Some(previous) => std::env::set_var(SOURCE_DATE_EPOCH, previous),
+                None => std::env::remove_var(SOURCE_DATE_EPOCH),
+            }
+        }
+    }
+
+    #[test]
+    fn reproducible_parse_epoch()
+    {
+        assert_eq!(parse_epoch(None).unwrap(), None);
+        assert_eq!(parse_epoch(Some(" 1700000000\n")).unwrap(), Some(1700000000));
+        assert!(parse_epoch(Some("yesterday")).is_err());
+        assert!(parse_epoch(Some("-1")).is_err());
+    }
+
+    // the only test setting SOURCE_DATE_EPOCH, the others don't depend on it
+    #[test]
+    fn reproducible_source_date_epoch()
+    {
+        let _epoch = EpochGuard::set("1700000000");
+        assert_eq!(source_date_epoch().unwrap(), Some(1700000000));
+        assert_eq!(mtime().unwrap(), 1700000000);
+        assert_eq!(created().unwrap().as_deref(), Some("2023-11-14T22:13:20Z"));
+
+        // canonical: sorted keys, compact
+        let value = json!({"b": 1, "a": {"d": [2, 1], "c": null}});
+        assert_eq!(to_json(&value).unwrap(), br#"{"a":{"c":null,"d":[2,1]},"b":1}"#);
+
+        let dir = std::env::temp_dir().join(format!("ir-sign-reproducible-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let rootfs = dir.join("rootfs");
+        std::fs::create_dir_all(&rootfs).unwrap();
+        std::fs::write(rootfs.join("file"), b"content").unwrap();
+        let spec = ImageSpec {
+            entrypoint: &[],
+            cmd: &[],
+            env: &[],
+            labels: &[],
+            working_dir: None,
+            architecture: "arm64",
+            os: "linux",
+        };
+
+        // same input, same image whatever the app and the file times
+        let build = |app: &str| {
+            builder::build_image(&dir.join(app), "v1", &rootfs, &spec, Compression::Gzip).unwrap()
+        };
+        let first = build("first");
+        std::fs::write(rootfs.join("file"), b"content").unwrap();
+        assert_eq!(build("second"), first);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
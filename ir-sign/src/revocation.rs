@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::crypto::{self, PrivateKey};
+use crate::format::{self, FileFormat};
+use crate::{error::SignerError, utils, SignerResult};
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(SignerError::Crypto(format!($($arg)+))))
+}
+
+/// List of revoked vendor keys, identified by SHA256 fingerprints of their
+/// SPKI DER encoding. The JSON file is signed with ROOT-CA private key and the
+/// signature is kept in a separate DER file (same as for the vendor key).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct RevocationList
+{
+    pub issued: u64,
+    pub revoked: Vec<String>,
+}
+
+pub(crate) fn fingerprint(public_der: &[u8]) -> SignerResult<String>
+{
+    let hash = crypto::hash_reader("sha256", &mut &public_der[..])?;
+    Ok(hex::encode(hash))
+}
+
+impl RevocationList
+{
+    pub fn from_file<T: AsRef<Path>>(path: T) -> SignerResult<Self>
+    {
+        let json = utils::file_read(path)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    /// Load the list verifying its signature with ROOT-CA public key
+    pub fn load<T: AsRef<Path>>(path: T, signature: T, ca_pub: &[u8]) -> SignerResult<Self>
+    {
+        let json = utils::file_read(path)?;
+        let sign = format::read_der(signature)?;
+
+        let c_pub = crypto::import_public(ca_pub)?;
+        c_pub
+            .verify(&json, &sign)
+            .or(err!("Revocation list signature verification failed"))?;
+
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    /// Serialize and sign the list with ROOT-CA private key, note that ir-server
+    /// and ir-client read DER signatures only
+    pub fn save<T: AsRef<Path>>(
+        &self,
+        path: T,
+        signature: T,
+        c_prv: &dyn PrivateKey,
+        signature_format: FileFormat,
+    ) -> SignerResult<()>
+    {
+        let json = serde_json::to_vec_pretty(self)?;
+
+        let sign = c_prv.sign(&json)?;
+
+        utils::file_write(path, &json)?;
+        format::write(signature, &sign, format::PEM_SIGNATURE, signature_format)?;
+
+        Ok(())
+    }
+
+    pub fn revoke(&mut self, public_der: &[u8]) -> SignerResult<bool>
+    {
+        let fp = fingerprint(public_der)?;
+        if self.revoked.contains(&fp) {
+            return Ok(false);
+        }
+
+        self.revoked.push(fp);
+        self.issued = utils::unix_time();
+
+        Ok(true)
+    }
+
+    pub fn is_revoked(&self, public_der: &[u8]) -> SignerResult<bool>
+    {
+        let fp = fingerprint(public_der)?;
+        Ok(self.revoked.contains(&fp))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::RevocationList;
+    use crate::crypto::{self, KeyAlgorithm};
+    use crate::format::FileFormat;
+
+    #[test]
+    fn revocation_revoke()
+    {
+        let vendor = crypto::generate_key(KeyAlgorithm::EcdsaP384).unwrap();
+        let vendor_pub = vendor.public_key().to_der().unwrap();
+
+        let mut list = RevocationList::default();
+        assert!(!list.is_revoked(&vendor_pub).unwrap());
+        assert!(list.revoke(&vendor_pub).unwrap());
+        assert!(!list.revoke(&vendor_pub).unwrap());
+        assert!(list.is_revoked(&vendor_pub).unwrap());
+        assert_eq!(list.revoked.len(), 1);
+        assert!(list.issued > 0);
+    }
+
+    #[test]
+    fn revocation_save_load()
+    {
+        let dir = std::env::temp_dir().join(format!("ir-sign-revocation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (json, sign) = (dir.join("revocation.json"), dir.join("revocation.sig"));
+
+        let ca = crypto::generate_key(KeyAlgorithm::EcdsaP256).unwrap();
+        let ca_pub = ca.public_key().to_der().unwrap();
+        let vendor = crypto::generate_key(KeyAlgorithm::Ed25519).unwrap();
+        let vendor_pub = vendor.public_key().to_der().unwrap();
+
+        let mut list = RevocationList::default();
+        list.revoke(&vendor_pub).unwrap();
+        for format in [FileFormat::Der, FileFormat::Pem] {
+            list.save(&json, &sign, ca.as_ref(), format).unwrap();
+            let loaded = RevocationList::load(&json, &sign, &ca_pub).unwrap();
+            assert!(loaded.is_revoked(&vendor_pub).unwrap());
+        }
+
+        // signed by someone else
+        let other = crypto::generate_key(KeyAlgorithm::EcdsaP256).unwrap();
+        let other_pub = other.public_key().to_der().unwrap();
+        assert!(RevocationList::load(&json, &sign, &other_pub).is_err());
+
+        // altered after signing
+        std::fs::write(&json, r#"{"issued": 0, "revoked": []}"#).unwrap();
+        assert!(RevocationList::load(&json, &sign, &ca_pub).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+
+use ir_verify::annotation::{
+    annotation, is_slot_annotation, SIGNATURE, SIGNATURE_ALGORITHM, SIGNATURE_EXPIRY,
+    SIGNATURE_TIMESTAMP, VENDORPUB, VENDORPUB_SIGNATURE,
+};
+
+use crate::crypto::KeyAlgorithm;
+use crate::{error::SignerError, SignerResult};
+
+macro_rules! err {
+    ($($arg:tt)+) => (Err(SignerError::OciRegistry(format!($($arg)+))))
+}
+
+/// A single config signature with the vendor "certificate" as kept in manifest
+/// annotations
+#[derive(Debug, Clone)]
+pub(crate) struct ImageSignature
+{
+    pub config_sign: Vec<u8>,
+    pub vendor_pub: Vec<u8>,
+    pub vendor_pub_sign: Vec<u8>,
+    pub timestamp: Option<String>,
+    pub expiry: Option<String>,
+    pub algorithm: Option<String>,
+}
+
+fn decode(annotations: &HashMap<String, String>, slot: usize, name: &str) -> SignerResult<Vec<u8>>
+{
+    let key = annotation(slot, name);
+    match annotations.get(&key) {
+        Some(value) => Ok(hex::decode(value.as_bytes())?),
+        None => err!("Missing \"{}\" annotation", key),
+    }
+}
+
+impl ImageSignature
+{
+    /// Signatures with a timestamp cover `ir_verify::envelope` of the config
+    /// digest, timestamp and expiry, so these can't be altered or stripped
+    /// from the manifest without breaking the signature. Signatures made
+    /// without a timestamp cover the config alone, `None` is returned then.
+    pub fn envelope(&self, config_digest: &str) -> SignerResult<Option<Vec<u8>>>
+    {
+        match (&self.timestamp, &self.expiry) {
+            (Some(t), e) => Ok(Some(ir_verify::envelope::envelope(
+                config_digest,
+                t,
+                e.as_deref(),
+            ))),
+            (None, Some(_)) => err!("Signature expiry given without a timestamp"),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Expiry as unix time in seconds
+    pub fn expiry(&self) -> SignerResult<Option<u64>>
+    {
+        match &self.expiry {
+            Some(e) => Ok(Some(
+                e.parse().or(err!("Incorrect signature expiry: {}", e))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Algorithm of the vendor key, signatures made before it was recorded are
+    /// all ECDSA P384
+    pub fn algorithm(&self) -> SignerResult<KeyAlgorithm>
+    {
+        match &self.algorithm {
+            Some(a) => a.parse(),
+            None => Ok(KeyAlgorithm::EcdsaP384),
+        }
+    }
+
+    fn read(annotations: &HashMap<String, String>, slot: usize) -> SignerResult<Option<Self>>
+    {
+        if !annotations.contains_key(&annotation(slot, VENDORPUB)) {
+            return Ok(None);
+        }
+
+        Ok(Some(ImageSignature {
+            config_sign: decode(annotations, slot, SIGNATURE)?,
+            vendor_pub: decode(annotations, slot, VENDORPUB)?,
+            vendor_pub_sign: decode(annotations, slot, VENDORPUB_SIGNATURE)?,
+            timestamp: annotations
+                .get(&annotation(slot, SIGNATURE_TIMESTAMP))
+                .cloned(),
+            expiry: annotations
+                .get(&annotation(slot, SIGNATURE_EXPIRY))
+                .cloned(),
+            algorithm: annotations
+                .get(&annotation(slot, SIGNATURE_ALGORITHM))
+                .cloned(),
+        }))
+    }
+
+    /// Read all the signatures, slots are consecutive
+    pub fn read_all(annotations: &HashMap<String, String>) -> SignerResult<Vec<Self>>
+    {
+        let mut signatures = Vec::new();
+        while let Some(signature) = Self::read(annotations, signatures.len())? {
+            signatures.push(signature);
+        }
+        Ok(signatures)
+    }
+
+    pub fn write(&self, annotations: &mut HashMap<String, String>, slot: usize)
+    {
+        annotations.insert(annotation(slot, SIGNATURE), hex::encode(&self.config_sign));
+        annotations.insert(annotation(slot, VENDORPUB), hex::encode(&self.vendor_pub));
+        annotations.insert(
+            annotation(slot, VENDORPUB_SIGNATURE),
+            hex::encode(&self.vendor_pub_sign),
+        );
+
+        for (name, value) in [
+            (SIGNATURE_TIMESTAMP, &self.timestamp),
+            (SIGNATURE_EXPIRY, &self.expiry),
+            (SIGNATURE_ALGORITHM, &self.algorithm),
+        ] {
+            match value {
+                Some(v) => annotations.insert(annotation(slot, name), v.clone()),
+                None => annotations.remove(&annotation(slot, name)),
+            };
+        }
+    }
+
+    /// Only the vendor "certificate" and the algorithm, for formats keeping the
+    /// signature itself somewhere else
+    pub fn write_vendor(&self, annotations: &mut HashMap<String, String>)
+    {
+        annotations.insert(annotation(0, VENDORPUB), hex::encode(&self.vendor_pub));
+        annotations.insert(
+            annotation(0, VENDORPUB_SIGNATURE),
+            hex::encode(&self.vendor_pub_sign),
+        );
+        if let Some(algorithm) = &self.algorithm {
+            annotations.insert(annotation(0, SIGNATURE_ALGORITHM), algorithm.clone());
+        }
+    }
+
+    /// Read what was written with `write_vendor()`, the rest is left empty
+    pub fn read_vendor(annotations: &HashMap<String, String>) -> SignerResult<Self>
+    {
+        Ok(ImageSignature {
+            config_sign: Vec::new(),
+            vendor_pub: decode(annotations, 0, VENDORPUB)?,
+            vendor_pub_sign: decode(annotations, 0, VENDORPUB_SIGNATURE)?,
+            timestamp: None,
+            expiry: None,
+            algorithm: annotations
+                .get(&annotation(0, SIGNATURE_ALGORITHM))
+                .cloned(),
+        })
+    }
+
+    /// Remove all the signatures, e.g. when the config they cover changes.
+    /// Other annotations under the same prefix are kept.
+    pub fn remove_all(annotations: &mut HashMap<String, String>)
+    {
+        annotations.retain(|key, _| !is_slot_annotation(key));
+    }
+
+    /// Slot to write a signature made with a given key: the one already signed
+    /// with that key (to replace it) or the next free one
+    pub fn find_slot(signatures: &[Self], vendor_pub: &[u8]) -> usize
+    {
+        signatures
+            .iter()
+            .position(|s| s.vendor_pub == vendor_pub)
+            .unwrap_or(signatures.len())
+    }
+}
+
+impl AsRef<ImageSignature> for ImageSignature
+{
+    fn as_ref(&self) -> &ImageSignature
+    {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::ImageSignature;
+    use crate::crypto::KeyAlgorithm;
+    use ir_verify::annotation::annotation;
+    use std::collections::HashMap;
+
+    fn signature(vendor_pub: &[u8], expiry: Option<&str>) -> ImageSignature
+    {
+        ImageSignature {
+            config_sign: vec![1, 2, 3],
+            vendor_pub: vendor_pub.to_vec(),
+            vendor_pub_sign: vec![4, 5, 6],
+            timestamp: Some("1700000000".to_string()),
+            expiry: expiry.map(|e| e.to_string()),
+            algorithm: None,
+        }
+    }
+
+    #[test]
+    fn signature_slots()
+    {
+        let mut annotations = HashMap::new();
+        signature(b"first", None).write(&mut annotations, 0);
+        signature(b"second", Some("1800000000")).write(&mut annotations, 1);
+        assert!(annotations.contains_key("com.samsung.islet.image.vendorpub"));
+        assert!(annotations.contains_key("com.samsung.islet.image.signatures.1.vendorpub"));
+
+        let signatures = ImageSignature::read_all(&annotations).unwrap();
+        assert_eq!(signatures.len(), 2);
+        assert_eq!(signatures[1].vendor_pub, b"second");
+        assert_eq!(signatures[1].expiry().unwrap(), Some(1800000000));
+        assert_eq!(signatures[0].expiry().unwrap(), None);
+        assert_eq!(signatures[0].algorithm().unwrap(), KeyAlgorithm::EcdsaP384);
+
+        assert_eq!(ImageSignature::find_slot(&signatures, b"second"), 1);
+        assert_eq!(ImageSignature::find_slot(&signatures, b"third"), 2);
+
+        // a slot missing one of its annotations is an error, not the end
+        annotations.remove(&annotation(1, "signature"));
+        assert!(ImageSignature::read_all(&annotations).is_err());
+    }
+
+    #[test]
+    fn signature_envelope()
+    {
+        const DIGEST: &str = "sha256:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae";
+
+        let mut signed = signature(b"key", Some("1800000000"));
+        let envelope = signed.envelope(DIGEST).unwrap().unwrap();
+        assert_eq!(
+            envelope,
+            ir_verify::envelope::envelope(DIGEST, "1700000000", Some("1800000000"))
+        );
+
+        // stripping the expiry changes what is signed
+        signed.expiry = None;
+        assert_ne!(signed.envelope(DIGEST).unwrap().unwrap(), envelope);
+
+        // signatures without a timestamp cover the config alone
+        signed.timestamp = None;
+        assert!(signed.envelope(DIGEST).unwrap().is_none());
+        signed.expiry = Some("1800000000".to_string());
+        assert!(signed.envelope(DIGEST).is_err());
+
+        signed.expiry = Some("tomorrow".to_string());
+        assert!(signed.expiry().is_err());
+    }
+
+    #[test]
+    fn signature_remove_all()
+    {
+        let mut annotations = HashMap::from([
+            ("com.samsung.islet.image.description".to_string(), "app".to_string()),
+            ("com.samsung.islet.image.signatures.note".to_string(), "kept".to_string()),
+            ("org.opencontainers.image.source".to_string(), "git".to_string()),
+        ]);
+        let mut signed = signature(b"first", Some("1800000000"));
+        signed.algorithm = Some("ed25519".to_string());
+        signed.write(&mut annotations, 0);
+        signed.write(&mut annotations, 1);
+        signed.write(&mut annotations, 12);
+
+        ImageSignature::remove_all(&mut annotations);
+        let mut keys: Vec<&str> = annotations.keys().map(|k| k.as_str()).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "com.samsung.islet.image.description",
+                "com.samsung.islet.image.signatures.note",
+                "org.opencontainers.image.source",
+            ]
+        );
+    }
+}
@@ -1,76 +1,302 @@
-use log::info;
+use clap::ValueEnum;
+use log::{error, info};
+use serde::Serialize;
+use std::collections::HashSet;
 use std::fs::File;
 use std::path::Path;
 
-use crate::{crypto, error::SignerError, oci, utils, SignerResult};
+use crate::crypto::PrivateKey;
+use crate::format::{self, FileFormat, PublicFormat};
+use crate::oci::BLOBS_SUBDIR;
+use crate::report::{ImageReport, ImageStatus, VerifyReport};
+use crate::revocation::RevocationList;
+use crate::transaction::Transaction;
+use crate::{
+    archive, builder, cosign, crypto, error::SignerError, oci, passphrase, utils, SignerResult,
+};
 
-const BLOBS_SUBDIR: &str = "blobs";
+const SECONDS_IN_DAY: u64 = 24 * 60 * 60;
 
 macro_rules! err {
     ($($arg:tt)+) => (Err(SignerError::OciRegistry(format!($($arg)+))))
 }
 
-pub(crate) fn cmd_generate_key(output: &str) -> SignerResult<()>
+/// Keys used to sign an image, either VENDOR_PUB_SIGNATURE and CA_PUB or CA_PRV
+/// need to be passed along with the vendor private key
+pub(crate) struct SigningKeys<'a>
 {
-    info!("Generating key: \"{}\"", output);
+    pub vendor_prv: &'a str,
+    pub vendor_pub_signature: Option<&'a str>,
+    pub ca_pub: Option<&'a str>,
+    pub ca_prv: Option<&'a str>,
+}
+
+/// Where and how the image signature is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SignatureFormat
+{
+    /// Manifest annotations, signing changes the manifest digest
+    Native,
+    /// Cosign simple signing payload in a separate "sha256-DIGEST.sig" manifest
+    Cosign,
+}
+
+/// Keys and requirements used to verify an image, REVOCATION_LIST and
+/// REVOCATION_SIGNATURE need to be passed together
+pub(crate) struct TrustPolicy<'a>
+{
+    pub ca_pub: &'a str,
+    pub revocation_list: Option<&'a str>,
+    pub revocation_signature: Option<&'a str>,
+    pub trusted_keys: &'a [String],
+    pub threshold: usize,
+    pub require_expiry: bool,
+}
+
+/// Vendor private key loaded once along with its signature made by root-ca
+struct VendorKey
+{
+    private: Box<dyn PrivateKey>,
+    signature: Vec<u8>,
+}
+
+fn expiry_from_days(expiry_days: Option<u64>) -> Option<u64>
+{
+    expiry_days.map(|days| utils::unix_time() + days * SECONDS_IN_DAY)
+}
+
+fn load_revocation_list(
+    revocation_list: Option<&str>,
+    revocation_signature: Option<&str>,
+    ca_pub: &[u8],
+) -> SignerResult<Option<RevocationList>>
+{
+    match (revocation_list, revocation_signature) {
+        (Some(list), Some(signature)) => {
+            info!("Loading revocation list: \"{}\"", list);
+            Ok(Some(RevocationList::load(list, signature, ca_pub)?))
+        }
+        (None, None) => Ok(None),
+        _ => err!("You need to pass both REVOCATION_LIST and REVOCATION_SIGNATURE"),
+    }
+}
+
+fn load_vendor_key(keys: &SigningKeys) -> SignerResult<VendorKey>
+{
+    let private = crypto::load_private(keys.vendor_prv, passphrase::source())?;
+
+    // realistic or simple variant handling
+    let signature = match (keys.vendor_pub_signature, keys.ca_pub, keys.ca_prv) {
+        (Some(vendor_pub_signature), Some(ca_pub), None) => {
+            let ca_pub = format::read_der(ca_pub)?;
+            let vendor_sign = format::read_der(vendor_pub_signature)?;
+            oci::verify_vendor_pub_signature(private.as_ref(), &vendor_sign, &ca_pub)?;
+            vendor_sign
+        }
+        (None, None, Some(ca_prv)) => {
+            let ca_prv = crypto::load_private(ca_prv, passphrase::source())?;
+            oci::sign_vendor_pub(private.as_ref(), ca_prv.as_ref())?
+        }
+        _ => err!("You need to pass either VENDOR_PUB_SIGNATURE and CA_PUB or CA_PRV")?,
+    };
+
+    Ok(VendorKey { private, signature })
+}
+
+fn load_verify_policy<'a>(
+    trust: &TrustPolicy,
+    revocation: Option<&'a RevocationList>,
+) -> SignerResult<oci::VerifyPolicy<'a>>
+{
+    if trust.threshold == 0 {
+        err!("THRESHOLD needs to be at least 1")?;
+    }
+    let mut trusted = Vec::new();
+    for key in trust.trusted_keys {
+        info!("Trusting vendor key: \"{}\"", key);
+        trusted.push(format::read_der(key)?);
+    }
+
+    Ok(oci::VerifyPolicy {
+        threshold: trust.threshold,
+        trusted,
+        revocation,
+        require_expiry: trust.require_expiry,
+    })
+}
+
+/// Sign a single reference of an application, returns the digest of the
+/// signed manifest which changes with the native format. Nothing is written
+/// unless all the changes succeeded, or at all with DRY_RUN.
+fn sign_reference(
+    registry: &str,
+    app_name: &str,
+    reference: &str,
+    key: &VendorKey,
+    expiry: Option<u64>,
+    format: SignatureFormat,
+    dry_run: bool,
+) -> SignerResult<String>
+{
+    let app = Path::new(registry).join(app_name);
+    let digest = oci::find_manifest_by_reference(&app, reference)?;
+    let mut tx = Transaction::open(&app, dry_run)?;
+
+    let signed = match format {
+        SignatureFormat::Cosign => {
+            info!(
+                "Signing manifest: \"{}\" in: \"{}\" with cosign format",
+                reference,
+                app.display()
+            );
+            cosign::sign_manifest(
+                &mut tx,
+                &digest,
+                app_name,
+                key.private.as_ref(),
+                &key.signature,
+                expiry,
+            )?;
+            digest
+        }
+        SignatureFormat::Native => {
+            info!(
+                "Signing config for manifest: \"{}\" in: \"{}\"",
+                reference,
+                app.display()
+            );
+            oci::sign_image(
+                &mut tx,
+                &digest,
+                key.private.as_ref(),
+                &key.signature,
+                expiry,
+            )?
+        }
+    };
+
+    tx.commit()?;
+
+    if !dry_run {
+        info!("Manifest signed");
+    }
+
+    Ok(signed)
+}
+
+/// Verify a single reference of an application, returns the manifest digest
+fn verify_reference(
+    app: &Path,
+    reference: &str,
+    ca_pub: &[u8],
+    policy: &oci::VerifyPolicy,
+    format: SignatureFormat,
+) -> SignerResult<String>
+{
+    let blobs = app.join(BLOBS_SUBDIR);
+    let digest = oci::find_manifest_by_reference(app, reference)?;
+    match format {
+        SignatureFormat::Native => oci::verify_config(&blobs, &digest, ca_pub, policy)?,
+        SignatureFormat::Cosign => cosign::verify_manifest(&blobs, &digest, ca_pub, policy)?,
+    }
 
-    let key = crypto::generate_key();
-    let key_u8 = crypto::export_private(&key)?;
-    utils::file_write(output, &key_u8)?;
+    Ok(digest)
+}
+
+fn is_signed(app: &Path, digest: &str, format: SignatureFormat) -> SignerResult<bool>
+{
+    let blobs = app.join(BLOBS_SUBDIR);
+    match format {
+        SignatureFormat::Native => oci::is_signed(&blobs, digest),
+        SignatureFormat::Cosign => cosign::is_signed(&blobs, digest),
+    }
+}
+
+pub(crate) fn cmd_generate_key(
+    output: &str,
+    algorithm: crypto::KeyAlgorithm,
+    encrypt: Option<crypto::Kdf>,
+    output_format: FileFormat,
+) -> SignerResult<()>
+{
+    info!("Generating {} key: \"{}\"", algorithm, output);
+
+    let key = crypto::generate_key(algorithm)?;
+    let key_u8 = match encrypt {
+        Some(kdf) => {
+            info!("Encrypting the key with {:?} derived passphrase", kdf);
+            let passphrase = passphrase::read_new(output, passphrase::source())?;
+            crypto::encrypt_private(key.as_ref(), kdf, &passphrase)?
+        }
+        None => key.to_der()?,
+    };
+    let label = format::private_label(algorithm, encrypt.is_some());
+    format::write(output, &key_u8, label, output_format)?;
 
     info!("Key generated");
 
     Ok(())
 }
 
-pub(crate) fn cmd_extract_public(input: &str, output: &str) -> SignerResult<()>
+pub(crate) fn cmd_extract_public(
+    input: &str,
+    output: &str,
+    output_format: PublicFormat,
+) -> SignerResult<()>
 {
     info!("Extracting public key from \"{}\" to \"{}\"", input, output);
 
-    let private_u8 = utils::file_read(input)?;
-    let private = crypto::import_private(&private_u8)?;
-    let public = crypto::extract_public(&private);
-    let public_u8 = crypto::export_public(&public)?;
-    utils::file_write(output, &public_u8)?;
+    let private = crypto::load_private(input, passphrase::source())?;
+    let public = private.public_key();
+    format::write_public(output, public.as_ref(), output_format)?;
 
-    info!("Public key extracted");
+    info!("Public {} key extracted", public.algorithm());
 
     Ok(())
 }
 
 #[allow(dead_code)]
-pub(crate) fn cmd_sign(key: &str, file: &str, signature: &str) -> SignerResult<()>
+pub(crate) fn cmd_sign(
+    key: &str,
+    file: &str,
+    signature: &str,
+    output_format: FileFormat,
+) -> SignerResult<()>
 {
     info!(
         "Signing file \"{}\" with key \"{}\" into signature \"{}\"",
         file, key, signature
     );
 
-    let der = utils::file_read(key)?;
-    let private = crypto::import_private(&der)?;
+    let private = crypto::load_private(key, passphrase::source())?;
     let msg = utils::file_read(file)?;
 
-    let sign = crypto::sign(&private, &msg)?;
-    utils::file_write(signature, &sign)?;
+    let sign = private.sign(&msg)?;
+    format::write(signature, &sign, format::PEM_SIGNATURE, output_format)?;
 
     info!("File signed, signature written");
 
     Ok(())
 }
 
-pub(crate) fn cmd_sign_buf(key: &str, file: &str, signature: &str) -> SignerResult<()>
+pub(crate) fn cmd_sign_buf(
+    key: &str,
+    file: &str,
+    signature: &str,
+    output_format: FileFormat,
+) -> SignerResult<()>
 {
     info!(
         "Signing file \"{}\" with key \"{}\" into signature \"{}\"",
         file, key, signature
     );
 
-    let der = utils::file_read(key)?;
-    let private = crypto::import_private(&der)?;
+    let private = crypto::load_private(key, passphrase::source())?;
     let mut msg = File::open(file)?;
 
-    let sign = crypto::sign_reader(&private, &mut msg)?;
-    utils::file_write(signature, &sign)?;
+    let sign = private.sign_reader(&mut msg)?;
+    format::write(signature, &sign, format::PEM_SIGNATURE, output_format)?;
 
     info!("File signed, signature written");
 
@@ -85,12 +311,14 @@ pub(crate) fn cmd_verify(key: &str, file: &str, signature: &str) -> SignerResult
         file, key, signature
     );
 
-    let der = utils::file_read(key)?;
+    let der = format::read_der(key)?;
     let public = crypto::import_public(&der)?;
     let msg = utils::file_read(file)?;
-    let sign = utils::file_read(signature)?;
+    let sign = format::read_der(signature)?;
 
-    crypto::verify(&public, &msg, &sign).or(err!("Signature verification failed"))?;
+    public
+        .verify(&msg, &sign)
+        .or(err!("Signature verification failed"))?;
 
     info!("File verified");
 
@@ -104,12 +332,14 @@ pub(crate) fn cmd_verify_buf(key: &str, file: &str, signature: &str) -> SignerRe
         file, key, signature
     );
 
-    let der = utils::file_read(key)?;
+    let der = format::read_der(key)?;
     let public = crypto::import_public(&der)?;
     let mut msg = File::open(file)?;
-    let sign = utils::file_read(signature)?;
+    let sign = format::read_der(signature)?;
 
-    crypto::verify_reader(&public, &mut msg, &sign).or(err!("Signature verification failed"))?;
+    public
+        .verify_reader(&mut msg, &sign)
+        .or(err!("Signature verification failed"))?;
 
     info!("File verified");
 
@@ -123,6 +353,7 @@ pub(crate) fn cmd_sign_config(
     vendor_prv: &str,
     vendor_pub_signature: &str,
     ca_pub: &str,
+    expiry_days: Option<u64>,
 ) -> SignerResult<()>
 {
     let blobs = Path::new(registry).join(app).join(BLOBS_SUBDIR);
@@ -132,12 +363,18 @@ pub(crate) fn cmd_sign_config(
         blobs.display()
     );
 
-    let vendor_prv = utils::file_read(vendor_prv)?;
-    let ca_pub = utils::file_read(ca_pub)?;
-    let vendor_sign = utils::file_read(vendor_pub_signature)?;
+    let vendor_prv = crypto::load_private(vendor_prv, passphrase::source())?;
+    let ca_pub = format::read_der(ca_pub)?;
+    let vendor_sign = format::read_der(vendor_pub_signature)?;
 
-    oci::verify_vendor_pub_signature(&vendor_prv, &vendor_sign, &ca_pub)?;
-    oci::sign_config(&blobs, digest, &vendor_prv, &vendor_sign)?;
+    oci::verify_vendor_pub_signature(vendor_prv.as_ref(), &vendor_sign, &ca_pub)?;
+    oci::sign_config(
+        &blobs,
+        digest,
+        vendor_prv.as_ref(),
+        &vendor_sign,
+        expiry_from_days(expiry_days),
+    )?;
 
     info!("Config signed");
 
@@ -164,51 +401,93 @@ pub(crate) fn cmd_sign_image(
     registry: &str,
     app: &str,
     reference: &str,
-    vendor_prv: &str,
-    vendor_pub_signature: Option<&str>,
-    ca_pub: Option<&str>,
-    ca_prv: Option<&str>,
+    keys: &SigningKeys,
+    expiry_days: Option<u64>,
+    format: SignatureFormat,
+    dry_run: bool,
 ) -> SignerResult<()>
 {
-    let vendor_prv = utils::file_read(vendor_prv)?;
+    let key = load_vendor_key(keys)?;
+    sign_reference(
+        registry,
+        app,
+        reference,
+        &key,
+        expiry_from_days(expiry_days),
+        format,
+        dry_run,
+    )?;
 
-    // realistic or simple variant handling
-    let vendor_sign = match (vendor_pub_signature, ca_pub, ca_prv) {
-        (Some(vendor_pub_signature), Some(ca_pub), None) => {
-            let ca_pub = utils::file_read(ca_pub)?;
-            let vendor_sign = utils::file_read(vendor_pub_signature)?;
-            oci::verify_vendor_pub_signature(&vendor_prv, &vendor_sign, &ca_pub)?;
-            vendor_sign
-        }
-        (None, None, Some(ca_prv)) => {
-            let ca_prv = utils::file_read(ca_prv)?;
-            oci::sign_vendor_pub(&vendor_prv, &ca_prv)?
-        }
-        _ => err!("You need to pass either VENDOR_PUB_SIGNATURE and CA_PUB or CA_PRV")?,
-    };
+    Ok(())
+}
 
-    let app = Path::new(registry).join(app);
-    let blobs = app.join(BLOBS_SUBDIR);
+/// Sign all the tags matching a pattern in all the applications of a registry,
+/// a manifest with more tags is signed only once
+pub(crate) fn cmd_sign_registry(
+    registry: &str,
+    tag_pattern: &str,
+    keys: &SigningKeys,
+    expiry_days: Option<u64>,
+    format: SignatureFormat,
+    dry_run: bool,
+) -> SignerResult<()>
+{
     info!(
-        "Signing config for manifest: \"{}\" in: \"{}\"",
-        reference,
-        app.display()
+        "Signing tags matching \"{}\" in registry: \"{}\"",
+        tag_pattern, registry
     );
-    let digest = oci::find_manifest_by_reference(&app, reference)?;
-    oci::sign_config(&blobs, &digest, &vendor_prv, &vendor_sign)?;
 
-    info!("Rehashing file: \"{}\" in: \"{}\"", digest, blobs.display());
-    let new_info = oci::rehash_rename_file(&blobs, &digest)?;
-
-    if let Some((new_digest, _)) = new_info {
-        info!("Rehashed to: \"{}\"", new_digest);
-        info!("Updating all indexes referencing the manifest");
-        oci::replace_hash_index(&blobs, &digest, &new_digest)?;
-    } else {
-        info!("File does not require renaming");
+    let key = load_vendor_key(keys)?;
+    let expiry = expiry_from_days(expiry_days);
+    let (mut signed, mut failed) = (0, 0);
+
+    for app_name in oci::list_apps(Path::new(registry))? {
+        let app = Path::new(registry).join(&app_name);
+        let tags = match oci::list_tags(&app) {
+            Ok(tags) => tags,
+            Err(e) => {
+                error!("Can't read application \"{}\": {}", app_name, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let mut signed_digests = HashSet::new();
+        for tag in tags.iter().filter(|t| utils::glob_match(tag_pattern, t)) {
+            // other tags of the manifest already point to the signed one
+            let digest = match oci::find_manifest_by_reference(&app, tag) {
+                Ok(digest) => digest,
+                Err(e) => {
+                    error!("Can't find \"{}:{}\": {}", app_name, tag, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            if signed_digests.contains(&digest) {
+                info!("Tag \"{}\" of \"{}\" already signed", tag, app_name);
+                continue;
+            }
+
+            match sign_reference(registry, &app_name, tag, &key, expiry, format, dry_run) {
+                Ok(new_digest) => {
+                    // with DRY_RUN the other tags still point to the old one
+                    signed_digests.insert(digest);
+                    signed_digests.insert(new_digest);
+                    signed += 1;
+                }
+                Err(e) => {
+                    error!("Signing \"{}:{}\" failed: {}", app_name, tag, e);
+                    failed += 1;
+                }
+            }
+        }
     }
 
-    info!("Config signed for a given manifest");
+    info!("Signed {} manifests, {} failed", signed, failed);
+
+    if failed > 0 {
+        return err!("Signing of {} manifests failed", failed);
+    }
 
     Ok(())
 }
@@ -218,55 +497,242 @@ pub(crate) fn cmd_extract_sign_image(
     filename: &str,
     app: Option<&str>,
     reference: &str,
-    vendor_prv: &str,
-    vendor_pub_signature: Option<&str>,
-    ca_pub: Option<&str>,
-    ca_prv: Option<&str>,
+    keys: &SigningKeys,
+    expiry_days: Option<u64>,
+    format: SignatureFormat,
 ) -> SignerResult<()>
 {
     let path = Path::new(filename);
     let app_name = match app {
         Some(a) => a,
-        None => path.file_stem().unwrap().to_str().unwrap(),
+        None => match archive::app_name(path) {
+            Some(name) if !name.is_empty() => name,
+            _ => return err!("Can't get the application name from \"{}\"", filename),
+        },
     };
 
     let app_dir = Path::new(registry).join(app_name);
     info!("Unpacking \"{}\" into \"{}\"", filename, app_dir.display());
-    std::fs::create_dir(&app_dir)?;
-
-    let mut tar = tar::Archive::new(File::open(path)?);
-    tar.unpack(&app_dir)?;
+    archive::import(path, &app_dir)?;
 
     cmd_sign_image(
         registry,
         app_name,
         reference,
-        vendor_prv,
-        vendor_pub_signature,
-        ca_pub,
-        ca_prv,
+        keys,
+        expiry_days,
+        format,
+        false,
     )
 }
 
+pub(crate) fn cmd_build(
+    registry: &str,
+    app: &str,
+    tag: &str,
+    rootfs: &str,
+    spec: &builder::ImageSpec,
+    compression: builder::Compression,
+) -> SignerResult<()>
+{
+    let app = Path::new(registry).join(app);
+    info!(
+        "Building \"{}\" from \"{}\" in: \"{}\"",
+        tag,
+        rootfs,
+        app.display()
+    );
+
+    let digest = builder::build_image(&app, tag, Path::new(rootfs), spec, compression)?;
+
+    info!("Image built: \"{}\"", digest);
+
+    Ok(())
+}
+
+pub(crate) fn cmd_append_layer(
+    registry: &str,
+    app: &str,
+    reference: &str,
+    layer: &str,
+    tag: Option<&str>,
+    compression: builder::Compression,
+) -> SignerResult<()>
+{
+    let app = Path::new(registry).join(app);
+    info!(
+        "Appending \"{}\" to \"{}\" in: \"{}\"",
+        layer,
+        reference,
+        app.display()
+    );
+
+    let digest = builder::append_layer(&app, reference, Path::new(layer), tag, compression)?;
+
+    info!("Layer appended: \"{}\"", digest);
+
+    Ok(())
+}
+
 pub(crate) fn cmd_verify_image(
     registry: &str,
     app: &str,
     reference: &str,
-    ca_pub: &str,
+    trust: &TrustPolicy,
+    format: SignatureFormat,
 ) -> SignerResult<()>
 {
     let app = Path::new(registry).join(app);
-    let blobs = app.join(BLOBS_SUBDIR);
     info!(
         "Verifying config for manifest: \"{}\" in: \"{}\"",
         reference,
         app.display()
     );
-    let ca_pub = utils::file_read(ca_pub)?;
-    let digest = oci::find_manifest_by_reference(&app, reference)?;
-    oci::verify_config(&blobs, &digest, &ca_pub)?;
+    let ca_pub = format::read_der(trust.ca_pub)?;
+    let revocation =
+        load_revocation_list(trust.revocation_list, trust.revocation_signature, &ca_pub)?;
+    let policy = load_verify_policy(trust, revocation.as_ref())?;
+    verify_reference(&app, reference, &ca_pub, &policy, format)?;
 
     info!("Verification succesful");
 
     Ok(())
 }
+
+/// Verify all the tags matching a pattern in all the applications of a
+/// registry and write a JSON report to OUTPUT or stdout
+pub(crate) fn cmd_verify_registry(
+    registry: &str,
+    tag_pattern: &str,
+    trust: &TrustPolicy,
+    format: SignatureFormat,
+    output: Option<&str>,
+) -> SignerResult<()>
+{
+    info!(
+        "Verifying tags matching \"{}\" in registry: \"{}\"",
+        tag_pattern, registry
+    );
+
+    let ca_pub = format::read_der(trust.ca_pub)?;
+    let revocation =
+        load_revocation_list(trust.revocation_list, trust.revocation_signature, &ca_pub)?;
+    let policy = load_verify_policy(trust, revocation.as_ref())?;
+    let mut report = VerifyReport::new(registry, format);
+
+    for app_name in oci::list_apps(Path::new(registry))? {
+        let app = Path::new(registry).join(&app_name);
+        let tags = match oci::list_tags(&app) {
+            Ok(tags) => tags,
+            Err(e) => {
+                error!("Can't read application \"{}\": {}", app_name, e);
+                report.add(ImageReport {
+                    app: app_name,
+                    tag: None,
+                    digest: None,
+                    status: ImageStatus::Failed,
+                    error: Some(e.to_string()),
+                });
+                continue;
+            }
+        };
+
+        for tag in tags
+            .into_iter()
+            .filter(|t| utils::glob_match(tag_pattern, t))
+        {
+            let digest = match oci::find_manifest_by_reference(&app, &tag) {
+                Ok(digest) => digest,
+                Err(e) => {
+                    error!("Can't find \"{}:{}\": {}", app_name, tag, e);
+                    report.add(ImageReport {
+                        app: app_name.clone(),
+                        tag: Some(tag),
+                        digest: None,
+                        status: ImageStatus::Failed,
+                        error: Some(e.to_string()),
+                    });
+                    continue;
+                }
+            };
+            let (status, error) = match verify_reference(&app, &tag, &ca_pub, &policy, format) {
+                Ok(_) => (ImageStatus::Verified, None),
+                // an unreadable signature is a failure, not a missing one
+                Err(_) if !is_signed(&app, &digest, format).unwrap_or(true) => {
+                    (ImageStatus::Unsigned, None)
+                }
+                Err(e) => (ImageStatus::Failed, Some(e.to_string())),
+            };
+            info!("Image \"{}:{}\": {:?}", app_name, tag, status);
+            report.add(ImageReport {
+                app: app_name.clone(),
+                tag: Some(tag),
+                digest: Some(digest),
+                status,
+                error,
+            });
+        }
+    }
+
+    let json = serde_json::to_vec_pretty(&report)?;
+    match output {
+        Some(path) => {
+            info!("Writing report: \"{}\"", path);
+            utils::file_write(path, &json)?;
+        }
+        None => println!("{}", String::from_utf8_lossy(&json)),
+    }
+
+    info!(
+        "Verified: {}, failed: {}, unsigned: {}",
+        report.verified, report.failed, report.unsigned
+    );
+
+    if !report.is_success() {
+        return err!("Not all images in the registry verified");
+    }
+
+    Ok(())
+}
+
+pub(crate) fn cmd_revoke_key(
+    vendor_pub: &str,
+    revocation_list: &str,
+    revocation_signature: &str,
+    ca_prv: &str,
+    output_format: FileFormat,
+) -> SignerResult<()>
+{
+    info!(
+        "Revoking key \"{}\" in revocation list \"{}\"",
+        vendor_pub, revocation_list
+    );
+
+    let vendor_pub = format::read_der(vendor_pub)?;
+    let ca_prv = crypto::load_private(ca_prv, passphrase::source())?;
+
+    // make sure it's a proper public key before adding its fingerprint
+    crypto::import_public(&vendor_pub)?;
+
+    let mut list = if Path::new(revocation_list).exists() {
+        RevocationList::from_file(revocation_list)?
+    } else {
+        info!("Revocation list doesn't exist, creating a new one");
+        RevocationList::default()
+    };
+
+    if !list.revoke(&vendor_pub)? {
+        info!("Key already revoked, signing the list again");
+    }
+
+    list.save(
+        revocation_list,
+        revocation_signature,
+        ca_prv.as_ref(),
+        output_format,
+    )?;
+
+    info!("Revocation list signed, signature written");
+
+    Ok(())
+}
@@ -0,0 +1,291 @@
+use log::info;
+use oci_spec::image::ImageIndex;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::oci::{BLOBS_SUBDIR, INDEX_JSON};
+use crate::{crypto, digest::Digest, reproducible, utils, SignerResult};
+
+/// Changes to an application made so that a crash never leaves a broken
+/// layout: new blobs are written and synced first, "index.json" is replaced
+/// atomically on commit and blobs no longer referenced are removed only after
+/// that. Files are staged in the application directory, outside of the blobs
+/// where a leftover could be taken for a blob. In dry-run mode nothing is
+/// written, the changes are only logged.
+pub(crate) struct Transaction
+{
+    app: PathBuf,
+    dry_run: bool,
+    index: ImageIndex,
+    // blobs written in this transaction, dry-run needs them to read them back
+    written: HashMap<String, Vec<u8>>,
+    replaced: Vec<(String, String)>,
+    stale: HashSet<String>,
+}
+
+impl Transaction
+{
+    pub fn open<T: AsRef<Path>>(app: T, dry_run: bool) -> SignerResult<Self>
+    {
+        let app = app.as_ref().to_path_buf();
+        let index = ImageIndex::from_file(app.join(INDEX_JSON))?;
+        if !dry_run {
+            remove_staged(&app.join(BLOBS_SUBDIR))?;
+        }
+
+        Ok(Self {
+            app,
+            dry_run,
+            index,
+            written: HashMap::new(),
+            replaced: Vec::new(),
+            stale: HashSet::new(),
+        })
+    }
+
+    pub fn blobs(&self) -> PathBuf
+    {
+        self.app.join(BLOBS_SUBDIR)
+    }
+
+    pub fn index(&self) -> &ImageIndex
+    {
+        &self.index
+    }
+
+    pub fn index_mut(&mut self) -> &mut ImageIndex
+    {
+        &mut self.index
+    }
+
+    /// Add a blob hashed with a given algorithm, returns its digest and size
+    pub fn write_blob(&mut self, algo: &str, data: &[u8]) -> SignerResult<(String, i64)>
+    {
+        let hash = crypto::hash_reader(algo, &mut &data[..])?;
+        let digest = Digest::new_unchecked(algo.to_string(), hex::encode(hash));
+        let path = self.blobs().join(digest.to_path());
+        let digest = String::from(digest);
+
+        // blobs are content addressed, writing a new one can't break anything
+        if !self.dry_run {
+            utils::file_write_atomic(&path, &self.app, data)?;
+        }
+        self.written.insert(digest.clone(), data.to_vec());
+
+        Ok((digest, data.len().try_into().unwrap()))
+    }
+
+    /// Read a blob, also the one written in this transaction
+    pub fn read_blob(&self, digest: &str) -> SignerResult<Vec<u8>>
+    {
+        match self.written.get(digest) {
+            Some(data) => Ok(data.clone()),
+            None => Ok(utils::file_read(
+                self.blobs().join(Digest::try_from(digest)?.to_path()),
+            )?),
+        }
+    }
+
+    /// Record that a blob is replaced by another one, the old one is removed
+    pub fn replace(&mut self, from: &str, to: &str)
+    {
+        self.replaced.push((from.to_string(), to.to_string()));
+        self.remove(from);
+    }
+
+    /// Remove a blob once the transaction is committed
+    pub fn remove(&mut self, digest: &str)
+    {
+        self.stale.insert(digest.to_string());
+    }
+
+    pub fn commit(self) -> SignerResult<()>
+    {
+        let stale: Vec<&String> = self
+            .stale
+            .iter()
+            .filter(|digest| !self.written.contains_key(*digest))
+            .collect();
+
+        if self.dry_run {
+            info!("Dry run, nothing is written");
+            for (from, to) in &self.replaced {
+                info!("Would replace \"{}\" with \"{}\"", from, to);
+            }
+            for digest in self.written.keys() {
+                info!("Would write blob \"{}\"", digest);
+            }
+            for digest in stale {
+                info!("Would remove blob \"{}\"", digest);
+            }
+            return Ok(());
+        }
+
+        let index = reproducible::to_json(&self.index)?;
+        utils::file_write_atomic(self.app.join(INDEX_JSON), &self.app, &index)?;
+
+        for digest in stale {
+            let path = self.blobs().join(Digest::try_from(digest)?.to_path());
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Remove "*.tmp" files left in the blobs by an interrupted write, they were
+/// staged there before and would be taken for blobs with an invalid digest
+fn remove_staged(blobs: &Path) -> SignerResult<()>
+{
+    if !blobs.is_dir() {
+        return Ok(());
+    }
+
+    for algo in std::fs::read_dir(blobs)? {
+        let algo = algo?.path();
+        if !algo.is_dir() {
+            continue;
+        }
+        for file in std::fs::read_dir(&algo)? {
+            let file = file?.path();
+            if file.extension().is_some_and(|ext| ext == "tmp") {
+                info!("Removing leftover \"{}\"", file.display());
+                std::fs::remove_file(file)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use oci_spec::image::{ImageIndex, ImageIndexBuilder, MediaType};
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use super::Transaction;
+    use crate::digest::Digest;
+    use crate::oci::{BLOBS_SUBDIR, INDEX_JSON};
+    use crate::reproducible;
+
+    fn create_app(name: &str) -> PathBuf
+    {
+        let app = std::env::temp_dir().join(format!("ir-sign-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&app);
+        std::fs::create_dir_all(app.join(BLOBS_SUBDIR).join("sha256")).unwrap();
+        let index = ImageIndexBuilder::default()
+            .schema_version(2u32)
+            .media_type(MediaType::ImageIndex)
+            .manifests(vec![])
+            .build()
+            .unwrap();
+        reproducible::write_json(app.join(INDEX_JSON), &index).unwrap();
+        app
+    }
+
+    fn blob_path(app: &Path, digest: &str) -> PathBuf
+    {
+        app.join(BLOBS_SUBDIR)
+            .join(Digest::try_from(digest).unwrap().to_path())
+    }
+
+    fn annotate(tx: &mut Transaction, value: &str)
+    {
+        let annotations = HashMap::from([("test".to_string(), value.to_string())]);
+        tx.index_mut().set_annotations(Some(annotations));
+    }
+
+    fn annotation(app: &Path) -> Option<String>
+    {
+        let index = ImageIndex::from_file(app.join(INDEX_JSON)).unwrap();
+        index.annotations().as_ref()?.get("test").cloned()
+    }
+
+    #[test]
+    fn transaction_commit()
+    {
+        let app = create_app("transaction-commit");
+
+        let mut tx = Transaction::open(&app, false).unwrap();
+        let (old, size) = tx.write_blob("sha256", b"old").unwrap();
+        assert_eq!(size, 3);
+        annotate(&mut tx, "old");
+        tx.commit().unwrap();
+        assert_eq!(annotation(&app).as_deref(), Some("old"));
+
+        // nothing but the content addressed blob is visible until committed
+        let mut tx = Transaction::open(&app, false).unwrap();
+        let (new, _) = tx.write_blob("sha256", b"new").unwrap();
+        assert_eq!(tx.read_blob(&old).unwrap(), b"old");
+        tx.replace(&old, &new);
+        annotate(&mut tx, "new");
+        drop(tx);
+        assert_eq!(annotation(&app).as_deref(), Some("old"));
+        assert!(blob_path(&app, &old).exists());
+
+        let mut tx = Transaction::open(&app, false).unwrap();
+        let (new, _) = tx.write_blob("sha256", b"new").unwrap();
+        tx.replace(&old, &new);
+        // a blob written again in the same transaction is kept
+        tx.remove(&new);
+        annotate(&mut tx, "new");
+        tx.commit().unwrap();
+        assert_eq!(annotation(&app).as_deref(), Some("new"));
+        assert!(!blob_path(&app, &old).exists());
+        assert!(blob_path(&app, &new).exists());
+
+        std::fs::remove_dir_all(&app).unwrap();
+    }
+
+    #[test]
+    fn transaction_dry_run()
+    {
+        let app = create_app("transaction-dry-run");
+
+        let mut tx = Transaction::open(&app, true).unwrap();
+        let (digest, _) = tx.write_blob("sha256", b"blob").unwrap();
+        assert_eq!(tx.read_blob(&digest).unwrap(), b"blob");
+        annotate(&mut tx, "dry-run");
+        tx.commit().unwrap();
+
+        assert!(!blob_path(&app, &digest).exists());
+        assert_eq!(annotation(&app), None);
+
+        std::fs::remove_dir_all(&app).unwrap();
+    }
+
+    #[test]
+    fn transaction_staging()
+    {
+        let app = create_app("transaction-staging");
+        let blobs = app.join(BLOBS_SUBDIR).join("sha256");
+        let leftover = blobs.join(format!("{}.tmp", "0".repeat(64)));
+        std::fs::write(&leftover, b"partial").unwrap();
+
+        // leftovers of an interrupted write are cleaned, dry-run leaves them
+        drop(Transaction::open(&app, true).unwrap());
+        assert!(leftover.exists());
+        let mut tx = Transaction::open(&app, false).unwrap();
+        assert!(!leftover.exists());
+
+        // only blobs end up in the blobs, nothing staged is left behind
+        let (digest, _) = tx.write_blob("sha256", b"blob").unwrap();
+        annotate(&mut tx, "staged");
+        tx.commit().unwrap();
+        for entry in std::fs::read_dir(&blobs).unwrap() {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            Digest::try_from(format!("sha256:{}", name).as_str()).unwrap();
+        }
+        for entry in std::fs::read_dir(&app).unwrap() {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            assert!(!name.contains(".tmp"), "leftover \"{}\"", name);
+        }
+        assert!(blob_path(&app, &digest).exists());
+
+        std::fs::remove_dir_all(&app).unwrap();
+    }
+}
@@ -14,7 +14,66 @@ pub fn file_write<T: AsRef<Path>>(filename: T, data: &[u8]) -> std::io::Result<(
     File::create(filename)?.write_all(data)
 }
 
+/// Write a file so it's either complete or not there at all: a temporary file
+/// is created in the staging directory, synced and renamed over the target,
+/// then the directory is synced. The staging directory has to be on the same
+/// filesystem as the target.
+pub fn file_write_atomic<T: AsRef<Path>, S: AsRef<Path>>(
+    filename: T,
+    staging: S,
+    data: &[u8],
+) -> std::io::Result<()>
+{
+    let path = filename.as_ref();
+
+    let mut file = tempfile::NamedTempFile::new_in(staging)?;
+    file.write_all(data)?;
+    file.as_file().sync_all()?;
+    file.persist(path).map_err(|e| e.error)?;
+
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
 pub fn file_len<T: AsRef<Path>>(filename: T) -> std::io::Result<u64>
 {
     Ok(std::fs::metadata(filename)?.len())
 }
+
+/// Shell style pattern matching with "*" and "?" wildcards
+pub fn glob_match(pattern: &str, text: &str) -> bool
+{
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last "*" and of the text it matches up to
+    let mut star = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+pub fn unix_time() -> u64
+{
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
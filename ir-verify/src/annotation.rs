@@ -0,0 +1,73 @@
+//! Names of the manifest annotations keeping the signatures. The first
+//! signature is kept directly under the prefix for compatibility, the
+//! following ones under "PREFIX.signatures.N." with N starting from 1.
+
+pub const ANNOTATION_PREFIX: &str = "com.samsung.islet.image";
+pub const ANNOTATION_SIGNATURES: &str = "signatures";
+
+pub const SIGNATURE: &str = "signature";
+pub const VENDORPUB: &str = "vendorpub";
+pub const VENDORPUB_SIGNATURE: &str = "vendorpub.signature";
+pub const SIGNATURE_TIMESTAMP: &str = "signature.timestamp";
+pub const SIGNATURE_EXPIRY: &str = "signature.expiry";
+pub const SIGNATURE_ALGORITHM: &str = "signature.algorithm";
+
+/// Names kept in every signature slot
+pub const SLOT_NAMES: &[&str] = &[
+    SIGNATURE,
+    VENDORPUB,
+    VENDORPUB_SIGNATURE,
+    SIGNATURE_TIMESTAMP,
+    SIGNATURE_EXPIRY,
+    SIGNATURE_ALGORITHM,
+];
+
+/// Annotation key of a name in a signature slot
+pub fn annotation(slot: usize, name: &str) -> String
+{
+    match slot {
+        0 => format!("{}.{}", ANNOTATION_PREFIX, name),
+        n => format!(
+            "{}.{}.{}.{}",
+            ANNOTATION_PREFIX, ANNOTATION_SIGNATURES, n, name
+        ),
+    }
+}
+
+/// Whether an annotation belongs to one of the signature slots
+pub fn is_slot_annotation(key: &str) -> bool
+{
+    let Some(name) = key.strip_prefix(ANNOTATION_PREFIX).and_then(|k| k.strip_prefix('.')) else {
+        return false;
+    };
+    let name = name
+        .strip_prefix(ANNOTATION_SIGNATURES)
+        .and_then(|n| n.strip_prefix('.'))
+        .and_then(|n| n.split_once('.'))
+        .filter(|(slot, _)| slot.parse::<usize>().is_ok_and(|slot| slot > 0))
+        .map_or(name, |(_, name)| name);
+    SLOT_NAMES.contains(&name)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{annotation, is_slot_annotation, SIGNATURE_EXPIRY, VENDORPUB};
+
+    #[test]
+    fn annotation_slots()
+    {
+        assert_eq!(annotation(0, VENDORPUB), "com.samsung.islet.image.vendorpub");
+        assert_eq!(
+            annotation(2, SIGNATURE_EXPIRY),
+            "com.samsung.islet.image.signatures.2.signature.expiry"
+        );
+
+        assert!(is_slot_annotation(&annotation(0, VENDORPUB)));
+        assert!(is_slot_annotation(&annotation(12, SIGNATURE_EXPIRY)));
+        assert!(!is_slot_annotation("com.samsung.islet.image.description"));
+        assert!(!is_slot_annotation("com.samsung.islet.image.signatures.note"));
+        assert!(!is_slot_annotation("com.samsung.islet.image.signatures.0.vendorpub"));
+        assert!(!is_slot_annotation("org.opencontainers.image.vendorpub"));
+    }
+}
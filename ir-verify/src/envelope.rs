@@ -0,0 +1,56 @@
+//! What a vendor key signs for a signature with a timestamp: the config digest
+//! along with the timestamp and expiry kept in the manifest annotations. Every
+//! field is length-prefixed, so none of them can be moved into another or
+//! dropped without breaking the signature. Signatures without a timestamp
+//! cover the config blob alone.
+
+const ENVELOPE_TYPE: &str = "com.samsung.islet.image.signature.v1";
+
+fn push_field(envelope: &mut Vec<u8>, field: Option<&str>)
+{
+    match field {
+        Some(field) => {
+            envelope.push(1);
+            envelope.extend_from_slice(&(field.len() as u64).to_be_bytes());
+            envelope.extend_from_slice(field.as_bytes());
+        }
+        None => envelope.push(0),
+    }
+}
+
+/// Envelope of a config given by its digest ("sha256:HEX")
+pub fn envelope(config_digest: &str, timestamp: &str, expiry: Option<&str>) -> Vec<u8>
+{
+    let mut envelope = Vec::new();
+    push_field(&mut envelope, Some(ENVELOPE_TYPE));
+    push_field(&mut envelope, Some(config_digest));
+    push_field(&mut envelope, Some(timestamp));
+    push_field(&mut envelope, expiry);
+    envelope
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::envelope;
+
+    const DIGEST: &str = "sha256:2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae";
+
+    #[test]
+    fn envelope_fields()
+    {
+        let signed = envelope(DIGEST, "1700000000", Some("1800000000"));
+        assert_ne!(signed, envelope(DIGEST, "1700000000", None));
+        assert_ne!(signed, envelope(DIGEST, "1700000000\n1800000000", None));
+        assert_ne!(
+            envelope(DIGEST, "1700000000", Some("")),
+            envelope(DIGEST, "1700000000", None)
+        );
+        assert_eq!(signed, envelope(DIGEST, "1700000000", Some("1800000000")));
+
+        // the expiry is the last field, nothing can follow it
+        let mut extended = envelope(DIGEST, "1700000000", None);
+        extended.extend_from_slice(b"1800000000");
+        assert_ne!(signed, extended);
+    }
+}
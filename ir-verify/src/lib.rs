@@ -0,0 +1,201 @@
+//! Verification of signatures made with `ir-sign` keys, shared by the tools
+//! that need to check them (e.g. a revocation list signed with ROOT-CA key).
+//! The algorithm is detected from the SPKI public key and the hash function is
+//! fixed per algorithm: SHA384 for ECDSA P384, SHA256 for ECDSA P256 and
+//! RSA-PSS, none for Ed25519.
+//!
+//! It also keeps what the tools need to agree on to read the signatures: the
+//! annotation names, the signed envelope and the date format.
+
+pub mod annotation;
+pub mod envelope;
+pub mod time;
+
+use p384::ecdsa::signature::Verifier;
+use rsa::pkcs1::DecodeRsaPublicKey;
+use sha2::Sha256;
+use spki::der::pem;
+use spki::{DecodePublicKey, ObjectIdentifier, SubjectPublicKeyInfoRef};
+
+/// id-RSASSA-PSS (RFC 4055), what `openssl genpkey -algorithm RSA-PSS` writes
+/// instead of rsaEncryption
+pub const RSASSA_PSS_OID: ObjectIdentifier =
+    ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.10");
+
+/// PEM label of the signatures written by `ir-sign --format pem`
+pub const PEM_SIGNATURE: &str = "SIGNATURE";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyError
+{
+    UnsupportedKey,
+    MalformedSignature,
+    VerificationFailed,
+}
+
+impl std::error::Error for VerifyError {}
+
+impl std::fmt::Display for VerifyError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self {
+            VerifyError::UnsupportedKey => write!(f, "Unsupported or malformed public key"),
+            VerifyError::MalformedSignature => write!(f, "Malformed signature"),
+            VerifyError::VerificationFailed => write!(f, "Signature verification failed"),
+        }
+    }
+}
+
+/// Verify a signature of a message with a public key in SPKI DER, ECDSA
+/// signatures are DER encoded, Ed25519 and RSA-PSS ones are raw
+pub fn verify(public_der: &[u8], msg: &[u8], signature: &[u8]) -> Result<(), VerifyError>
+{
+    if let Ok(key) = p384::ecdsa::VerifyingKey::from_public_key_der(public_der) {
+        let sign = p384::ecdsa::Signature::from_der(signature)
+            .or(Err(VerifyError::MalformedSignature))?;
+        return key
+            .verify(msg, &sign)
+            .or(Err(VerifyError::VerificationFailed));
+    }
+
+    if let Ok(key) = p256::ecdsa::VerifyingKey::from_public_key_der(public_der) {
+        let sign = p256::ecdsa::Signature::from_der(signature)
+            .or(Err(VerifyError::MalformedSignature))?;
+        return key
+            .verify(msg, &sign)
+            .or(Err(VerifyError::VerificationFailed));
+    }
+
+    if let Ok(key) = ed25519_dalek::VerifyingKey::from_public_key_der(public_der) {
+        let sign = ed25519_dalek::Signature::from_slice(signature)
+            .or(Err(VerifyError::MalformedSignature))?;
+        return key
+            .verify(msg, &sign)
+            .or(Err(VerifyError::VerificationFailed));
+    }
+
+    let key = rsa::pss::VerifyingKey::<Sha256>::new(rsa_public_key(public_der)?);
+    let sign =
+        rsa::pss::Signature::try_from(signature).or(Err(VerifyError::MalformedSignature))?;
+    key.verify(msg, &sign)
+        .or(Err(VerifyError::VerificationFailed))
+}
+
+/// Signature read from a file, either DER (or raw) or PEM with the
+/// `PEM_SIGNATURE` label
+pub fn signature_der(input: &[u8]) -> Result<Vec<u8>, VerifyError>
+{
+    let start = input
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(0);
+    if !input[start..].starts_with(b"-----BEGIN ") {
+        return Ok(input.to_vec());
+    }
+
+    match pem::decode_vec(&input[start..]) {
+        Ok((label, der)) if label == PEM_SIGNATURE => Ok(der),
+        _ => Err(VerifyError::MalformedSignature),
+    }
+}
+
+/// RSA public key from SPKI DER with either rsaEncryption or id-RSASSA-PSS
+/// algorithm. The PSS parameters, if any, are not enforced: signatures are
+/// always made and checked with SHA256 and salt of the hash length.
+pub fn rsa_public_key(public_der: &[u8]) -> Result<rsa::RsaPublicKey, VerifyError>
+{
+    let spki =
+        SubjectPublicKeyInfoRef::try_from(public_der).or(Err(VerifyError::UnsupportedKey))?;
+
+    if spki.algorithm.oid != RSASSA_PSS_OID {
+        return rsa::RsaPublicKey::try_from(spki).or(Err(VerifyError::UnsupportedKey));
+    }
+
+    let Some(pkcs1) = spki.subject_public_key.as_bytes() else {
+        return Err(VerifyError::UnsupportedKey);
+    };
+    rsa::RsaPublicKey::from_pkcs1_der(pkcs1).or(Err(VerifyError::UnsupportedKey))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{rsa_public_key, signature_der, verify, VerifyError, PEM_SIGNATURE};
+    use p384::ecdsa::signature::Signer;
+    use spki::der::pem::{self, LineEnding};
+    use spki::EncodePublicKey;
+
+    const MSG: &[u8] = b"revocation list";
+
+    #[test]
+    fn verify_ecdsa()
+    {
+        let p384 = p384::ecdsa::SigningKey::from_slice(&[0x11; 48]).unwrap();
+        let public = p384.verifying_key().to_public_key_der().unwrap();
+        let sign: p384::ecdsa::Signature = p384.sign(MSG);
+        let sign = sign.to_der();
+        assert_eq!(verify(public.as_bytes(), MSG, sign.as_bytes()), Ok(()));
+        assert_eq!(
+            verify(public.as_bytes(), b"other", sign.as_bytes()),
+            Err(VerifyError::VerificationFailed)
+        );
+
+        let p256 = p256::ecdsa::SigningKey::from_slice(&[0x22; 32]).unwrap();
+        let public = p256.verifying_key().to_public_key_der().unwrap();
+        let sign: p256::ecdsa::Signature = p256.sign(MSG);
+        assert_eq!(verify(public.as_bytes(), MSG, sign.to_der().as_bytes()), Ok(()));
+        assert_eq!(
+            verify(public.as_bytes(), MSG, &[0x30, 0x00]),
+            Err(VerifyError::MalformedSignature)
+        );
+    }
+
+    #[test]
+    fn verify_ed25519()
+    {
+        let key = ed25519_dalek::SigningKey::from_bytes(&[0x33; 32]);
+        let public = key.verifying_key().to_public_key_der().unwrap();
+        let sign = key.sign(MSG).to_bytes();
+        assert_eq!(verify(public.as_bytes(), MSG, &sign), Ok(()));
+        assert_eq!(
+            verify(public.as_bytes(), b"other", &sign),
+            Err(VerifyError::VerificationFailed)
+        );
+    }
+
+    #[test]
+    fn verify_rsa_pss_oid()
+    {
+        // made with `openssl genpkey -algorithm RSA-PSS` and signed with
+        // `openssl dgst -sha256 -sigopt rsa_padding_mode:pss -sigopt rsa_pss_saltlen:digest`
+        let public = include_bytes!("../tests/rsa-pss.pub.der");
+        let sign = include_bytes!("../tests/rsa-pss.sig");
+        assert_eq!(verify(public, MSG, sign), Ok(()));
+        assert_eq!(verify(public, b"other", sign), Err(VerifyError::VerificationFailed));
+
+        // the same key with rsaEncryption algorithm
+        let key = rsa_public_key(public).unwrap();
+        let public = key.to_public_key_der().unwrap();
+        assert_eq!(verify(public.as_bytes(), MSG, sign), Ok(()));
+    }
+
+    #[test]
+    fn verify_signature_pem()
+    {
+        let sign = include_bytes!("../tests/rsa-pss.sig");
+        assert_eq!(signature_der(sign).unwrap(), sign);
+
+        let pem = pem::encode_string(PEM_SIGNATURE, LineEnding::LF, sign).unwrap();
+        assert_eq!(signature_der(pem.as_bytes()).unwrap(), sign);
+
+        let key = pem::encode_string("PUBLIC KEY", LineEnding::LF, sign).unwrap();
+        assert_eq!(signature_der(key.as_bytes()), Err(VerifyError::MalformedSignature));
+    }
+
+    #[test]
+    fn verify_unsupported_key()
+    {
+        assert_eq!(verify(b"not a key", MSG, b""), Err(VerifyError::UnsupportedKey));
+    }
+}
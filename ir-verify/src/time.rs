@@ -0,0 +1,77 @@
+//! RFC 3339 dates in UTC, days to date as in
+//! http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn civil_from_days(days: u64) -> (u64, u64, u64)
+{
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    (year, month, day)
+}
+
+fn format(secs: u64, fraction: &str) -> String
+{
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        fraction
+    )
+}
+
+/// Unix time in seconds, e.g. "2024-01-31T12:00:00Z"
+pub fn rfc3339(time: u64) -> String
+{
+    format(time, "")
+}
+
+/// System time with milliseconds, e.g. "2024-01-31T12:00:00.123Z"
+pub fn rfc3339_millis(time: SystemTime) -> String
+{
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format(
+        since_epoch.as_secs(),
+        &format!(".{:03}", since_epoch.subsec_millis()),
+    )
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{rfc3339, rfc3339_millis};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn time_rfc3339()
+    {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1700000000), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn time_rfc3339_millis()
+    {
+        assert_eq!(rfc3339_millis(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            rfc3339_millis(UNIX_EPOCH + Duration::from_millis(1709210096789)),
+            "2024-02-29T12:34:56.789Z"
+        );
+    }
+}